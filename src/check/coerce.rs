@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use voile_util::axiom::Axiom;
+use voile_util::tags::Plicit;
+use voile_util::uid::{next_uid, DBI, UID};
+
+use crate::syntax::core::{CaseSplit, Closure, Fields, Neutral, Val};
+
+/// The coercion from a subtype to its supertype,
+/// which is inserted into the core term by elaboration.
+#[derive(Debug, Clone)]
+pub(crate) enum Coercion {
+    /// The value is already of the supertype.
+    Id,
+    /// Functions, `\x. cod (f (dom x))` where `x` is the axiom `UID`.
+    /// The domain coercion is from the domain of the supertype (contravariance).
+    /// The implicit parameters are erased, so only the result is coerced.
+    Pi(Plicit, UID, Box<Self>, Box<Self>),
+    /// Pairs, component-wise.
+    Sigma(Box<Self>, Box<Self>),
+    /// The arguments of a constructor, one by one.
+    /// The implicit arguments are erased.
+    Tele(Plicit, Box<Self>, Box<Self>),
+    /// Records, with the fields coerced and the fields not in the supertype removed.
+    Rec(BTreeMap<String, Self>, Vec<String>),
+    /// Variants, with the arity and the payload coercion of each constructor.
+    /// The unknown constructors of an open row are kept as they are.
+    Variant(BTreeMap<String, (usize, Self)>, bool),
+}
+
+impl Coercion {
+    pub fn is_id(&self) -> bool {
+        matches!(self, Coercion::Id)
+    }
+
+    pub fn pi(plicit: Plicit, uid: UID, dom: Self, cod: Self) -> Self {
+        if dom.is_id() && cod.is_id() {
+            Coercion::Id
+        } else {
+            Coercion::Pi(plicit, uid, Box::new(dom), Box::new(cod))
+        }
+    }
+
+    pub fn sigma(fst: Self, snd: Self) -> Self {
+        if fst.is_id() && snd.is_id() {
+            Coercion::Id
+        } else {
+            Coercion::Sigma(Box::new(fst), Box::new(snd))
+        }
+    }
+
+    pub fn tele(plicit: Plicit, fst: Self, snd: Self) -> Self {
+        if fst.is_id() && snd.is_id() {
+            Coercion::Id
+        } else {
+            Coercion::Tele(plicit, Box::new(fst), Box::new(snd))
+        }
+    }
+
+    pub fn record(mut fields: BTreeMap<String, Self>, removed: Vec<String>) -> Self {
+        fields.retain(|_, coercion| !coercion.is_id());
+        if fields.is_empty() && removed.is_empty() {
+            Coercion::Id
+        } else {
+            Coercion::Rec(fields, removed)
+        }
+    }
+
+    pub fn variant(payloads: BTreeMap<String, (usize, Self)>, open: bool) -> Self {
+        if payloads.values().all(|(_, coercion)| coercion.is_id()) {
+            Coercion::Id
+        } else {
+            Coercion::Variant(payloads, open)
+        }
+    }
+
+    /// Insert the coercion into the core term `val`.
+    pub fn coerce(&self, val: Val) -> Val {
+        use Coercion::*;
+        match self {
+            Id => val,
+            Pi(Plicit::Im, _, _, cod) => cod.coerce(val),
+            Pi(Plicit::Ex, uid, dom, cod) => {
+                let param = dom.coerce(Val::postulate(*uid));
+                let body = cod.coerce(under_binder(val).apply(param));
+                Val::closure_lam(body.abstract_axiom(*uid))
+            }
+            Sigma(fst, snd) => match val {
+                Val::Pair(a, b) => Val::pair(fst.coerce(*a), snd.coerce(*b)),
+                pair => {
+                    let a = fst.coerce(pair.clone().first());
+                    Val::pair(a, snd.coerce(pair.second()))
+                }
+            },
+            Tele(..) => self.coerce_args(vec![val]).remove(0),
+            Rec(fields, removed) => {
+                let mut coerced = Fields::new();
+                let mut rest = val.clone();
+                for (label, coercion) in fields {
+                    let field = val.clone().project(label.clone());
+                    coerced.insert(label.clone(), coercion.coerce(field));
+                    rest = rest.restrict(label.clone());
+                }
+                let rest = (removed.iter()).fold(rest, |rest, label| rest.restrict(label.clone()));
+                if coerced.is_empty() {
+                    rest
+                } else {
+                    Val::Rec(coerced).rec_extend(rest)
+                }
+            }
+            Variant(payloads, open) => match val {
+                Val::Cons(label, args) => match payloads.get(&label) {
+                    Some((_, coercion)) => Val::cons(label, coercion.coerce_args(args)),
+                    None => Val::Cons(label, args),
+                },
+                // Splitting on the unknown constructors is not possible.
+                val if *open => val,
+                val => {
+                    let split = (payloads.iter())
+                        .map(|(label, (arity, coercion))| {
+                            let uid = unsafe { next_uid() };
+                            let args = Val::postulate(uid).cons_args(*arity);
+                            let cons = Val::cons(label.clone(), coercion.coerce_args(args));
+                            (label.clone(), Closure::plain(cons.abstract_axiom(uid)))
+                        })
+                        .collect::<CaseSplit>();
+                    Val::case_tree(split).apply(val)
+                }
+            },
+        }
+    }
+
+    /// Coerce the explicit arguments of a constructor,
+    /// whose payload type is a telescope or the type of the only argument.
+    fn coerce_args(&self, mut args: Vec<Val>) -> Vec<Val> {
+        match self {
+            Coercion::Tele(Plicit::Im, _, snd) => snd.coerce_args(args),
+            Coercion::Tele(Plicit::Ex, fst, snd) => {
+                let rest = args.split_off(1);
+                let mut coerced = vec![fst.coerce(args.pop().unwrap())];
+                coerced.append(&mut snd.coerce_args(rest));
+                coerced
+            }
+            coercion => args.into_iter().map(|arg| coercion.coerce(arg)).collect(),
+        }
+    }
+}
+
+/// The local variables in `val` are one binder further when it's put under a lambda.
+/// Only the generated axioms are referred by de Bruijn indices:
+/// the postulated ones are abstracted by their UIDs where they're bound
+/// (so they're at the right index wherever they're put), and the unimplemented
+/// and the implicit ones are not bound locally.
+fn under_binder(val: Val) -> Val {
+    use Axiom::*;
+    val.map_axiom(&mut |axiom| {
+        Neutral::Axi(match axiom {
+            Generated(uid, dbi) => Generated(uid, DBI(dbi.0 + 1)),
+            Postulated(..) | Unimplemented(..) | Implicit(..) => axiom,
+        })
+    })
+}
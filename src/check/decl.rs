@@ -1,15 +1,185 @@
-use std::mem::swap;
+use std::collections::BTreeSet;
+use std::mem::{swap, take};
+use std::rc::Rc;
 
-use voile_util::loc::ToLoc;
+use voile_util::loc::{Ident, ToLoc};
+use voile_util::meta::MetaSolution;
+use voile_util::tags::VarRec;
+use voile_util::uid::GI;
 
 use crate::syntax::abs::AbsDecl;
-use crate::syntax::core::{Neutral, TraverseNeutral, Val, ValInfo, TYPE_OMEGA};
+use crate::syntax::core::{Neutral, TraverseNeutral, Val, ValInfo, Variants, TYPE_OMEGA};
 
 use super::monad::{ValTCM, TCE, TCM, TCS};
+use super::positivity::check_positivity;
+use super::termination::check_termination;
 
 /// Checking a list of declarations.
 pub fn check_decls(tcs: TCS, decls: Vec<AbsDecl>) -> TCM {
-    decls.into_iter().try_fold(tcs, check_decl)
+    let (tcs, errors) = check_decls_recovering(tcs, decls);
+    TCE::from_errors(errors).map(|()| tcs)
+}
+
+/// Checking a list of declarations, carrying on after errors.
+///
+/// A declaration is poisoned if it's broken or it refers to poisoned ones.
+/// Poisoned declarations are postulated instead of checked, so only the
+/// declarations depending on broken ones are skipped (without new errors).
+pub fn check_decls_recovering(mut tcs: TCS, decls: Vec<AbsDecl>) -> (TCS, Vec<TCE>) {
+    let mut errors = Vec::new();
+    let mut poisoned = BTreeSet::new();
+    let mut warnings = take(&mut tcs.warnings);
+    let mut tcs = decls.into_iter().fold(tcs, |mut tcs, decl| {
+        let index = match &decl {
+            AbsDecl::Sign(_, index) | AbsDecl::Impl(_, index, _) | AbsDecl::Poisoned(_, index) => {
+                *index
+            }
+            AbsDecl::Decl(..) => GI(tcs.glob_len()),
+        };
+        let depends_on_poisoned = poisoned.contains(&index)
+            || (decl.refs().into_iter()).any(|(_, gi)| poisoned.contains(&gi));
+        if depends_on_poisoned || matches!(decl, AbsDecl::Poisoned(..)) {
+            poisoned.insert(index);
+            return postulate(tcs, index);
+        }
+        let names = Rc::make_mut(&mut tcs.names);
+        for (ident, gi) in decl.refs() {
+            (names.entry(gi)).or_insert_with(|| ident.text.clone());
+        }
+        let refs = match &decl {
+            AbsDecl::Sign(..) => BTreeSet::new(),
+            decl => (decl.refs().into_iter()).map(|(_, gi)| gi).collect(),
+        };
+        // The state is lost on error, so we keep a copy. It only shares
+        // the global parts, which are changed after the copy is dropped.
+        let copy = tcs.clone();
+        let defined = match check_decl(tcs, decl) {
+            Ok((tcs, checked)) => {
+                drop(copy);
+                define(tcs, index, checked, refs)
+            }
+            Err(err) => Err((err, copy)),
+        };
+        match defined {
+            Ok(mut tcs) => {
+                warnings.append(&mut tcs.warnings);
+                tcs
+            }
+            Err((err, tcs)) => {
+                errors.push(err);
+                poisoned.insert(index);
+                postulate(tcs, index)
+            }
+        }
+    });
+    tcs.warnings = warnings;
+    match solve_universes(tcs.clone()) {
+        Ok(tcs) => (tcs, errors),
+        Err(err) => {
+            errors.push(err);
+            (tcs, errors)
+        }
+    }
+}
+
+/// Solve the universe variables, which are inlined into the checked declarations.
+/// The errors are located at the last declaration, which completes the constraints.
+fn solve_universes(tcs: TCS) -> TCM {
+    let last = tcs.env.last().map(|val| val.loc).unwrap_or_default();
+    let mut tcs = tcs.solve_universes().map_err(|e| e.wrap(last))?;
+    let (mut env, mut gamma) = (take(&mut tcs.env), take(&mut tcs.gamma));
+    let globals = (Rc::make_mut(&mut env).iter_mut()).chain(Rc::make_mut(&mut gamma));
+    for global in globals {
+        let ValInfo { loc, ast } = take(global);
+        *global = (inline_solutions(&tcs, ast).map_err(|e| e.wrap(loc))?).into_info(loc);
+    }
+    tcs.env = env;
+    tcs.gamma = gamma;
+    Ok(tcs)
+}
+
+/// Put a placeholder for a poisoned declaration
+/// if it's not yet in the context.
+fn postulate(mut tcs: TCS, index: GI) -> TCS {
+    if index.0 == tcs.glob_len() {
+        Rc::make_mut(&mut tcs.env)
+            .push(Val::fresh_unimplemented(index).into_info(Default::default()));
+        Rc::make_mut(&mut tcs.gamma).push(Val::fresh_axiom().into_info(Default::default()));
+    }
+    tcs
+}
+
+/// A checked declaration, put into the context by [`define`](define).
+enum Checked {
+    /// A new global definition, with its value and type.
+    New(ValInfo, ValInfo),
+    /// The implementation of a signature, and the name it's defined with.
+    Impl(Ident, ValInfo),
+    Poisoned,
+}
+
+/// Put the checked declaration `index` into the context, with the global definitions
+/// it refers to. Implementations are checked for termination and positivity,
+/// the changes (and the metas solved checking it) are taken back if they fail.
+fn define(
+    mut tcs: TCS,
+    index: GI,
+    checked: Checked,
+    refs: BTreeSet<GI>,
+) -> Result<TCS, (TCE, TCS)> {
+    let old_env = tcs.env[index.0..].to_vec();
+    let old_gamma = tcs.gamma[index.0..].to_vec();
+    let old_refs = tcs.refs.get(&index).cloned();
+    if !refs.is_empty() {
+        (Rc::make_mut(&mut tcs.refs).entry(index).or_default()).extend(refs);
+    }
+    let defined = match checked {
+        Checked::New(val, ty) => {
+            Rc::make_mut(&mut tcs.env).push(val);
+            Rc::make_mut(&mut tcs.gamma).push(ty);
+            Ok(())
+        }
+        Checked::Poisoned => {
+            tcs = postulate(tcs, index);
+            Ok(())
+        }
+        Checked::Impl(name, val) => {
+            let env = Rc::make_mut(&mut tcs.env);
+            env[index.0] = val;
+
+            // Every references to me are now actually valid (they were axioms before),
+            // replace them with a global reference.
+            for i in index.0..env.len() {
+                unimplemented_to_glob(env, i);
+            }
+            let gamma = Rc::make_mut(&mut tcs.gamma);
+            for i in index.0 + 1..gamma.len() {
+                unimplemented_to_glob(gamma, i);
+            }
+            check_termination(&tcs, index, name.loc).and_then(|()| check_positivity(&tcs, index))
+        }
+    };
+    match defined {
+        Ok(()) => {
+            tcs.commit();
+            Ok(tcs)
+        }
+        Err(err) => {
+            let env = Rc::make_mut(&mut tcs.env);
+            env.truncate(index.0);
+            env.extend(old_env);
+            let gamma = Rc::make_mut(&mut tcs.gamma);
+            gamma.truncate(index.0);
+            gamma.extend(old_gamma);
+            let refs = Rc::make_mut(&mut tcs.refs);
+            match old_refs {
+                Some(old_refs) => refs.insert(index, old_refs),
+                None => refs.remove(&index),
+            };
+            tcs.rollback();
+            Err((err, tcs))
+        }
+    }
 }
 
 fn require_local_emptiness(tcs: &TCS) {
@@ -24,19 +194,79 @@ fn unimplemented_to_glob(v: &mut [ValInfo], i: usize) {
     swap(&mut placeholder, &mut v[i]);
 }
 
-pub fn inline_metas(mut tcs: TCS, val: ValInfo) -> ValTCM {
-    use Neutral::*;
+pub fn inline_metas(tcs: TCS, val: ValInfo) -> ValTCM {
     let info = val.loc;
-    let val = val.ast.try_map_neutral(&mut |neut| match neut {
-        Meta(mi) => tcs
-            .meta_context
-            .take_meta(mi)
-            .ok_or_else(|| TCE::MetaUnsolved(mi)),
-        e => Ok(Val::Neut(e)),
-    })?;
+    let val = inline_solutions(&tcs, val.ast)?;
     Ok((val.into_info(info), tcs))
 }
 
+/// Replace the metas with their solutions,
+/// which may mention other metas solved later.
+fn inline_solutions(tcs: &TCS, val: Val) -> TCM<Val> {
+    use Neutral::*;
+    val.try_map_neutral(&mut |neut| match neut {
+        Meta(mi) => match tcs.meta_context.solution(mi) {
+            MetaSolution::Solved(solution) => inline_solutions(tcs, *solution.clone()),
+            // Solved when all declarations are checked.
+            _ if tcs.universes.is_var(mi) => Ok(Val::meta(mi)),
+            _ => Err(TCE::MetaUnsolved(mi)),
+        },
+        Type(level) => {
+            let level = inline_solutions(tcs, Val::Neut(*level))?;
+            Val::univ(level.clone()).ok_or(TCE::NotLevel(level))
+        }
+        LMax(n, atoms) => (atoms.into_iter()).try_fold(Val::Lvl(n.into()), |max, (atom, k)| {
+            let atom = inline_solutions(tcs, Val::Neut(atom))?;
+            let level = atom
+                .clone()
+                .level_suc(k)
+                .and_then(|atom| max.level_max(atom));
+            level.ok_or(TCE::NotLevel(atom))
+        }),
+        // The extensions of open rows may be solved, with the rows merged.
+        Row(kind, variants, ext) => {
+            let row = Val::RowPoly(kind, inline_variants(tcs, variants)?);
+            let ext = inline_solutions(tcs, Val::Neut(*ext))?;
+            row.row_extend_safe(ext)
+                .map_err(|pair| extension_error(kind, pair))
+        }
+        Rec(fields, ext) => {
+            let record = Val::Rec(inline_variants(tcs, fields)?);
+            let ext = inline_solutions(tcs, Val::Neut(*ext))?;
+            record
+                .rec_extend_safe(ext)
+                .map_err(|pair| extension_error(VarRec::Record, pair))
+        }
+        e => Ok(Val::Neut(e)),
+    })
+}
+
+/// The row (or record) `row` cannot be extended by the solution `ext`,
+/// because they share a label or `ext` is not a row.
+fn extension_error(kind: VarRec, (row, ext): (Val, Val)) -> TCE {
+    let labels = |val: &Val| match val {
+        Val::RowPoly(_, labels)
+        | Val::Rec(labels)
+        | Val::Neut(Neutral::Row(_, labels, _))
+        | Val::Neut(Neutral::Rec(labels, _)) => labels.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    let ext_labels = labels(&ext);
+    match labels(&row)
+        .into_iter()
+        .find(|label| ext_labels.contains(label))
+    {
+        Some(label) => TCE::RowNotLacking(kind, ext, label),
+        None => TCE::CannotUnify(row, ext),
+    }
+}
+
+fn inline_variants(tcs: &TCS, variants: Variants) -> TCM<Variants> {
+    (variants.into_iter())
+        .map(|(label, ty)| Ok((label, inline_solutions(tcs, ty)?)))
+        .collect()
+}
+
 /**
 Checking one declaration.
 $$
@@ -83,60 +313,56 @@ $$
 }
 $$
 */
-fn check_decl(tcs: TCS, decl: AbsDecl) -> TCM {
+fn check_decl(tcs: TCS, decl: AbsDecl) -> TCM<(TCS, Checked)> {
     debug_assert_eq!(tcs.gamma.len(), tcs.env.len());
-    let tcs = match decl {
-        AbsDecl::Impl(impl_abs, sign_dbi) => {
+    let (tcs, checked) = match decl {
+        AbsDecl::Impl(impl_abs, sign_dbi, name) => {
             let sign = tcs.glob_type(sign_dbi);
             let sign_cloned = sign.ast.clone();
             let (val_fake, tcs) = tcs.check(&impl_abs, &sign_cloned)?;
+            let tcs = tcs
+                .require_no_postponed()
+                .and_then(TCS::require_consistent_universes)
+                .map_err(|e| e.wrap(impl_abs.loc()))?;
             // We generate axioms for lambda parameters during type-checking.
             // Now it's time to change them back to `var` references.
             let val = val_fake.map_ast(|ast| ast.generated_to_var());
-            let (val, mut tcs) = inline_metas(tcs, val)?;
-
-            tcs.env[sign_dbi.0] = val;
-
-            // Every references to me are now actually valid (they were axioms before),
-            // replace them with a global reference.
-            for i in sign_dbi.0..tcs.glob_len() {
-                unimplemented_to_glob(&mut tcs.env, i);
-            }
-            for i in sign_dbi.0 + 1..tcs.glob_len() {
-                unimplemented_to_glob(&mut tcs.gamma, i);
-            }
+            let (val, tcs) = inline_metas(tcs, val)?;
 
             // Err(TCE::DbiOverflow(tcs.env.len(), new_dbi))
-            tcs
+            (tcs, Checked::Impl(name, val))
         }
         AbsDecl::Sign(sign_abs, self_index) => {
             let loc = sign_abs.loc();
             let (sign_fake, tcs) = tcs.check(&sign_abs, &TYPE_OMEGA)?;
-            let (sign_fake, mut tcs) = inline_metas(tcs, sign_fake)?;
+            let tcs = (tcs.require_no_postponed())
+                .and_then(TCS::require_consistent_universes)
+                .map_err(|e| e.wrap(loc))?;
+            let (sign_fake, tcs) = inline_metas(tcs, sign_fake)?;
             let sign = sign_fake.map_ast(|ast| ast.generated_to_var());
             let val_info = Val::fresh_unimplemented(self_index).into_info(loc);
-            tcs.env.push(val_info);
-            tcs.gamma.push(sign);
 
             // Give warning on axiom?
-            tcs
+            (tcs, Checked::New(val_info, sign))
         }
+        AbsDecl::Poisoned(..) => (tcs, Checked::Poisoned),
         AbsDecl::Decl(impl_abs) => {
-            let (inferred, tcs) = tcs.infer(&impl_abs)?;
+            let (compiled, inferred, tcs) = tcs.elaborate(&impl_abs)?;
+            let tcs = tcs
+                .require_no_postponed()
+                .and_then(TCS::require_consistent_universes)
+                .map_err(|e| e.wrap(impl_abs.loc()))?;
             let (inferred, tcs) = inline_metas(tcs, inferred)?;
-            let (compiled, tcs) = tcs.evaluate(impl_abs);
-            let (compiled, mut tcs) = inline_metas(tcs, compiled)?;
+            let (compiled, tcs) = inline_metas(tcs, compiled)?;
             let compiled = compiled.map_ast(|ast| ast.generated_to_var());
             let inferred = inferred.map_ast(|ast| ast.generated_to_var());
-            tcs.env.push(compiled);
-            tcs.gamma.push(inferred);
 
-            tcs
+            (tcs, Checked::New(compiled, inferred))
         }
     };
 
     require_local_emptiness(&tcs);
-    Ok(tcs)
+    Ok((tcs, checked))
 }
 
 impl TCS {
@@ -1,11 +1,14 @@
-use voile_util::level::LiftEx;
+use voile_util::level::{Level, LiftEx};
 use voile_util::loc::{merge_info, Ident};
 use voile_util::meta::MetaSolution;
-use voile_util::uid::DBI;
+use voile_util::tags::VarRec;
+use voile_util::uid::{DBI, GI};
 
 use crate::check::monad::TCS;
 use crate::syntax::abs::{Abs, LabAbs};
-use crate::syntax::core::{CaseSplit, Closure, Neutral, TraverseNeutral, Val, ValInfo, Variants};
+use crate::syntax::core::{
+    CaseSplit, Closure, LevelPrim, Neutral, TraverseNeutral, Val, ValInfo, Variants,
+};
 
 /**
 Evaluation rules.
@@ -117,12 +120,17 @@ fn evaluate(tcs: TCS, abs: Abs) -> (ValInfo, TCS) {
     use Abs::*;
     match abs {
         Type(info, level) => (Val::Type(level).into_info(info), tcs),
+        Universe(info, mi) => {
+            let level = tcs.expand_level(Val::meta(mi));
+            (universe(level).into_info(info), tcs)
+        }
+        LevelPrim(info, prim) => (compile_level_prim(prim).into_info(info), tcs),
         Var(ident, _, i) => {
-            let resolved = tcs.local_val(i).ast.clone().attach_dbi(i);
+            let resolved = tcs.attach_local_dbi(tcs.local_val(i).ast.clone(), i);
             (resolved.into_info(ident.loc), tcs)
         }
         Rec(info, fields, ext) => {
-            let (variants, tcs) = evaluate_variants(tcs, fields);
+            let (variants, tcs) = evaluate_variants(tcs, fields, false);
             let record = Val::Rec(variants);
             match ext {
                 None => (record.into_info(info), tcs),
@@ -133,19 +141,32 @@ fn evaluate(tcs: TCS, abs: Abs) -> (ValInfo, TCS) {
             }
         }
         Ref(ident, dbi) => (tcs.glob_val(dbi).ast.clone().into_info(ident.loc), tcs),
-        Cons(info) => (compile_cons(info), tcs),
-        App(info, f, _, a) => {
+        // The arity is unknown here, the arguments are taken when it's applied.
+        Cons(info) => (compile_cons(info, 0), tcs),
+        // Implicit arguments are erased.
+        App(info, f, Some(_), _) => {
+            let (f, tcs) = evaluate(tcs, *f);
+            (f.ast.into_info(info), tcs)
+        }
+        App(info, f, None, a) => {
+            // `Type l` is the universe at level `l`, `Type1 l` at `lsuc l`, etc.
+            if let Type(_, Level::Num(levels)) = *f {
+                let (a, tcs) = evaluate(tcs, *a);
+                let level = tcs.expand_level(a.ast).level_suc(levels);
+                return (universe(level.expect(NOT_LEVEL)).into_info(info), tcs);
+            }
             // The function should always be compiled to DBI-based terms
             let (f, tcs) = evaluate(tcs, *f);
             let (a, tcs) = evaluate(tcs, *a);
-            let (f, tcs) = tcs.expand_global(f.ast);
-            let applied = f.apply(a.ast);
+            let (applied, tcs) = tcs.apply_global(f.ast, a.ast);
             (applied.into_info(info), tcs)
         }
-        Dt(info, kind, _, param_plicit, param_ty, ret_ty) => {
+        Dt(info, kind, _, name, param_plicit, param_ty, ret_ty) => {
             let (param_ty, tcs) = evaluate(tcs, *param_ty);
             let (ret_ty, tcs) = evaluate(tcs, *ret_ty);
-            let term = Val::closure_dependent_type(kind, param_plicit, param_ty.ast, ret_ty.ast);
+            let name = name.map(|name| name.text);
+            let term =
+                Val::closure_dependent_type(kind, param_plicit, name, param_ty.ast, ret_ty.ast);
             (term.into_info(info), tcs)
         }
         Pair(info, a, b) => {
@@ -168,10 +189,25 @@ fn evaluate(tcs: TCS, abs: Abs) -> (ValInfo, TCS) {
             let (rec, tcs) = tcs.expand_global(rec.ast);
             (rec.project(field.text).into_info(info), tcs)
         }
-        // This branch is not likely to be reached.
-        Lam(info, _, _, body) => {
-            let (body, tcs) = evaluate(tcs, *body);
-            (body.ast.into_info(info), tcs)
+        Restrict(info, rec, label) => {
+            let (rec, tcs) = evaluate(tcs, *rec);
+            let (rec, tcs) = tcs.expand_global(rec.ast);
+            (rec.restrict(label.text).into_info(info), tcs)
+        }
+        Rename(info, rec, from, to) => {
+            let (rec, tcs) = evaluate(tcs, *rec);
+            let (rec, tcs) = tcs.expand_global(rec.ast);
+            (rec.rename(from.text, to.text).into_info(info), tcs)
+        }
+        // Lambdas passed as arguments, like `f (\x. a)`.
+        Lam(info, param, uid, body) => {
+            let mut tcs = tcs;
+            let param = Val::postulate(uid).into_info(param.loc);
+            tcs.local_env.push(param);
+            let (body, mut tcs) = evaluate(tcs, *body);
+            tcs.local_env.pop();
+            let lam = Val::closure_lam(body.ast.abstract_axiom(uid));
+            (lam.into_info(info), tcs)
         }
         Lift(info, levels, expr) => {
             let (expr, tcs) = evaluate(tcs, *expr);
@@ -186,7 +222,7 @@ fn evaluate(tcs: TCS, abs: Abs) -> (ValInfo, TCS) {
             }
         }
         RowPoly(info, kind, variants, ext) => {
-            let (variants, tcs) = evaluate_variants(tcs, variants);
+            let (variants, tcs) = evaluate_variants(tcs, variants, kind == VarRec::Record);
             let row_poly = Val::RowPoly(kind, variants);
             match ext {
                 None => (row_poly.into_info(info), tcs),
@@ -201,26 +237,64 @@ fn evaluate(tcs: TCS, abs: Abs) -> (ValInfo, TCS) {
             let expr = Val::RowKind(Default::default(), kind, labels);
             (expr.into_info(info), tcs)
         }
-        CaseOr(label, _, _, body, or) => {
-            let (or, tcs) = tcs.evaluate(*or);
-            let (body, tcs) = tcs.evaluate(*body);
+        CaseOr(label, bindings, uid, body, or) => {
+            let (or, mut tcs) = tcs.evaluate(*or);
+            let payload = Val::postulate(uid);
+            tcs.local_env.push(payload.clone().into_info(label.loc));
+            let arity = bindings.len();
+            if arity > 1 {
+                for (binding, arg) in bindings.iter().zip(payload.cons_args(arity)) {
+                    tcs.push_local_def(arg.into_info(binding.loc));
+                }
+            }
+            let (body, mut tcs) = tcs.evaluate(*body);
+            if arity > 1 {
+                bindings.iter().for_each(|_| tcs.pop_local_def());
+            }
+            tcs.local_env.pop();
             let info = merge_info(&label, &or);
             let mut split = CaseSplit::default();
-            split.insert(label.text, Closure::plain(body.ast));
+            split.insert(label.text, Closure::plain(body.ast.abstract_axiom(uid)));
             let lam = Val::case_tree(split);
             (or.ast.split_extend(lam).into_info(info), tcs)
         }
         Whatever(info) => (Val::Lam(Closure::default()).into_info(info), tcs),
+        Ann(info, expr, _) => {
+            let (expr, tcs) = tcs.evaluate(*expr);
+            (expr.ast.into_info(info), tcs)
+        }
+        // Local definitions are unfolded.
+        Let(info, _, _, _, value, body) => {
+            let (value, mut tcs) = tcs.evaluate(*value);
+            tcs.push_local_def(value);
+            let (body, mut tcs) = tcs.evaluate(*body);
+            tcs.pop_local_def();
+            (body.ast.into_info(info), tcs)
+        }
+        Match(..) => unreachable!("Pattern-matching functions are compiled when checked."),
     }
 }
 
-fn evaluate_variants(mut tcs: TCS, variants: Vec<LabAbs>) -> (Variants, TCS) {
+/// The earlier fields are in scope of the later ones if they're `dependent`,
+/// like in record types.
+fn evaluate_variants(mut tcs: TCS, variants: Vec<LabAbs>, dependent: bool) -> (Variants, TCS) {
     let mut out_variants = Variants::new();
+    let count = variants.len();
+    if dependent {
+        tcs.enter_fields();
+    }
     for labelled in variants.into_iter() {
         let (expr, new_tcs) = tcs.evaluate(labelled.expr);
         tcs = new_tcs;
+        if dependent {
+            tcs.push_field(&labelled.label);
+        }
         out_variants.insert(labelled.label.text, expr.ast);
     }
+    if dependent {
+        (0..count).for_each(|_| tcs.pop_local_def());
+        tcs.leave_fields();
+    }
     (out_variants, tcs)
 }
 
@@ -237,6 +311,8 @@ fn expand_global(tcs: TCS, expr: Val) -> (Val, TCS) {
             Fst(p) => java(p).first(),
             Snd(p) => java(p).second(),
             Proj(r, f) => java(r).project(f),
+            Restrict(r, l) => java(r).restrict(l),
+            Rename(r, a, b) => java(r).rename(a, b),
             Meta(mi) => match &tcs.meta_context.solution(mi) {
                 MetaSolution::Solved(val) => *val.clone(),
                 // Type-checking error instead of panicking?
@@ -244,23 +320,13 @@ fn expand_global(tcs: TCS, expr: Val) -> (Val, TCS) {
                 MetaSolution::Inlined => unreachable!(),
             },
             SplitOn(split, obj) => Val::case_tree(split).apply(java(obj)),
+            Type(level) => universe(expand_level(tcs, Val::Neut(*level))),
+            level @ LMax(..) => expand_level(tcs, Val::Neut(level)),
             OrSplit(split, or) => Val::case_tree(split).split_extend(java(or)),
             // Change variants?
-            Row(kind, variants, ext) => Val::RowPoly(kind, variants)
-                .row_extend_safe(java(ext))
-                .unwrap_or_else(|(a, b)| match (a, b) {
-                    (Val::RowPoly(_, v), Val::Neut(ext)) => Val::neutral_row_type(kind, v, ext),
-                    _ => unreachable!(),
-                }),
+            Row(kind, variants, ext) => Val::RowPoly(kind, variants).row_extend(java(ext)),
             // Change fields?
-            Rec(fields, ext) => {
-                (Val::Rec(fields).rec_extend_safe(java(ext))).unwrap_or_else(|(a, b)| {
-                    match (a, b) {
-                        (Val::Rec(v), Val::Neut(ext)) => Val::neutral_record(v, ext),
-                        _ => unreachable!(),
-                    }
-                })
-            }
+            Rec(fields, ext) => Val::Rec(fields).rec_extend(java(ext)),
             neut => Val::Neut(neut),
         }
     }
@@ -268,11 +334,94 @@ fn expand_global(tcs: TCS, expr: Val) -> (Val, TCS) {
     (val, tcs)
 }
 
-/// Evaluate a single constructor as a lambda.
-pub fn compile_cons(info: Ident) -> ValInfo {
+/// Replace solved metas in a level with their solutions.
+/// Unlike [`expand_global`], unsolved metas are kept.
+/// Values that are not levels are returned unchanged.
+fn expand_level(tcs: &TCS, level: Val) -> Val {
+    let (n, atoms) = match level.clone().level_parts() {
+        Some((Level::Num(n), atoms)) => (n, atoms),
+        _ => return level,
+    };
+    let expanded = (atoms.into_iter()).try_fold(Val::Lvl(Level::Num(n)), |max, (atom, k)| {
+        let atom = match atom {
+            Neutral::Meta(mi) => match tcs.meta_context.solution(mi) {
+                MetaSolution::Solved(sol) => expand_level(tcs, *sol.clone()),
+                _ => Val::meta(mi),
+            },
+            atom => Val::Neut(atom),
+        };
+        max.level_max(atom.level_suc(k)?)
+    });
+    expanded.unwrap_or(level)
+}
+
+const NOT_LEVEL: &str = "Cannot evaluate a universe at a value that's not a level.";
+
+/// Levels of well-typed universes are levels.
+fn universe(level: Val) -> Val {
+    Val::univ(level).expect(NOT_LEVEL)
+}
+
+/// Evaluate a level primitive, functions are compiled as lambdas.
+fn compile_level_prim(prim: LevelPrim) -> Val {
+    match prim {
+        LevelPrim::Level => Val::LevelTy,
+        LevelPrim::Zero => Val::Lvl(Default::default()),
+        LevelPrim::Suc => {
+            let suc = Val::level_from_parts(0, vec![(Neutral::Var(DBI(0)), 1)]);
+            Val::closure_lam(suc)
+        }
+        LevelPrim::Max => {
+            let atoms = vec![(Neutral::Var(DBI(1)), 0), (Neutral::Var(DBI(0)), 0)];
+            Val::closure_lam(Val::closure_lam(Val::level_from_parts(0, atoms)))
+        }
+    }
+}
+
+/// The global reference `val` is headed by, like `[|i|]` or `[|i|] a b`.
+pub fn global_head(val: &Val) -> Option<GI> {
+    match val {
+        Val::Neut(Neutral::Ref(index)) => Some(*index),
+        Val::Neut(Neutral::App(f, _)) => match &**f {
+            Neutral::Ref(index) => Some(*index),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Unfold the global reference `val` is headed by (see [`global_head`]) once.
+/// Recursive types refer to themselves by global references,
+/// so this is how we look into them (equi-recursive types).
+///
+/// Definitions unfolding to another global reference
+/// are not unfolded, so unfolding always makes progress.
+fn unfold_global(tcs: &TCS, val: &Val) -> Option<Val> {
+    let unfolded = match val {
+        Val::Neut(Neutral::Ref(index)) => tcs.glob_val(*index).ast.clone(),
+        Val::Neut(Neutral::App(f, args)) => match &**f {
+            Neutral::Ref(index) => {
+                let f = tcs.glob_val(*index).ast.clone();
+                args.iter().cloned().fold(f, Val::apply)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match global_head(&unfolded) {
+        None => Some(unfolded),
+        Some(_) => None,
+    }
+}
+
+/// Evaluate a constructor as a curried function of its `arity` arguments.
+/// The arguments beyond `arity` are taken when it's applied.
+pub fn compile_cons(info: Ident, arity: usize) -> ValInfo {
     let mut text = info.text;
     text.remove(0);
-    Val::closure_lam(Val::cons(text, Val::var(DBI(0)))).into_info(info.loc)
+    let args = (0..arity).rev().map(|i| Val::var(DBI(i))).collect();
+    let cons = (0..arity).fold(Val::cons(text, args), |body, _| Val::closure_lam(body));
+    cons.into_info(info.loc)
 }
 
 /// So you can do some functional programming based on method call chains.
@@ -288,4 +437,25 @@ impl TCS {
     pub fn expand_global(self, expr: Val) -> (Val, TCS) {
         expand_global(self, expr)
     }
+
+    /// Apply `f` to `a`, with the global references in `f` expanded.
+    pub(crate) fn apply_global(self, f: Val, a: Val) -> (Val, TCS) {
+        // Lambdas can be applied directly, expanding the global references
+        // in their bodies moves bound variables under other binders.
+        let (f, tcs) = match f {
+            lam @ Val::Lam(..) => (lam, self),
+            f => self.expand_global(f),
+        };
+        (f.apply(a), tcs)
+    }
+
+    #[inline]
+    pub fn unfold_global(&self, val: &Val) -> Option<Val> {
+        unfold_global(self, val)
+    }
+
+    #[inline]
+    pub fn expand_level(&self, level: Val) -> Val {
+        expand_level(self, level)
+    }
 }
@@ -1,13 +1,22 @@
+use std::collections::BTreeMap;
+
 use voile_util::level::{Level, LevelType, LiftEx};
 use voile_util::loc::*;
 use voile_util::meta::MetaSolution;
 use voile_util::tags::{PiSig::*, Plicit, VarRec, VarRec::*};
+use voile_util::uid::{next_uid, UID};
 
 use crate::syntax::abs::{Abs, LabAbs};
-use crate::syntax::core::{CaseSplit, Closure, Fields, Neutral, Val, Variants, TYPE_OMEGA};
+use crate::syntax::core::{
+    level_leq, CaseSplit, Closure, Fields, LevelPrim, Neutral, TVal, Val, ValInfo, Variants,
+    TYPE_OMEGA,
+};
 
-use super::eval::compile_cons;
-use super::monad::{ValTCM, TCE, TCM, TCS};
+use super::coerce::Coercion;
+use super::eval::{compile_cons, global_head};
+use super::matching::check_clauses;
+use super::monad::{ValTCM, TCE, TCM, TCS, TCW};
+use super::unify::unfold_either;
 
 /**
 Check an abstract term against an expected type and produce a well-typed term.
@@ -86,7 +95,7 @@ $$
 }
 \\\\ \space \\\\
 \cfrac{
-  \cheval{\Gamma,\xx : \cA\_1}{b}{\cB} \quad
+  \cheval{\Gamma,\xx : \cA\_1}{b}{\cB[\xx := n\ \xx]} \quad
   \Gtyck{A\_1}{\ty}{\cA\_1}
 }{
   \cfrac{
@@ -126,6 +135,8 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
             Ok((expr.into_info(*info), tcs))
         }
         (Meta(ident, mi), ty) => {
+            tcs.record_meta_scope(*mi);
+            tcs.constrain_meta(*mi, ty);
             let axiom = Val::fresh_axiom();
             let mocked = mock_for(ty, || axiom.clone());
             if mocked != axiom {
@@ -133,20 +144,16 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
             }
             Ok((Val::meta(*mi).into_info(ident.loc), tcs))
         }
-        (Pair(info, fst, snd), Val::Dt(Sigma, Plicit::Ex, param_ty, closure)) => {
-            let (fst_term, mut tcs) = tcs.check(&**fst, &**param_ty).map_err(|e| e.wrap(*info))?;
-            let fst_term_ast = fst_term.ast.clone();
-            let snd_ty = closure.instantiate_borrow(&fst_term_ast);
-            // This `fst_term.loc()` is probably wrong, but I'm not sure how to fix
-            let param_type = param_ty.clone().into_info(fst_term.loc());
-            tcs.local_gamma.push(param_type);
-            tcs.local_env.push(fst_term);
-            let (snd_term, mut tcs) = tcs.check(&**snd, &snd_ty).map_err(|e| e.wrap(*info))?;
-            tcs.pop_local();
-            let pair = Val::pair(fst_term_ast, snd_term.ast).into_info(*info);
+        (Pair(info, fst, snd), Val::Dt(Sigma, Plicit::Ex, _, param_ty, closure)) => {
+            let (fst_term, tcs) = tcs.check(&**fst, &**param_ty).map_err(|e| e.wrap(*info))?;
+            // The second component is not under a binder, the first one is only
+            // substituted into its type.
+            let snd_ty = closure.instantiate_borrow(&fst_term.ast);
+            let (snd_term, tcs) = tcs.check(&**snd, &snd_ty).map_err(|e| e.wrap(*info))?;
+            let pair = Val::pair(fst_term.ast, snd_term.ast).into_info(*info);
             Ok((pair, tcs))
         }
-        (Lam(full_loc, param_loc, uid, body), Val::Dt(Pi, Plicit::Ex, param_ty, ret_ty)) => {
+        (Lam(full_loc, param_loc, uid, body), Val::Dt(Pi, Plicit::Ex, _, param_ty, ret_ty)) => {
             let param_type = param_ty.clone().into_info(param_loc.loc);
             tcs.local_gamma.push(param_type);
             let mocked = mock_for(&**param_ty, || Val::postulate(*uid));
@@ -160,20 +167,34 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
             let lam = Val::closure_lam(lam_term.ast);
             Ok((lam.into_info(*full_loc), tcs))
         }
-        (Lam(..), Val::Dt(Pi, Plicit::Im, param_ty, ret_ty)) => {
-            let param_type = param_ty.clone().into_info(Default::default());
-            tcs.local_gamma.push(param_type);
+        (Let(info, name, _, sig, value, body), ty) => {
+            let tcs = check_local_def(tcs, name, sig, value).map_err(|e| e.wrap(*info))?;
+            let (body, mut tcs) = tcs.check(&**body, ty).map_err(|e| e.wrap(*info))?;
+            tcs.local_gamma.pop();
+            tcs.pop_local_def();
+            Ok((body, tcs))
+        }
+        // Implicit parameters are erased, so the checked term is not wrapped,
+        // and the parameter is not in the local context (it has no name in `expr`).
+        (_, Val::Dt(Pi, Plicit::Im, _, param_ty, ret_ty)) => {
             let mocked = mock_for(&**param_ty, Val::fresh_implicit);
-            let mocked_term = mocked.clone().into_info(Default::default());
-            tcs.local_env.push(mocked_term);
-            let ret_ty_body = ret_ty.instantiate_cloned(mocked);
-
-            let (lam, mut tcs) = tcs.check(&expr.clone(), &ret_ty_body)?;
-            tcs.pop_local();
-            Ok((lam, tcs))
+            tcs.check(expr, &ret_ty.instantiate_cloned(mocked))
+        }
+        (Cons(info), Val::Dt(Pi, ..)) => {
+            let arity = explicit_params(expected_type);
+            Ok((compile_cons(info.clone(), arity), tcs))
         }
-        (Cons(info), Val::Dt(Pi, ..)) => Ok((compile_cons(info.clone()), tcs)),
-        (Dt(info, kind, uid, param_plicit, param, ret), Val::Type(..)) => {
+        (App(info, ..), Val::RowPoly(Variant, variants)) => match cons_spine(expr) {
+            Some((cons, args)) if variants.contains_key(&cons.text[1..]) => {
+                let label = cons.text[1..].to_owned();
+                let payload_ty = variants[&label].clone();
+                let (args, tcs) = check_cons_args(tcs, *info, &label, payload_ty, args)?;
+                Ok((Val::cons(label, args).into_info(*info), tcs))
+            }
+            _ => check_fallback(tcs, expr, expected_type),
+        },
+        (Dt(info, kind, uid, name, param_plicit, param, ret), Val::Type(..))
+        | (Dt(info, kind, uid, name, param_plicit, param, ret), Val::Neut(Neutral::Type(..))) => {
             let (param, mut tcs) = tcs
                 .check(&**param, expected_type)
                 .map_err(|e| e.wrap(*info))?;
@@ -184,18 +205,25 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
                 .check(&**ret, expected_type)
                 .map_err(|e| e.wrap(*info))?;
             tcs.pop_local();
-            let dt = Val::closure_dependent_type(*kind, *param_plicit, param.ast, ret.ast)
+            let name = name.as_ref().map(|name| name.text.clone());
+            // The parameter may be substituted under binders in `ret`, like the parameters
+            // of a data type, so the references are abstracted by the axiom.
+            let ret = ret.ast.abstract_axiom(*uid);
+            let dt = Val::closure_dependent_type(*kind, *param_plicit, name, param.ast, ret)
                 .into_info(*info);
             Ok((dt, tcs))
         }
         (RowPoly(info, Record, variants, ext), Val::RowKind(l, Record, labels)) => {
-            check_row_polymorphic_type(tcs, *info, *l, Record, variants, ext, labels)
+            let universe = Val::Type(*l);
+            check_row_polymorphic_type(tcs, *info, &universe, Record, variants, ext, labels)
         }
         (RowPoly(info, Variant, variants, ext), Val::RowKind(l, Variant, labels)) => {
-            check_row_polymorphic_type(tcs, *info, *l, Variant, variants, ext, labels)
+            let universe = Val::Type(*l);
+            check_row_polymorphic_type(tcs, *info, &universe, Variant, variants, ext, labels)
         }
-        (RowPoly(info, kind, variants, ext), Val::Type(l)) => {
-            check_row_polymorphic_type(tcs, *info, *l, *kind, variants, ext, &[])
+        (RowPoly(info, kind, variants, ext), Val::Type(..))
+        | (RowPoly(info, kind, variants, ext), Val::Neut(Neutral::Type(..))) => {
+            check_row_polymorphic_type(tcs, *info, expected_type, *kind, variants, ext, &[])
         }
         (Rec(info, fields, more), Val::RowPoly(Record, field_types)) => {
             // Warn about unneeded fields?
@@ -223,6 +251,11 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
                     let record = Val::Rec(nice_fields).rec_extend(more.ast);
                     Ok((record.into_info(*info), tcs))
                 }
+                None if matches!(**more_types, Neutral::Meta(..)) => {
+                    let (nice_fields, tcs) =
+                        check_extra_fields(tcs, nice_fields, fields, field_types, more_types)?;
+                    check_fields_no_more(*info, nice_fields, rest_field_types, tcs)
+                }
                 None => check_fields_no_more(*info, nice_fields, rest_field_types, tcs),
             }
         }
@@ -233,43 +266,337 @@ fn check(mut tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
                 .map_err(|e| e.wrap(*info))?;
             Ok((expr.map_ast(|ast| ast.lift(*levels)), tcs))
         }
-        (Whatever(info), Val::Dt(Pi, _, param_ty, ..)) => match &**param_ty {
-            Val::RowPoly(Variant, variants) if variants.is_empty() => {
-                Ok((Val::Lam(Closure::default()).into_info(*info), tcs))
+        (Whatever(info), Val::Dt(Pi, _, _, param_ty, ..)) => {
+            match tcs.unfold_global(param_ty).as_ref().unwrap_or(param_ty) {
+                Val::RowPoly(Variant, variants) if variants.is_empty() => {
+                    Ok((Val::Lam(Closure::default()).into_info(*info), tcs))
+                }
+                Val::RowPoly(Variant, variants) => {
+                    let missing = (variants.iter()).map(|(label, ty)| cons_wildcards(label, ty));
+                    Err(TCE::NonExhaustive(*info, missing.collect()))
+                }
+                // The rest of an inferred variant type, which is closed here.
+                ty @ Val::Neut(Neutral::Meta(..)) => {
+                    let empty = Val::variant_type(Default::default());
+                    let tcs = tcs.unify(ty, &empty).map_err(|e| e.wrap(*info))?;
+                    Ok((Val::Lam(Closure::default()).into_info(*info), tcs))
+                }
+                ty => Err(TCE::NotEmpty(*info, ty.clone())),
             }
-            ty => Err(TCE::NotEmpty(*info, ty.clone())),
+        }
+        (CaseOr(..), Val::Dt(Pi, Plicit::Ex, _, param_ty, ret_ty)) => {
+            check_case_chain(tcs, expr, param_ty, ret_ty)
+        }
+        (Match(info, clauses), ty) => check_clauses(tcs, *info, clauses, ty),
+        (expr, anything) => match tcs.unfold_global(anything) {
+            Some(unfolded) => tcs.check(expr, &unfolded),
+            None => check_fallback(tcs, expr, anything),
         },
-        // How about when `Dt` is `Plicit::Im`?
-        (CaseOr(label, binding, uid, body, or), Val::Dt(Pi, Plicit::Ex, param_ty, ret_ty)) => {
-            let lam_info = merge_info(binding, &**body);
-            let lam = Lam(lam_info, binding.clone(), *uid, body.clone());
-            let (variants, ext) = match &**param_ty {
-                Val::Neut(Neutral::Row(Variant, variants, ext)) => (variants, Some(&**ext)),
-                Val::RowPoly(Variant, variants) => (variants, None),
-                ty => {
-                    let info = merge_info(label, &**or);
-                    return Err(TCE::NotRowType(Variant, info, ty.clone()));
-                }
-            };
-            let mut variants = variants.clone();
-            let param_ty = variants
-                .remove(&label.text)
-                .ok_or_else(|| TCE::MissingVariant(Variant, label.text.clone()))?;
-            let input = match ext {
-                None => Val::variant_type(variants),
-                Some(ext) => Val::neutral_variant_type(variants, ext.clone()),
-            };
-            let stripped_function = Val::pi(Plicit::Ex, input, ret_ty.clone());
-            let dt = Val::pi(Plicit::Ex, param_ty, ret_ty.clone());
-            let (body, tcs) = tcs.check(&lam, &dt)?;
-            let mut split = CaseSplit::default();
+    }
+}
+
+/// Check the branches of a case chain one by one, the first branch of each label
+/// goes to the case split, the rest of the chain is checked against the variants not split on.
+/// The return type may depend on the scrutinee, so each branch is checked against it
+/// instantiated with the constructor.
+/// The later branches of a label are unreachable, so they're reported as warnings.
+fn check_case_chain(mut tcs: TCS, chain: &Abs, param_ty: &Val, ret_ty: &Closure) -> ValTCM {
+    let param_ty = tcs
+        .unfold_global(param_ty)
+        .unwrap_or_else(|| param_ty.clone());
+    let (variants, ext) = match param_ty {
+        Val::Neut(Neutral::Row(Variant, variants, ext)) => (variants, Some(*ext)),
+        Val::RowPoly(Variant, variants) => (variants, None),
+        ty @ Val::Neut(Neutral::Meta(..)) => {
+            let (variants, ext, new_tcs) = infer_case_chain_variants(tcs, chain);
+            let open_ty = Val::neutral_variant_type(variants.clone(), ext.clone());
+            tcs = (new_tcs.unify(&ty, &open_ty)).map_err(|e| e.wrap(chain.loc()))?;
+            (variants, Some(ext))
+        }
+        ty => return Err(TCE::NotRowType(Variant, chain.loc(), ty)),
+    };
+    let mut split = CaseSplit::default();
+    let mut or = chain;
+    while let Abs::CaseOr(label, bindings, uid, body, rest) = or {
+        or = &**rest;
+        let param_ty = (variants.get(&label.text).cloned())
+            .ok_or_else(|| TCE::MissingVariant(Variant, label.text.clone()))?;
+        // Like checking a lambda, but the motive knows the constructor.
+        let info = merge_info(label, &**body);
+        let args = bind_cons_args(&mut tcs, label, bindings, *uid, param_ty)?;
+        let motive = ret_ty.instantiate_cloned(Val::cons(label.text.clone(), args));
+        let (body, new_tcs) = tcs.check(body, &motive).map_err(|e| e.wrap(info))?;
+        tcs = new_tcs;
+        unbind_cons_args(&mut tcs, bindings.len());
+        if split.contains_key(&label.text) {
+            tcs.warnings.push(TCW::UnreachableBranch(label.clone()));
+        } else {
             split.insert(label.text.clone(), Closure::plain(body.ast));
-            let ext = Val::case_tree(split);
-            let (or, tcs) = tcs.check(&**or, &stripped_function)?;
-            Ok((or.ast.split_extend(ext).into_info(or.loc), tcs))
         }
-        (expr, anything) => check_fallback(tcs, expr, anything),
     }
+    let rest = (variants.iter())
+        .filter(|(label, _)| !split.contains_key(*label))
+        .map(|(label, ty)| (label.clone(), ty.clone()))
+        .collect();
+    let input = match ext {
+        None => Val::variant_type(rest),
+        Some(ref ext) => Val::variant_type(rest).row_extend(Val::Neut(ext.clone())),
+    };
+    let stripped_function = Val::pi(Plicit::Ex, input, ret_ty.clone());
+    let (or, tcs) = match or {
+        // Checked against the stripped function by themselves.
+        Abs::Whatever(..)
+        | Abs::Lam(..)
+        | Abs::Match(..)
+        | Abs::Meta(..)
+        | Abs::Let(..)
+        | Abs::Lift(..)
+        | Abs::Cons(..) => tcs.check(or, &stripped_function)?,
+        fallback => {
+            let (value, inferred, tcs) = tcs.elaborate(fallback)?;
+            let (inferred_type, tcs) = insert_implicits(tcs, inferred.ast);
+            if ext.is_none() {
+                if let Some(missing) = uncovered(&tcs, &split, &inferred_type, &variants) {
+                    return Err(TCE::NonExhaustive(fallback.loc(), missing));
+                }
+            }
+            let inferred_type = inferred_type.into_info(inferred.loc);
+            coerce_elaborated(tcs, value, inferred_type, &stripped_function)?
+        }
+    };
+    let ext = Val::case_tree(split);
+    Ok((or.ast.split_extend(ext).into_info(or.loc), tcs))
+}
+
+/// The variant type of an unknown scrutinee, with a variant of a fresh type
+/// for each branch, extended by a fresh row meta for the rest of the chain.
+fn infer_case_chain_variants(mut tcs: TCS, chain: &Abs) -> (Variants, Neutral, TCS) {
+    let mut variants = Variants::new();
+    let mut or = chain;
+    while let Abs::CaseOr(label, bindings, _, _, rest) = or {
+        or = &**rest;
+        if !variants.contains_key(&label.text) {
+            let last = tcs.fresh_meta();
+            let payload_ty = (bindings[1..].iter()).fold(last, |rest, _| {
+                Val::sig(tcs.fresh_meta(), Closure::plain(rest))
+            });
+            variants.insert(label.text.clone(), payload_ty);
+        }
+    }
+    let labels: Vec<_> = variants.keys().cloned().collect();
+    let ext = tcs.fresh_row_meta(Variant, &labels);
+    (variants, ext, tcs)
+}
+
+/// The variants of a closed case chain handled neither by the branches in `split`
+/// nor by the fallback of type `fallback_ty`. `None` if they're all handled,
+/// or it's unknown what the fallback accepts.
+fn uncovered(
+    tcs: &TCS,
+    split: &CaseSplit,
+    fallback_ty: &Val,
+    variants: &Variants,
+) -> Option<Vec<String>> {
+    let domain = match fallback_ty {
+        Val::Dt(Pi, Plicit::Ex, _, domain, _) => &**domain,
+        _ => return None,
+    };
+    let accepted = match tcs.unfold_global(domain).as_ref().unwrap_or(domain) {
+        Val::RowPoly(Variant, accepted) => accepted.clone(),
+        _ => return None,
+    };
+    let missing = (variants.iter())
+        .filter(|(label, _)| !split.contains_key(*label) && !accepted.contains_key(*label))
+        .map(|(label, ty)| cons_wildcards(label, ty))
+        .collect::<Vec<_>>();
+    Some(missing).filter(|missing| !missing.is_empty())
+}
+
+/// The pattern of a constructor of this payload type, with wildcard arguments.
+pub(crate) fn cons_wildcards(label: &str, payload_ty: &Val) -> String {
+    format!("@{}{}", label, " _".repeat(payload_ty.cons_arity()))
+}
+
+/// The payload type of a constructor without the implicit arguments,
+/// which are mocked like implicit parameters since they're erased.
+pub(crate) fn explicit_payload(payload_ty: TVal) -> TVal {
+    match payload_ty {
+        Val::Dt(Tele, Plicit::Im, _, param_ty, rest) => {
+            let mocked = mock_for(&param_ty, Val::fresh_implicit);
+            explicit_payload(rest.instantiate(mocked))
+        }
+        Val::Dt(Tele, Plicit::Ex, name, param_ty, rest) => {
+            let uid = unsafe { next_uid() };
+            let rest = explicit_payload(rest.instantiate(Val::postulate(uid)));
+            let rest = Closure::plain(rest.abstract_axiom(uid));
+            Val::Dt(Tele, Plicit::Ex, name, param_ty, rest)
+        }
+        ty => ty,
+    }
+}
+
+/// The types of the explicit arguments of a constructor,
+/// the payload type is [`explicit_payload`](self::explicit_payload).
+pub(crate) fn cons_arg_types(mut payload_ty: TVal, args: &[Val]) -> Vec<TVal> {
+    let mut types = Vec::with_capacity(args.len());
+    for arg in &args[..args.len() - 1] {
+        match payload_ty {
+            Val::Dt(Tele, _, _, param_ty, rest) => {
+                types.push(*param_ty);
+                payload_ty = rest.instantiate(arg.clone());
+            }
+            _ => unreachable!(),
+        }
+    }
+    types.push(payload_ty);
+    types
+}
+
+/// Bring the arguments of a constructor into scope of a case branch, like parameters,
+/// see [`CaseOr`](crate::syntax::abs::Abs::CaseOr).
+/// Returns the arguments.
+fn bind_cons_args(
+    tcs: &mut TCS,
+    label: &Ident,
+    bindings: &[Ident],
+    uid: UID,
+    payload_ty: TVal,
+) -> TCM<Vec<Val>> {
+    let payload_ty = explicit_payload(payload_ty);
+    let arity = payload_ty.cons_arity();
+    if bindings.len() != arity {
+        let actual = bindings.len();
+        return Err(TCE::ConsArity(label.loc, label.text.clone(), arity, actual));
+    }
+    let payload = mock_for(&payload_ty, || Val::postulate(uid));
+    let loc = if arity == 1 {
+        bindings[0].loc
+    } else {
+        label.loc
+    };
+    tcs.local_gamma.push(payload_ty.clone().into_info(loc));
+    tcs.local_env.push(payload.clone().into_info(loc));
+    if arity == 1 {
+        return Ok(vec![payload]);
+    }
+    let args = payload.cons_args(arity);
+    let types = cons_arg_types(payload_ty, &args);
+    for ((binding, arg), ty) in bindings.iter().zip(&args).zip(types) {
+        tcs.local_gamma.push(ty.into_info(binding.loc));
+        tcs.push_local_def(arg.clone().into_info(binding.loc));
+    }
+    Ok(args)
+}
+
+/// Pop what's pushed by [`bind_cons_args`](self::bind_cons_args).
+fn unbind_cons_args(tcs: &mut TCS, arity: usize) {
+    if arity > 1 {
+        for _ in 0..arity {
+            tcs.local_gamma.pop();
+            tcs.pop_local_def();
+        }
+    }
+    tcs.pop_local();
+}
+
+/// The constructor applied in `expr`, with the arguments
+/// and the names of the named implicit arguments.
+fn cons_spine(expr: &Abs) -> Option<(&Ident, Vec<(Option<&Ident>, &Abs)>)> {
+    let mut args = Vec::new();
+    let mut f = expr;
+    while let Abs::App(_, g, name, a) = f {
+        args.push((name.as_ref(), &**a));
+        f = &**g;
+    }
+    args.reverse();
+    match f {
+        Abs::Cons(cons) => Some((cons, args)),
+        _ => None,
+    }
+}
+
+/// Check the arguments of a constructor against the components of its payload type,
+/// the implicit arguments not given by name are inferred.
+/// Returns the explicit arguments, since the implicit ones are erased.
+fn check_cons_args(
+    mut tcs: TCS,
+    info: Loc,
+    label: &str,
+    payload_ty: TVal,
+    args: Vec<(Option<&Ident>, &Abs)>,
+) -> TCM<(Vec<Val>, TCS)> {
+    let arity = payload_ty.cons_arity();
+    let actual = args.iter().filter(|(name, _)| name.is_none()).count();
+    if actual != arity {
+        return Err(TCE::ConsArity(info, label.to_owned(), arity, actual));
+    }
+    let mut args = args.into_iter().peekable();
+    let mut checked = Vec::with_capacity(arity);
+    let mut ty = payload_ty.clone();
+    loop {
+        let (plicit, name, param_ty, rest) = match ty {
+            Val::Dt(Tele, plicit, name, param_ty, rest) => (plicit, name, *param_ty, Some(rest)),
+            last => (Plicit::Ex, None, last, None),
+        };
+        let arg = match (plicit, args.peek()) {
+            (Plicit::Im, Some((Some(arg_name), a))) if name.as_ref() == Some(&arg_name.text) => {
+                let (a, new_tcs) = tcs.check(a, &param_ty).map_err(|e| e.wrap(info))?;
+                args.next();
+                tcs = new_tcs;
+                a.ast
+            }
+            (Plicit::Im, _) => tcs.fresh_meta_of(&param_ty),
+            (Plicit::Ex, Some((None, a))) => {
+                let (a, new_tcs) = tcs.check(a, &param_ty).map_err(|e| e.wrap(info))?;
+                args.next();
+                tcs = new_tcs;
+                checked.push(a.ast.clone());
+                a.ast
+            }
+            (Plicit::Ex, _) => break,
+        };
+        match rest {
+            Some(rest) => ty = rest.instantiate(arg),
+            None => break,
+        }
+    }
+    match args.next() {
+        Some((Some(name), _)) => {
+            let payload_ty = tcs.zonk_universes(payload_ty);
+            Err(TCE::NoSuchImplicit(name.clone(), payload_ty))
+        }
+        _ => Ok((checked, tcs)),
+    }
+}
+
+/// The number of explicit parameters of a function type.
+fn explicit_params(ty: &Val) -> usize {
+    match ty {
+        Val::Dt(Pi, plicit, _, _, Closure::Plain(ret)) => {
+            explicit_params(ret) + (*plicit == Plicit::Ex) as usize
+        }
+        Val::Dt(Pi, Plicit::Ex, ..) => 1,
+        _ => 0,
+    }
+}
+
+/// Check the value of a local definition against its signature (or infer its type),
+/// then bring it into the local context, with the value known.
+fn check_local_def(tcs: TCS, name: &Ident, sig: &Option<Box<Abs>>, value: &Abs) -> TCM<TCS> {
+    let (value, ty, mut tcs) = match sig {
+        Some(sig) => {
+            let (ty, tcs) = tcs.check(&**sig, &TYPE_OMEGA)?;
+            let (value, tcs) = tcs.check(value, &ty.ast)?;
+            (value, ty.ast, tcs)
+        }
+        None => {
+            let (value, ty, tcs) = tcs.elaborate(value)?;
+            (value, ty.ast, tcs)
+        }
+    };
+    tcs.local_gamma.push(ty.into_info(name.loc));
+    tcs.push_local_def(value);
+    Ok(tcs)
 }
 
 fn mock_for(param_ty: &Val, fallback: impl FnOnce() -> Val) -> Val {
@@ -279,7 +606,7 @@ fn mock_for(param_ty: &Val, fallback: impl FnOnce() -> Val) -> Val {
             RowPoly(Record, v) if v.is_empty() => Some(Rec(Default::default())),
             RowPoly(Variant, v) if v.len() == 1 => {
                 let (name, ty) = v.iter().next().unwrap();
-                Some(Val::cons(name.clone(), go(ty)?))
+                Some(Val::cons(name.clone(), vec![go(ty)?]))
             }
             _ => None,
         }
@@ -288,11 +615,29 @@ fn mock_for(param_ty: &Val, fallback: impl FnOnce() -> Val) -> Val {
 }
 
 fn check_fallback(tcs: TCS, expr: &Abs, expected_type: &Val) -> ValTCM {
-    let (inferred, tcs) = tcs.infer(expr)?;
-    Ok(tcs
-        .subtype(&inferred.ast, expected_type)
-        .map_err(|e| e.wrap(inferred.loc))?
-        .evaluate(expr.clone()))
+    let (value, inferred, tcs) = tcs.elaborate(expr)?;
+    // An unknown expected type may as well be the polymorphic one.
+    let (inferred_type, tcs) = match expected_type {
+        Val::Neut(Neutral::Meta(..)) => (inferred.ast, tcs),
+        _ => insert_implicits(tcs, inferred.ast),
+    };
+    let inferred_type = inferred_type.into_info(inferred.loc);
+    coerce_elaborated(tcs, value, inferred_type, expected_type)
+}
+
+/// Coerce the elaborated `value` from its type to the expected one.
+fn coerce_elaborated(tcs: TCS, value: ValInfo, inferred: ValInfo, expected_type: &Val) -> ValTCM {
+    let (coercion, tcs) =
+        (tcs.subtype(&inferred.ast, expected_type)).map_err(|e| e.wrap(inferred.loc))?;
+    Ok((value.map_ast(|value| coercion.coerce(value)), tcs))
+}
+
+/// Instantiate the leading implicit parameters of `ty` with fresh metas.
+fn insert_implicits(mut tcs: TCS, mut ty: Val) -> (Val, TCS) {
+    while let Val::Dt(Pi, Plicit::Im, _, param_ty, closure) = ty {
+        ty = closure.instantiate(tcs.fresh_meta_of(&param_ty));
+    }
+    (ty, tcs)
 }
 
 fn check_fields_no_more(
@@ -307,24 +652,68 @@ fn check_fields_no_more(
     }
 }
 
+/// The fields not in the expected record type belong to its extension,
+/// which is a meta solved by their types.
+fn check_extra_fields(
+    mut tcs: TCS,
+    mut nice_fields: Fields,
+    fields: &[LabAbs],
+    field_types: &Variants,
+    more: &Neutral,
+) -> TCM<(Fields, TCS)> {
+    let mut extra_types = Variants::new();
+    for field in fields {
+        if field_types.contains_key(&field.label.text) {
+            continue;
+        }
+        let (val, ty, new_tcs) = tcs.elaborate(&field.expr)?;
+        tcs = new_tcs;
+        extra_types.insert(field.label.text.clone(), ty.ast);
+        nice_fields.insert(field.label.text.clone(), val.ast);
+    }
+    let tcs = tcs.unify(&Val::Neut(more.clone()), &Val::record_type(extra_types))?;
+    Ok((nice_fields, tcs))
+}
+
+/// Check the fields against their types, which may refer to the other fields,
+/// so a field is checked after the fields its type refers to.
+/// The references to the checked fields are instantiated in the rest field types.
 fn check_fields(
     mut tcs: TCS,
     fields: &[LabAbs],
     field_types: &Fields,
 ) -> TCM<(Fields, Variants, TCS)> {
     let mut nice_fields = Fields::new();
-    for field in fields {
-        if let Some(ty) = field_types.get(&field.label.text) {
-            let key = field.label.text.clone();
-            let (field, new_tcs) = tcs.check(&field.expr, ty)?;
-            tcs = new_tcs;
-            nice_fields.insert(key, field.ast);
-        }
+    let mut pending: Vec<_> = (fields.iter())
+        .filter(|field| field_types.contains_key(&field.label.text))
+        .collect();
+    while !pending.is_empty() {
+        let is_pending = |label: &String| pending.iter().any(|field| &field.label.text == label);
+        let ready = (pending.iter()).position(|field| {
+            let refs = field_types[&field.label.text].field_refs();
+            refs.iter().all(|label| !is_pending(label))
+        });
+        let field = match ready {
+            Some(ready) => pending.remove(ready),
+            None => {
+                let labels = (pending.iter()).map(|field| field.label.text.clone());
+                return Err(TCE::CyclicFields(pending[0].label.loc, labels.collect()));
+            }
+        };
+        let ty = field_types[&field.label.text]
+            .clone()
+            .instantiate_fields_with(&mut |label| nice_fields.get(label).cloned());
+        let (val, new_tcs) = tcs.check(&field.expr, &ty)?;
+        tcs = new_tcs;
+        nice_fields.insert(field.label.text.clone(), val.ast);
     }
     let rest_field_types = field_types
         .iter()
         .filter(|(label, _)| !nice_fields.contains_key(&**label))
-        .map(|(label, expr)| (label.clone(), expr.clone()))
+        .map(|(label, ty)| {
+            let ty = (ty.clone()).instantiate_fields_with(&mut |l| nice_fields.get(l).cloned());
+            (label.clone(), ty)
+        })
         .collect();
     Ok((nice_fields, rest_field_types, tcs))
 }
@@ -369,16 +758,19 @@ $$
 fn check_row_polymorphic_type(
     mut tcs: TCS,
     info: Loc,
-    level: Level,
+    universe: &Val,
     kind: VarRec,
     variants: &[LabAbs],
     ext: &Option<Box<Abs>>,
     labels: &[String],
 ) -> ValTCM {
     let mut out_variants = Variants::new();
+    if kind == Record {
+        tcs.enter_fields();
+    }
     for labelled in variants {
         let (val, new_tcs) = tcs
-            .check(&labelled.expr, &Val::Type(level))
+            .check(&labelled.expr, universe)
             .map_err(|e| e.wrap(info))?;
         tcs = new_tcs;
         let label = &labelled.label.text;
@@ -387,13 +779,25 @@ fn check_row_polymorphic_type(
         } else if labels.contains(label) {
             return Err(TCE::UnexpectedVariant(val.loc, label.clone()));
         }
+        if kind == Record {
+            tcs.local_gamma.push(val.clone());
+            tcs.push_field(&labelled.label);
+        }
         out_variants.insert(label.clone(), val.ast);
     }
+    if kind == Record {
+        tcs.pop_fields(variants.len());
+    }
     match ext {
         None => Ok((Val::RowPoly(kind, out_variants).into_info(info), tcs)),
         Some(ext) => {
             let known_labels = out_variants.keys().chain(labels.iter()).cloned().collect();
-            let expected_kind = Val::RowKind(Default::default(), kind, known_labels);
+            // The extension of a row at an unknown level may be of any level.
+            let level = match universe {
+                Val::Type(level) => *level,
+                _ => Level::Omega,
+            };
+            let expected_kind = Val::RowKind(level, kind, known_labels);
             let (ext, new_tcs) = tcs
                 .check(&**ext, &expected_kind)
                 .map_err(|e| e.wrap(info))?;
@@ -473,17 +877,36 @@ fn infer(tcs: TCS, value: &Abs) -> ValTCM {
     let info = value.loc();
     match value {
         Type(_, level) => Ok((Val::Type(*level + 1).into_info(info), tcs)),
+        Universe(_, mi) => {
+            let mut tcs = tcs;
+            tcs.universes.insert_var(*mi);
+            let level = tcs.expand_level(Val::meta(*mi));
+            let universe = level.clone().level_suc(1).and_then(Val::univ);
+            let universe = universe.ok_or(TCE::NotLevel(level))?;
+            Ok((universe.into_info(info), tcs))
+        }
         RowKind(..) => Ok((Val::Type(From::from(1 as LevelType)).into_info(info), tcs)),
+        LevelPrim(_, prim) => Ok((level_prim_type(*prim).into_info(info), tcs)),
         RowPoly(_, kind, variants, more) => {
             let mut labels = Vec::with_capacity(variants.len());
             let mut tcs = tcs;
             let mut max_level = Level::default();
+            if *kind == Record {
+                tcs.enter_fields();
+            }
             for variant in variants {
                 let (val, new_tcs) = tcs.check(&variant.expr, &TYPE_OMEGA)?;
                 tcs = new_tcs;
                 labels.push(variant.label.text.clone());
                 // Not sure :(
                 max_level = max_level.max(val.ast.level());
+                if *kind == Record {
+                    tcs.local_gamma.push(val);
+                    tcs.push_field(&variant.label);
+                }
+            }
+            if *kind == Record {
+                tcs.pop_fields(variants.len());
             }
             let kind_level = max_level + 1;
             match more {
@@ -496,36 +919,14 @@ fn infer(tcs: TCS, value: &Abs) -> ValTCM {
             }
         }
         Rec(_, fields, ext) => {
-            let (ext, tcs) = ext
-                .as_ref()
-                .map(|abs| tcs.infer(&**abs).map_err(|e| e.wrap(info)))
-                .transpose()?
-                .unwrap_or_default();
-            let (mut ext_fields, more) = match ext.ast {
-                Val::RowPoly(Record, fields) => (fields, None),
-                Val::Neut(Neutral::Row(Record, fields, more)) => (fields, Some(*more)),
-                e => return Err(TCE::NotRecVal(ext.loc, e)),
-            };
-            let mut tcs = tcs;
-            for field in fields {
-                if ext_fields.contains_key(&field.label.text) {
-                    return Err(TCE::duplicate_field(field.label.clone()));
-                }
-                let (inferred, new_tcs) = tcs.infer(&field.expr).map_err(|e| e.wrap(info))?;
-                tcs = new_tcs;
-                ext_fields.insert(field.label.text.clone(), inferred.ast);
-            }
-            let ty = match more {
-                None => Val::record_type(ext_fields),
-                Some(more) => Val::neutral_record_type(ext_fields, more),
-            };
-            Ok((ty.into_info(info), tcs))
+            let (_, ty, tcs) = elaborate_record(tcs, info, fields, ext)?;
+            Ok((ty, tcs))
         }
         Var(_, _, dbi) => {
-            let local = tcs.local_type(*dbi).ast.clone().attach_dbi(*dbi);
+            let local = tcs.attach_local_dbi(tcs.local_type(*dbi).ast.clone(), *dbi);
             Ok((local.into_info(info), tcs))
         }
-        Lam(..) => {
+        Lam(..) | CaseOr(..) => {
             let mut tcs = tcs;
             let param_meta = tcs.fresh_meta();
             let ret_meta = tcs.fresh_meta();
@@ -541,6 +942,18 @@ fn infer(tcs: TCS, value: &Abs) -> ValTCM {
             let (expr, tcs) = tcs.infer(&**expr).map_err(|e| e.wrap(info))?;
             Ok((expr.map_ast(|ast| ast.lift(*levels)), tcs))
         }
+        Ann(_, expr, ty) => {
+            let (ty, tcs) = tcs.check(&**ty, &TYPE_OMEGA).map_err(|e| e.wrap(info))?;
+            let (_, tcs) = tcs.check(&**expr, &ty.ast).map_err(|e| e.wrap(info))?;
+            Ok((ty.ast.into_info(info), tcs))
+        }
+        Let(_, name, _, sig, value, body) => {
+            let tcs = check_local_def(tcs, name, sig, value).map_err(|e| e.wrap(info))?;
+            let (body_ty, mut tcs) = tcs.infer(&**body).map_err(|e| e.wrap(info))?;
+            tcs.local_gamma.pop();
+            tcs.pop_local_def();
+            Ok((body_ty.ast.into_info(info), tcs))
+        }
         Ref(_, dbi) => Ok((tcs.glob_type(*dbi).ast.clone().into_info(info), tcs)),
         Pair(_, fst, snd) => {
             let (fst_ty, tcs) = tcs.infer(&**fst).map_err(|e| e.wrap(info))?;
@@ -550,26 +963,46 @@ fn infer(tcs: TCS, value: &Abs) -> ValTCM {
         }
         Fst(_, pair) => {
             let (pair_ty, tcs) = tcs.infer(&**pair).map_err(|e| e.wrap(info))?;
-            match pair_ty.ast {
-                Val::Dt(Sigma, Plicit::Ex, param_type, ..) => Ok((param_type.into_info(info), tcs)),
+            let (pair_ty_ast, tcs) = insert_implicits(tcs, pair_ty.ast);
+            match pair_ty_ast {
+                Val::Dt(Sigma, Plicit::Ex, _, param_type, ..) => {
+                    Ok((param_type.into_info(info), tcs))
+                }
                 ast => Err(TCE::NotSigma(pair_ty.loc, ast)),
             }
         }
         Proj(_, record, field) => {
             let (record_ty, tcs) = tcs.infer(&**record).map_err(|e| e.wrap(info))?;
-            match record_ty.ast {
+            let (record_ty_ast, tcs) = insert_implicits(tcs, record_ty.ast);
+            match record_ty_ast {
+                Val::Neut(Neutral::Row(Record, fields, more))
+                    if !fields.contains_key(&field.text) && matches!(*more, Neutral::Meta(..)) =>
+                {
+                    let ty = Val::neutral_record_type(fields, *more);
+                    let (field_ty, _, tcs) = unify_open_record(tcs, info, &ty, field)?;
+                    Ok((field_ty.into_info(info), tcs))
+                }
+                ty @ Val::Neut(Neutral::Meta(..)) => {
+                    let (field_ty, _, tcs) = unify_open_record(tcs, info, &ty, field)?;
+                    Ok((field_ty.into_info(info), tcs))
+                }
                 Val::Neut(Neutral::Row(Record, mut fields, ..))
-                | Val::RowPoly(Record, mut fields) => fields
-                    .remove(&field.text)
-                    .map(|ty| (ty.into_info(info), tcs))
-                    .ok_or_else(|| TCE::MissingVariant(Record, field.text.clone())),
+                | Val::RowPoly(Record, mut fields) => {
+                    let ty = (fields.remove(&field.text))
+                        .ok_or_else(|| TCE::MissingVariant(Record, field.text.clone()))?;
+                    let (ty, tcs) = instantiate_field_type(tcs, record, ty);
+                    Ok((ty.into_info(info), tcs))
+                }
                 ast => Err(TCE::NotRowType(Record, record_ty.loc, ast)),
             }
         }
+        Restrict(_, record, label) => infer_label_op(tcs, info, record, label, None),
+        Rename(_, record, from, to) => infer_label_op(tcs, info, record, from, Some(to)),
         Snd(_, pair) => {
             let (pair_ty, tcs) = tcs.infer(&**pair).map_err(|e| e.wrap(info))?;
-            match pair_ty.ast {
-                Val::Dt(Sigma, Plicit::Ex, _, closure) => {
+            let (pair_ty_ast, tcs) = insert_implicits(tcs, pair_ty.ast);
+            match pair_ty_ast {
+                Val::Dt(Sigma, Plicit::Ex, _, _, closure) => {
                     // Since we can infer the type of `pair`, it has to be well-typed
                     let (pair_compiled, tcs) = tcs.evaluate(*pair.clone());
                     let fst = pair_compiled.ast.first();
@@ -578,48 +1011,420 @@ fn infer(tcs: TCS, value: &Abs) -> ValTCM {
                 ast => Err(TCE::NotSigma(pair_ty.loc, ast)),
             }
         }
-        App(_, f, _app_plicit, a) => match &**f {
-            Cons(variant_info) => {
-                let (a, tcs) = tcs.infer(a).map_err(|e| e.wrap(info))?;
-                let mut variant = Variants::default();
-                variant.insert(variant_info.text[1..].to_owned(), a.ast);
-                Ok((Val::variant_type(variant).into_info(info), tcs))
+        App(..) if cons_spine(value).is_some() => {
+            let (cons, args) = cons_spine(value).unwrap();
+            infer_cons_app(tcs, info, value, cons, args)
+        }
+        App(_, f, name, a) => match &**f {
+            Type(_, Level::Num(levels)) if name.is_none() => {
+                let (level, tcs) = tcs.check(a, &Val::LevelTy).map_err(|e| e.wrap(info))?;
+                let level = tcs.expand_level(level.ast);
+                let universe = level.clone().level_suc(levels + 1).and_then(Val::univ);
+                let universe = universe
+                    .ok_or(TCE::NotLevel(level))
+                    .map_err(|e| e.wrap(info))?;
+                Ok((universe.into_info(info), tcs))
             }
-            Whatever(whatever_info) => {
+            Whatever(whatever_info) if name.is_none() => {
                 let empty = Val::Lam(Closure::default());
                 let (_, mut tcs) = tcs.check(a, &empty).map_err(|e| e.wrap(info))?;
                 Ok((tcs.fresh_meta().into_info(*whatever_info), tcs))
             }
             f => {
                 let (f_ty, tcs) = tcs.infer(f).map_err(|e| e.wrap(info))?;
-                check_app_type(tcs, f, info, a, &f_ty.ast)
+                let (ty, _, tcs) = infer_app(tcs, info, f, name.as_ref(), a, f_ty.ast)?;
+                Ok((ty, tcs))
             }
         },
         e => Err(TCE::CannotInfer(info, e.clone())),
     }
 }
 
-/// Recursive function to insert meta for implicit argument
-fn check_app_type(tcs: TCS, f: &Abs, info: Loc, a: &Abs, pi_ty: &Val) -> ValTCM {
+/// Check the argument of an application of `f`, which is of type `f_ty`.
+/// Returns the type of the application and the checked argument.
+fn infer_app(
+    tcs: TCS,
+    info: Loc,
+    f: &Abs,
+    name: Option<&Ident>,
+    a: &Abs,
+    f_ty: Val,
+) -> TCM<(ValInfo, ValInfo, TCS)> {
+    // Report the whole function type.
+    let shown = name.map(|_| tcs.zonk_universes(f_ty.clone()));
+    check_app_type(tcs, f, info, name, a, &f_ty).map_err(|e| match e {
+        TCE::NoSuchImplicit(name, _) => TCE::NoSuchImplicit(name, shown.unwrap()),
+        e => e,
+    })
+}
+
+/// Infer the type of `expr` like `infer`, and produce the term of it.
+/// Unlike `evaluate`, the coercions inserted while checking the arguments,
+/// the components of pairs and the ascribed terms are kept in the term.
+fn elaborate(tcs: TCS, expr: &Abs) -> TCM<(ValInfo, ValInfo, TCS)> {
+    use Abs::*;
+    let info = expr.loc();
+    match expr {
+        App(_, f, name, a) if cons_spine(expr).is_none() && !is_universe_app(f, name) => {
+            let (f_val, f_ty, tcs) = elaborate(tcs, f).map_err(|e| e.wrap(info))?;
+            let (ty, a, tcs) = infer_app(tcs, info, f, name.as_ref(), a, f_ty.ast)?;
+            // Implicit arguments are erased.
+            let (value, tcs) = match name {
+                Some(_) => (f_val.ast, tcs),
+                None => tcs.apply_global(f_val.ast, a.ast),
+            };
+            Ok((value.into_info(info), ty, tcs))
+        }
+        Ann(_, expr, ty) => {
+            let (ty, tcs) = tcs.check(&**ty, &TYPE_OMEGA).map_err(|e| e.wrap(info))?;
+            let (value, tcs) = tcs.check(&**expr, &ty.ast).map_err(|e| e.wrap(info))?;
+            Ok((value.ast.into_info(info), ty.ast.into_info(info), tcs))
+        }
+        Pair(_, fst, snd) => {
+            let (fst, fst_ty, tcs) = elaborate(tcs, fst).map_err(|e| e.wrap(info))?;
+            let (snd, snd_ty, tcs) = elaborate(tcs, snd).map_err(|e| e.wrap(info))?;
+            let sigma = Val::sig(fst_ty.ast, Closure::plain(snd_ty.ast));
+            let pair = Val::pair(fst.ast, snd.ast);
+            Ok((pair.into_info(info), sigma.into_info(info), tcs))
+        }
+        Rec(_, fields, ext) => elaborate_record(tcs, info, fields, ext),
+        Let(_, name, _, sig, value, body) => {
+            let tcs = check_local_def(tcs, name, sig, value).map_err(|e| e.wrap(info))?;
+            let (body, body_ty, mut tcs) = elaborate(tcs, body).map_err(|e| e.wrap(info))?;
+            tcs.local_gamma.pop();
+            tcs.pop_local_def();
+            Ok((body.ast.into_info(info), body_ty.ast.into_info(info), tcs))
+        }
+        _ => {
+            let (ty, tcs) = tcs.infer(expr)?;
+            let (value, tcs) = tcs.evaluate(expr.clone());
+            Ok((value, ty, tcs))
+        }
+    }
+}
+
+/// Infer the type of a record, and produce the record with its fields elaborated.
+fn elaborate_record(
+    tcs: TCS,
+    info: Loc,
+    fields: &[LabAbs],
+    ext: &Option<Box<Abs>>,
+) -> TCM<(ValInfo, ValInfo, TCS)> {
+    let (ext_val, ext, tcs) = match ext {
+        Some(abs) => {
+            let (ext_val, ext, tcs) = tcs.elaborate(&**abs).map_err(|e| e.wrap(info))?;
+            (Some(ext_val.ast), ext, tcs)
+        }
+        None => (
+            None,
+            Val::record_type(Default::default()).into_info(info),
+            tcs,
+        ),
+    };
+    let (mut ext_fields, more) = match ext.ast {
+        Val::RowPoly(Record, fields) => (fields, None),
+        Val::Neut(Neutral::Row(Record, fields, more)) => (fields, Some(*more)),
+        // A record of a row variable, like `r` in `(R : Rec [x]) -> (r : R) -> ..`.
+        Val::Neut(more @ Neutral::Axi(..)) | Val::Neut(more @ Neutral::Meta(..)) => {
+            (Default::default(), Some(more))
+        }
+        e => return Err(TCE::NotRecVal(ext.loc, e)),
+    };
+    let mut tcs = tcs;
+    let mut field_vals = Fields::new();
+    for field in fields {
+        if ext_fields.contains_key(&field.label.text) {
+            return Err(TCE::duplicate_field(field.label.clone()));
+        }
+        let (val, inferred, new_tcs) = tcs.elaborate(&field.expr).map_err(|e| e.wrap(info))?;
+        tcs = new_tcs;
+        field_vals.insert(field.label.text.clone(), val.ast);
+        ext_fields.insert(field.label.text.clone(), inferred.ast);
+    }
+    let ty = match more {
+        None => Val::record_type(ext_fields),
+        Some(more) => {
+            // Otherwise the fields in `more` are overwritten.
+            let labels: Vec<_> = fields.iter().map(|f| f.label.text.clone()).collect();
+            let more = Val::Neut(more);
+            tcs = tcs
+                .require_lacks(Record, &more, &labels)
+                .map_err(|e| e.wrap(info))?;
+            Val::RowPoly(Record, ext_fields).row_extend(more)
+        }
+    };
+    let record = Val::Rec(field_vals);
+    let record = match ext_val {
+        Some(ext_val) => record.rec_extend(ext_val),
+        None => record,
+    };
+    Ok((record.into_info(info), ty.into_info(info), tcs))
+}
+
+/// `Type l` and `whatever a`, which are not applications of functions.
+fn is_universe_app(f: &Abs, name: &Option<Ident>) -> bool {
+    name.is_none() && matches!(f, Abs::Type(_, Level::Num(..)) | Abs::Whatever(..))
+}
+
+/// A constructor applied without an expected type is of the variant type of it,
+/// with the arguments being the components of the payload type.
+/// The variant type is extended by a fresh row meta, so other constructors can join.
+fn infer_cons_app(
+    mut tcs: TCS,
+    info: Loc,
+    value: &Abs,
+    cons: &Ident,
+    args: Vec<(Option<&Ident>, &Abs)>,
+) -> ValTCM {
+    let mut arg_types = Vec::with_capacity(args.len());
+    for (name, a) in args {
+        if name.is_some() {
+            return Err(TCE::CannotInfer(info, value.clone()));
+        }
+        let (ty, new_tcs) = tcs.infer(a).map_err(|e| e.wrap(info))?;
+        tcs = new_tcs;
+        arg_types.push(ty.ast);
+    }
+    let last = arg_types.pop().unwrap();
+    let payload_ty =
+        (arg_types.into_iter()).rfold(last, |rest, ty| Val::sig(ty, Closure::plain(rest)));
+    let label = cons.text[1..].to_owned();
+    let more = tcs.fresh_row_meta(Variant, &[label.clone()]);
+    let mut variant = Variants::default();
+    variant.insert(label, payload_ty);
+    let open_ty = Val::neutral_variant_type(variant, more);
+    Ok((open_ty.into_info(info), tcs))
+}
+
+/// Projection from a record of a partially known type.
+/// The type is unified with a record type of this field, extended by a fresh row meta,
+/// so the other fields are inferred from the other usages.
+/// Returns the type of the field and the row meta.
+fn unify_open_record(
+    mut tcs: TCS,
+    info: Loc,
+    record_ty: &Val,
+    field: &Ident,
+) -> TCM<(Val, Neutral, TCS)> {
+    let field_ty = tcs.fresh_meta();
+    let more = tcs.fresh_row_meta(Record, &[field.text.clone()]);
+    let mut fields = Variants::new();
+    fields.insert(field.text.clone(), field_ty.clone());
+    let open_ty = Val::neutral_record_type(fields, more.clone());
+    let tcs = (tcs.unify(record_ty, &open_ty)).map_err(|e| e.wrap(info))?;
+    Ok((field_ty, more, tcs))
+}
+
+/// The type of a field may refer to the other fields of `record`,
+/// which are instantiated with the fields of `record`.
+fn instantiate_field_type(tcs: TCS, record: &Abs, ty: Val) -> (Val, TCS) {
+    if ty.field_refs().is_empty() {
+        return (ty, tcs);
+    }
+    // Since we can infer the type of `record`, it has to be well-typed
+    let (record, tcs) = tcs.evaluate(record.clone());
+    (ty.instantiate_fields(&record.ast), tcs)
+}
+
+/// Restriction `r \ x`, or renaming `r [x -> y]` if `to` is present.
+/// Types and rows are restricted (or renamed) as a whole, keeping their kinds,
+/// while records get the label removed from (or renamed in) their types.
+/// The renamed label should not be present before renaming.
+fn infer_label_op(tcs: TCS, info: Loc, record: &Abs, from: &Ident, to: Option<&Ident>) -> ValTCM {
+    let (record_ty, tcs) = tcs.infer(record).map_err(|e| e.wrap(info))?;
+    let (record_ty_ast, tcs) = insert_implicits(tcs, record_ty.ast);
+    let lacks = |tcs: TCS, kind, row: &Val| match to {
+        Some(to) => (tcs.require_lacks(kind, row, &[to.text.clone()])).map_err(|e| e.wrap(info)),
+        None => Ok(tcs),
+    };
+    match record_ty_ast {
+        univ @ Val::Type(..) | univ @ Val::Neut(Neutral::Type(..)) => {
+            let (row, tcs) = tcs.evaluate(record.clone());
+            let tcs = match &row.ast {
+                Val::RowPoly(kind, fields) | Val::Neut(Neutral::Row(kind, fields, ..)) => {
+                    // Renaming renames the references as well.
+                    let dependent = (fields.iter())
+                        .find(|(_, ty)| to.is_none() && ty.field_refs().contains(&from.text));
+                    if let Some((dependent, _)) = dependent {
+                        let (from, dependent) = (from.text.clone(), dependent.clone());
+                        return Err(TCE::DependedField(info, from, dependent));
+                    }
+                    lacks(tcs, *kind, &row.ast)?
+                }
+                ast => return Err(TCE::NotRowType(Record, row.loc, ast.clone())),
+            };
+            Ok((univ.into_info(info), tcs))
+        }
+        Val::RowKind(level, kind, mut labels) => {
+            let (row, tcs) = tcs.evaluate(record.clone());
+            let tcs = lacks(tcs, kind, &row.ast)?;
+            // Otherwise the label is absent, so is the operation.
+            if !labels.contains(&from.text) {
+                if let Some(to) = to {
+                    labels.retain(|label| label != &to.text);
+                }
+                labels.push(from.text.clone());
+            }
+            Ok((Val::RowKind(level, kind, labels).into_info(info), tcs))
+        }
+        Val::Neut(Neutral::Row(Record, fields, more))
+            if !fields.contains_key(&from.text) && matches!(*more, Neutral::Meta(..)) =>
+        {
+            let ty = Val::neutral_record_type(fields, *more);
+            infer_open_label_op(tcs, info, &ty, from, to)
+        }
+        ty @ Val::Neut(Neutral::Meta(..)) => infer_open_label_op(tcs, info, &ty, from, to),
+        ty @ Val::RowPoly(Record, ..) | ty @ Val::Neut(Neutral::Row(Record, ..)) => {
+            let fields = match &ty {
+                Val::RowPoly(_, fields) | Val::Neut(Neutral::Row(_, fields, ..)) => fields,
+                _ => unreachable!(),
+            };
+            if !fields.contains_key(&from.text) {
+                return Err(TCE::MissingVariant(Record, from.text.clone()).wrap(info));
+            }
+            let dependent = (fields.values()).any(|ty| ty.field_refs().contains(&from.text));
+            let tcs = lacks(tcs, Record, &ty)?;
+            let (ty, tcs) = match to {
+                Some(to) => (ty.rename(from.text.clone(), to.text.clone()), tcs),
+                // The types referring to the removed field get the field of the record.
+                None if dependent => {
+                    let (record, tcs) = tcs.evaluate(record.clone());
+                    let ty = instantiate_field_refs(ty, &from.text, &record.ast);
+                    (ty.restrict(from.text.clone()), tcs)
+                }
+                None => (ty.restrict(from.text.clone()), tcs),
+            };
+            Ok((ty.into_info(info), tcs))
+        }
+        ast => Err(TCE::NotRowType(Record, record_ty.loc, ast)),
+    }
+}
+
+/// Instantiate the references to `label` in the field types of `record_ty`
+/// with the field of `record`.
+fn instantiate_field_refs(record_ty: Val, label: &str, record: &Val) -> Val {
+    let inst = |variants: Variants| {
+        (variants.into_iter())
+            .map(|(name, ty)| {
+                let ty = ty.instantiate_fields_with(&mut |l| {
+                    if l == label {
+                        Some(record.clone().project(l.clone()))
+                    } else {
+                        None
+                    }
+                });
+                (name, ty)
+            })
+            .collect()
+    };
+    match record_ty {
+        Val::RowPoly(kind, variants) => Val::RowPoly(kind, inst(variants)),
+        Val::Neut(Neutral::Row(kind, variants, more)) => {
+            Val::neutral_row_type(kind, inst(variants), *more)
+        }
+        ty => ty,
+    }
+}
+
+/// [`infer_label_op`](self::infer_label_op) on a record of a partially known type,
+/// the rest of the fields are represented by the row meta from
+/// [`unify_open_record`](self::unify_open_record).
+fn infer_open_label_op(
+    tcs: TCS,
+    info: Loc,
+    record_ty: &Val,
+    from: &Ident,
+    to: Option<&Ident>,
+) -> ValTCM {
+    let (field_ty, more, tcs) = unify_open_record(tcs, info, record_ty, from)?;
+    let more = Val::Neut(more);
+    match to {
+        Some(to) => {
+            let tcs =
+                (tcs.require_lacks(Record, &more, &[to.text.clone()])).map_err(|e| e.wrap(info))?;
+            let mut fields = Variants::new();
+            fields.insert(to.text.clone(), field_ty);
+            Ok((
+                Val::RowPoly(Record, fields)
+                    .row_extend(more)
+                    .into_info(info),
+                tcs,
+            ))
+        }
+        None => Ok((more.into_info(info), tcs)),
+    }
+}
+
+/// Types of the level primitives.
+fn level_prim_type(prim: LevelPrim) -> Val {
+    let level_to = |ret| Val::pi(Plicit::Ex, Val::LevelTy, Closure::plain(ret));
+    match prim {
+        LevelPrim::Level => Val::Type(Default::default()),
+        LevelPrim::Zero => Val::LevelTy,
+        LevelPrim::Suc => level_to(Val::LevelTy),
+        LevelPrim::Max => level_to(level_to(Val::LevelTy)),
+    }
+}
+
+/// Recursive function to insert meta for implicit argument,
+/// until the explicit parameter, or the implicit one called `name` if specified.
+/// Returns the type of the application and the checked argument.
+fn check_app_type(
+    tcs: TCS,
+    f: &Abs,
+    info: Loc,
+    name: Option<&Ident>,
+    a: &Abs,
+    pi_ty: &Val,
+) -> TCM<(ValInfo, ValInfo, TCS)> {
+    let is_named = |param_name: &String| name.map(|name| &name.text) == Some(param_name);
     match pi_ty {
-        Val::Dt(Pi, Plicit::Ex, param_type, closure) => {
+        Val::Dt(Pi, Plicit::Ex, _, param_type, closure) if name.is_none() => {
             let (new_a, tcs) = tcs.check(&a, &*param_type).map_err(|e| e.wrap(info))?;
-            Ok((closure.instantiate_cloned(new_a.ast).into_info(info), tcs))
+            let ty = closure
+                .instantiate_cloned(new_a.ast.clone())
+                .into_info(info);
+            Ok((ty, new_a, tcs))
         }
-        Val::Dt(Pi, Plicit::Im, _param_type, closure) => {
+        Val::Dt(Pi, Plicit::Im, Some(param_name), param_type, closure) if is_named(param_name) => {
+            let (new_a, tcs) = tcs.check(a, param_type).map_err(|e| e.wrap(info))?;
+            let ty = closure
+                .instantiate_cloned(new_a.ast.clone())
+                .into_info(info);
+            Ok((ty, new_a, tcs))
+        }
+        Val::Dt(Pi, Plicit::Im, _, param_type, closure) => {
             let mut tcs = tcs;
-            let inserted_meta = tcs.fresh_meta();
+            let inserted_meta = tcs.fresh_meta_of(param_type);
             let new_closure = closure.instantiate_cloned(inserted_meta);
-            check_app_type(tcs, f, info, a, &new_closure)
+            check_app_type(tcs, f, info, name, a, &new_closure)
         }
         Val::Neut(Neutral::Meta(mi)) => match tcs.meta_context.solution(*mi) {
             MetaSolution::Solved(sol) => {
                 let sol = *sol.clone();
-                check_app_type(tcs, f, info, a, &sol)
+                check_app_type(tcs, f, info, name, a, &sol)
             }
-            _ => Err(TCE::MetaUnsolved(*mi)),
+            // Refine the unknown function type to a non-dependent function type.
+            _ => {
+                let mut tcs = tcs;
+                let param_type = tcs.fresh_meta();
+                let ret_type = Closure::plain(tcs.fresh_meta());
+                let (plicit, param_name) = match name {
+                    Some(name) => (Plicit::Im, Some(name.text.clone())),
+                    None => (Plicit::Ex, None),
+                };
+                let refined = Val::dependent_type(Pi, plicit, param_name, param_type, ret_type);
+                let tcs = tcs.unify(pi_ty, &refined).map_err(|e| e.wrap(info))?;
+                check_app_type(tcs, f, info, name, a, &refined)
+            }
+        },
+        other => match name {
+            Some(name) => Err(TCE::NoSuchImplicit(
+                name.clone(),
+                tcs.zonk_universes(other.clone()),
+            )),
+            None => Err(TCE::NotPi(info, other.clone())),
         },
-        other => Err(TCE::NotPi(info, other.clone())),
     }
 }
 
@@ -646,12 +1451,17 @@ $$
 \newcommand{\variantR}[1]{\mathbb{Sum}\ #1}
 \newcommand{\recordR}[1]{\mathbb{Rec}\  #1}
 \cfrac{
-  \Gvdash \cA\_0 \simeq \cA\_1 \quad
+  \Gvdash \cA\_1 \subt \cA\_0 \quad
   \Gvdash \cB\_0 \subt \cB\_1
 }{
   \Gvdash \piTy{
     \xx : \cA\_0 . \cB\_0
-  } \subt \piTy{\xx : \cA\_1 . \cB\_1} \quad
+  } \subt \piTy{\xx : \cA\_1 . \cB\_1}
+} \quad
+\cfrac{
+  \Gvdash \cA\_0 \subt \cA\_1 \quad
+  \Gvdash \cB\_0 \subt \cB\_1
+}{
   \Gvdash \sigTy{
     \xx : \cA\_0 . \cB\_0
   } \subt \sigTy{\xx : \cA\_1 . \cB\_1}
@@ -685,6 +1495,14 @@ $$
     \Gvdash \record{n : \cA, \ctyLab\_0} \subt \record{\ctyLab_1}
   }
 }
+\\\\ \space \\\\
+\cfrac{
+  \Gvdash \cA\_0 \subt \cA\_1 \quad
+  \Gvdash \variant{\ctyLab\_0} \subt \variant{\ctyLab\_1}
+}{
+  \Gvdash \variant{n : \cA\_0, \ctyLab\_0} \subt \variant{n : \cA\_1, \ctyLab\_1} \quad
+  \Gvdash \record{n : \cA\_0, \ctyLab\_0} \subt \record{n : \cA\_1, \ctyLab\_1}
+}
 \\\\
 \cfrac{
 }{
@@ -692,32 +1510,182 @@ $$
   \Gvdash \variantR{ns} \subt \ty
 }
 $$
+The coercions are inserted into the core terms, and the open rows are compared
+like the closed ones if they have the same extension.
 */
-fn subtype(tcs: TCS, sub: &Val, sup: &Val) -> TCM {
+fn subtype(tcs: TCS, sub: &Val, sup: &Val) -> TCM<(Coercion, TCS)> {
     use Val::*;
+    let id = |tcs| Ok((Coercion::Id, tcs));
+    if let Some(((kind, sub_vs, sub_ext), (_, sup_vs, sup_ext))) = row_pair(sub, sup) {
+        return subtype_rows(tcs, kind, sub_vs, sup_vs, sub_ext, sup_ext);
+    }
     match (sub, sup) {
-        (RowKind(sub_l, ..), Type(sup_l)) | (Type(sub_l), Type(sup_l)) if sub_l <= sup_l => Ok(tcs),
-        (RowPoly(Record, sub_vs), RowPoly(Record, sup_vs)) => {
-            tcs.unify_variants(Record, sup_vs, sub_vs)
+        (RowKind(sub_l, ..), Type(sup_l)) | (Type(sub_l), Type(sup_l)) if sub_l <= sup_l => id(tcs),
+        (RowKind(sub_l, ..), Neut(Neutral::Type(..))) => tcs.subtype(&Type(*sub_l), sup),
+        (RowKind(sub_l, sub_k, sub_ls), RowKind(sup_l, sup_k, sup_ls))
+            if sub_k == sup_k && sub_l <= sup_l =>
+        {
+            match sup_ls.iter().find(|label| !sub_ls.contains(label)) {
+                None => id(tcs),
+                Some(label) => Err(TCE::RowNotLacking(*sub_k, sub.clone(), label.clone())),
+            }
         }
-        (RowPoly(Variant, sub_vs), RowPoly(Variant, sup_vs)) => {
-            tcs.unify_variants(Variant, sub_vs, sup_vs)
+        (e, t) if e.univ_level().is_some() && t.univ_level().is_some() => {
+            let sub_l = tcs.expand_level(e.univ_level().unwrap());
+            let sup_l = tcs.expand_level(t.univ_level().unwrap());
+            let mut tcs = tcs;
+            if level_leq(&sub_l, &sup_l) || tcs.constrain_universe(&sub_l, &sup_l) {
+                id(tcs)
+            } else {
+                id(tcs.unify(e, t)?)
+            }
         }
-        (Dt(k0, plicit_a, input_a, clos_a), Dt(k1, plicit_b, input_b, clos_b))
-            if k0 == k1 && plicit_a == plicit_b =>
+        (Dt(Pi, plicit_a, _, input_a, clos_a), Dt(Pi, plicit_b, _, input_b, clos_b))
+            if plicit_a == plicit_b =>
         {
-            // Parameter invariance
-            let tcs = tcs.unify(input_a, input_b)?;
-            let p = Val::fresh_axiom();
-            let a = clos_a.instantiate_borrow(&p);
+            // Parameter contravariance
+            let (dom, tcs) = tcs.subtype(input_b, input_a)?;
+            let uid = unsafe { next_uid() };
+            let p = Val::postulate(uid);
+            let a = clos_a.instantiate_cloned(dom.coerce(p.clone()));
             let b = clos_b.instantiate_cloned(p);
             // Return value covariance
-            tcs.subtype(&a, &b)
+            let (cod, tcs) = tcs.subtype(&a, &b)?;
+            Ok((Coercion::pi(*plicit_a, uid, dom, cod), tcs))
         }
-        (e, t) => tcs.unify(e, t),
+        (Dt(kind_a, plicit_a, _, input_a, clos_a), Dt(kind_b, plicit_b, _, input_b, clos_b))
+            if kind_a == kind_b && *kind_a != Pi && plicit_a == plicit_b =>
+        {
+            let (fst, tcs) = tcs.subtype(input_a, input_b)?;
+            let p = Val::fresh_axiom();
+            let b = clos_b.instantiate_cloned(fst.coerce(p.clone()));
+            let a = clos_a.instantiate_cloned(p);
+            let (snd, tcs) = tcs.subtype(&a, &b)?;
+            let coercion = match kind_a {
+                Tele => Coercion::tele(*plicit_a, fst, snd),
+                _ => Coercion::sigma(fst, snd),
+            };
+            Ok((coercion, tcs))
+        }
+        (e, t) if global_head(e) != global_head(t) => {
+            let mut tcs = tcs;
+            if tcs.assume_unfolded(e, t) {
+                return id(tcs);
+            }
+            match unfold_either(&tcs, e, t) {
+                Some((e_unfolded, t_unfolded)) => {
+                    let fallback = tcs.clone();
+                    tcs.enter_unfolding(e, t);
+                    let (coercion, mut tcs) = tcs.subtype(&e_unfolded, &t_unfolded)?;
+                    // The assumption is that the recursive occurrences need no coercion,
+                    // otherwise the types have to be the same.
+                    if tcs.leave_unfolding(e, t) && !coercion.is_id() {
+                        id(fallback.unify(e, t)?)
+                    } else {
+                        Ok((coercion, tcs))
+                    }
+                }
+                None => id(tcs.unify(e, t)?),
+            }
+        }
+        (e, t) => id(tcs.unify(e, t)?),
+    }
+}
+
+/// The kind, the fields (or constructors) and the extension of a row type.
+type Row<'a> = (VarRec, &'a Variants, Option<&'a Neutral>);
+
+fn row_of(ty: &Val) -> Option<Row<'_>> {
+    match ty {
+        Val::RowPoly(kind, variants) => Some((*kind, variants, None)),
+        Val::Neut(Neutral::Row(kind, variants, ext)) => Some((*kind, variants, Some(&**ext))),
+        _ => None,
+    }
+}
+
+/// Two rows of the same kind, where at most one of them has an extension
+/// not shared with the other.
+fn row_pair<'a>(sub: &'a Val, sup: &'a Val) -> Option<(Row<'a>, Row<'a>)> {
+    let (sub_row, sup_row) = (row_of(sub)?, row_of(sup)?);
+    let distinct_exts = match (sub_row.2, sup_row.2) {
+        (Some(sub_ext), Some(sup_ext)) => sub_ext != sup_ext,
+        _ => false,
+    };
+    if sub_row.0 == sup_row.0 && !distinct_exts {
+        Some((sub_row, sup_row))
+    } else {
+        None
     }
 }
 
+/// Width and depth subtyping of rows, where the records may have more fields
+/// and the variants may have less constructors.
+/// When only one of the rows has an extension, the labels it doesn't mention
+/// go to the extension, so the field types of open rows are also cumulative.
+fn subtype_rows(
+    mut tcs: TCS,
+    kind: VarRec,
+    sub: &Variants,
+    sup: &Variants,
+    sub_ext: Option<&Neutral>,
+    sup_ext: Option<&Neutral>,
+) -> TCM<(Coercion, TCS)> {
+    let open = sub_ext.is_some();
+    // A shared extension has nothing to absorb.
+    let (sub_ext, sup_ext) = if sub_ext == sup_ext {
+        (None, None)
+    } else {
+        (sub_ext, sup_ext)
+    };
+    let (less, more, less_ext, more_ext) = match kind {
+        Record => (sup, sub, sup_ext, sub_ext),
+        Variant => (sub, sup, sub_ext, sup_ext),
+    };
+    let mut coercions = BTreeMap::new();
+    let mut absorbed = Variants::new();
+    for (label, ty) in less {
+        let counterpart = match (more.get(label), more_ext) {
+            (Some(counterpart), _) => counterpart,
+            (None, Some(..)) => {
+                absorbed.insert(label.clone(), ty.clone());
+                coercions.insert(label.clone(), (ty.cons_arity(), Coercion::Id));
+                continue;
+            }
+            (None, None) => return Err(TCE::MissingVariant(kind, label.clone())),
+        };
+        let (sub_ty, sup_ty) = match kind {
+            Record => (counterpart, ty),
+            Variant => (ty, counterpart),
+        };
+        let (coercion, new_tcs) = tcs.subtype(sub_ty, sup_ty)?;
+        tcs = new_tcs;
+        coercions.insert(label.clone(), (sub_ty.cons_arity(), coercion));
+    }
+    if let Some(ext) = more_ext {
+        tcs = tcs.unify(&Val::Neut(ext.clone()), &Val::RowPoly(kind, absorbed))?;
+    }
+    let unmentioned = (more.iter()).filter(|(label, _)| !less.contains_key(*label));
+    let unmentioned = unmentioned.map(|(label, ty)| (label.clone(), ty.clone()));
+    let mut removed = Vec::new();
+    match less_ext {
+        Some(ext) => {
+            tcs = tcs.unify(
+                &Val::Neut(ext.clone()),
+                &Val::RowPoly(kind, unmentioned.collect()),
+            )?
+        }
+        None => removed.extend(unmentioned.map(|(label, _)| label)),
+    }
+    let coercion = match kind {
+        Record => {
+            let fields = coercions.into_iter().map(|(label, (_, c))| (label, c));
+            Coercion::record(fields.collect(), removed)
+        }
+        Variant => Coercion::variant(coercions, open),
+    };
+    Ok((coercion, tcs))
+}
+
 /// So you can do some functional programming based on method call chains.
 impl TCS {
     #[inline]
@@ -731,7 +1699,12 @@ impl TCS {
     }
 
     #[inline]
-    pub fn subtype(self, sub: &Val, sup: &Val) -> TCM {
+    pub(crate) fn elaborate(self, expr: &Abs) -> TCM<(ValInfo, ValInfo, TCS)> {
+        elaborate(self, expr)
+    }
+
+    #[inline]
+    pub(crate) fn subtype(self, sub: &Val, sup: &Val) -> TCM<(Coercion, TCS)> {
         subtype(self, sub, sup)
     }
 }
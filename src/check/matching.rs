@@ -0,0 +1,397 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use voile_util::axiom::Axiom;
+use voile_util::loc::*;
+use voile_util::tags::{PiSig::*, Plicit, VarRec::*};
+use voile_util::uid::{next_uid, UID};
+
+use crate::syntax::abs::{AbsClause, AbsPat};
+use crate::syntax::core::{CaseSplit, Closure, Neutral, TVal, Val};
+
+use super::expr::{cons_arg_types, explicit_payload};
+use super::monad::{ValTCM, TCE, TCM, TCS};
+
+/// A pattern to be matched against a term in scope.
+#[derive(Debug, Clone)]
+struct Equation<'a> {
+    pat: &'a AbsPat,
+    term: Val,
+    ty: TVal,
+}
+
+/// A clause during the compilation.
+#[derive(Debug, Clone)]
+struct Row<'a> {
+    /// Index of the clause.
+    clause: usize,
+    /// Patterns of the parameters not introduced yet.
+    params: &'a [AbsPat],
+    /// Constructor patterns, waiting for a split.
+    equations: Vec<Equation<'a>>,
+    /// Values and types of the pattern variables.
+    bindings: BTreeMap<UID, (Val, TVal)>,
+}
+
+/// The clauses, and whether they are used by the case tree.
+struct Clauses<'a> {
+    clauses: &'a [AbsClause],
+    reached: BTreeSet<usize>,
+    /// The parameters introduced, and their types.
+    params: Vec<(Val, TVal)>,
+    /// The terms split on, with the label, arguments and their types
+    /// of the current branch.
+    refined: Vec<(Val, String, Vec<(Val, TVal)>)>,
+    /// Cases not covered by any clause, shown as patterns.
+    missing: Vec<String>,
+}
+
+/**
+Compile the clauses of a pattern-matching function into case trees,
+the first matching clause wins.
+Every case must be covered by some clause, with counterexamples reported otherwise,
+and every clause must be used somewhere in the case tree.
+*/
+pub fn check_clauses(tcs: TCS, info: Loc, clauses: &[AbsClause], expected_type: &Val) -> ValTCM {
+    let arity = clauses[0].patterns.len();
+    if let Some(clause) = (clauses.iter()).find(|clause| clause.patterns.len() != arity) {
+        let actual = clause.patterns.len();
+        return Err(TCE::ArityMismatch(clause.loc, arity, actual));
+    }
+    let rows = (clauses.iter().enumerate())
+        .map(|(clause, abs)| Row {
+            clause,
+            params: &abs.patterns,
+            equations: Vec::new(),
+            bindings: Default::default(),
+        })
+        .collect();
+    let mut clauses = Clauses {
+        clauses,
+        reached: Default::default(),
+        params: Vec::with_capacity(arity),
+        refined: Vec::new(),
+        missing: Vec::new(),
+    };
+    let (term, tcs) = clauses
+        .compile(tcs, rows, expected_type.clone())
+        .map_err(|e| e.wrap(info))?;
+    let unreachable = (clauses.clauses.iter().enumerate())
+        .filter(|(i, _)| !clauses.reached.contains(i))
+        .map(|(_, clause)| TCE::UnreachableClause(clause.loc));
+    let mut errors = unreachable.collect::<Vec<_>>();
+    if !clauses.missing.is_empty() {
+        errors.insert(0, TCE::NonExhaustive(info, clauses.missing));
+    }
+    TCE::from_errors(errors)?;
+    Ok((term.into_info(info), tcs))
+}
+
+impl<'a> Clauses<'a> {
+    fn compile(&mut self, tcs: TCS, rows: Vec<Row<'a>>, ty: TVal) -> TCM<(Val, TCS)> {
+        let rows = (rows.into_iter())
+            .map(|row| simplify(&tcs, row))
+            .collect::<TCM<Vec<_>>>()?;
+        let first = &rows[0];
+        match first.equations.first() {
+            Some(equation) => {
+                let (scrutinee, scrutinee_ty) = (equation.term.clone(), equation.ty.clone());
+                let info = equation.pat.loc();
+                self.split(tcs, rows, info, scrutinee, scrutinee_ty, ty)
+            }
+            None if !first.params.is_empty() => self.intro(tcs, rows, ty),
+            None => self.leaf(tcs, &rows[0], ty),
+        }
+    }
+
+    /// Introduce the next parameter, matched against the next pattern of each row.
+    fn intro(&mut self, tcs: TCS, mut rows: Vec<Row<'a>>, ty: TVal) -> TCM<(Val, TCS)> {
+        match ty {
+            // Implicit parameters are erased, just like in `check`.
+            Val::Dt(Pi, Plicit::Im, _, _, closure) => {
+                self.intro(tcs, rows, closure.instantiate(Val::fresh_implicit()))
+            }
+            Val::Dt(Pi, Plicit::Ex, _, param_ty, closure) => {
+                let uid = unsafe { next_uid() };
+                let param = Val::postulate(uid);
+                for row in &mut rows {
+                    let (pat, params) = row.params.split_first().unwrap();
+                    row.params = params;
+                    let (term, ty) = (param.clone(), *param_ty.clone());
+                    row.equations.push(Equation { pat, term, ty });
+                }
+                self.params.push((param.clone(), *param_ty));
+                let compiled = self.compile(tcs, rows, closure.instantiate(param));
+                self.params.pop();
+                let (body, tcs) = compiled?;
+                Ok((lambda(body, uid), tcs))
+            }
+            ty => match tcs.unfold_global(&ty) {
+                Some(ty) => self.intro(tcs, rows, ty),
+                None => Err(TCE::NotPi(rows[0].params[0].loc(), ty)),
+            },
+        }
+    }
+
+    /// Split on a neutral term of a variant type, one branch for each variant.
+    fn split(
+        &mut self,
+        mut tcs: TCS,
+        rows: Vec<Row<'a>>,
+        info: Loc,
+        scrutinee: Val,
+        scrutinee_ty: TVal,
+        ty: TVal,
+    ) -> TCM<(Val, TCS)> {
+        let variants = match unfold(&tcs, scrutinee_ty) {
+            Val::RowPoly(Variant, variants) => variants,
+            ty => return Err(TCE::NotRowType(Variant, info, ty)),
+        };
+        for row in &rows {
+            for equation in row.equations.iter().filter(|eq| eq.term == scrutinee) {
+                let (label, pats) = match equation.pat {
+                    AbsPat::Cons(label, pats) => (label, pats),
+                    _ => continue,
+                };
+                let payload_ty = (variants.get(&label.text[1..]))
+                    .ok_or_else(|| TCE::UnexpectedVariant(label.loc, label.text.clone()))?;
+                let arity = payload_ty.cons_arity();
+                if !pats.is_empty() && pats.len() != arity {
+                    let name = label.text[1..].to_owned();
+                    return Err(TCE::ConsArity(label.loc, name, arity, pats.len()));
+                }
+            }
+        }
+        let mut split = CaseSplit::new();
+        for (label, payload_ty) in variants {
+            let payload_ty = explicit_payload(payload_ty);
+            let uids = (0..payload_ty.cons_arity())
+                .map(|_| unsafe { next_uid() })
+                .collect::<Vec<_>>();
+            let args = uids
+                .iter()
+                .map(|uid| Val::postulate(*uid))
+                .collect::<Vec<_>>();
+            let arg_types = cons_arg_types(payload_ty, &args);
+            let cons = Val::cons(label.clone(), args.clone());
+            let rows: Vec<_> = (rows.iter().cloned())
+                .filter_map(|mut row| {
+                    let position = row.equations.iter().position(|eq| eq.term == scrutinee);
+                    if let Some(i) = position {
+                        match row.equations[i].pat {
+                            AbsPat::Cons(cons, pats) if cons.text[1..] == label[..] => {
+                                let arg_equations = (pats.iter().zip(&args).zip(&arg_types)).map(
+                                    |((pat, term), ty)| Equation {
+                                        pat,
+                                        term: term.clone(),
+                                        ty: ty.clone(),
+                                    },
+                                );
+                                row.equations.splice(i..=i, arg_equations);
+                            }
+                            _ => return None,
+                        }
+                    }
+                    // The scrutinee is known to be a constructor in this branch.
+                    for equation in &mut row.equations {
+                        equation.ty = refine(equation.ty.clone(), &scrutinee, &cons);
+                    }
+                    for (value, ty) in row.bindings.values_mut() {
+                        *value = refine(value.clone(), &scrutinee, &cons);
+                        *ty = refine(ty.clone(), &scrutinee, &cons);
+                    }
+                    Some(row)
+                })
+                .collect();
+            let typed_args = args.into_iter().zip(arg_types).collect();
+            self.refined
+                .push((scrutinee.clone(), label.clone(), typed_args));
+            let compiled = if rows.is_empty() {
+                let counterexample = self.counterexample(&tcs);
+                self.missing.push(counterexample);
+                // The result is not used, since this is an error.
+                Ok((Val::fresh_axiom(), tcs))
+            } else {
+                self.compile(tcs, rows, refine(ty.clone(), &scrutinee, &cons))
+            };
+            self.refined.pop();
+            let (body, new_tcs) = compiled?;
+            tcs = new_tcs;
+            split.insert(label, branch(body, &uids));
+        }
+        Ok((Val::case_tree(split).apply(scrutinee), tcs))
+    }
+
+    /// All patterns of the first row are matched, check its body
+    /// with the pattern variables defined.
+    fn leaf(&mut self, mut tcs: TCS, row: &Row<'a>, ty: TVal) -> TCM<(Val, TCS)> {
+        self.reached.insert(row.clause);
+        let clause = &self.clauses[row.clause];
+        let vars = clause.vars();
+        for (name, uid) in &vars {
+            let (value, var_ty) = row.bindings[uid].clone();
+            tcs.local_gamma.push(var_ty.into_info(name.loc));
+            tcs.push_local_def(value.into_info(name.loc));
+        }
+        let (body, mut tcs) = (tcs.check(&clause.body, &ty)).map_err(|e| e.wrap(clause.loc))?;
+        for _ in &vars {
+            tcs.local_gamma.pop();
+            tcs.pop_local_def();
+        }
+        Ok((body.ast, tcs))
+    }
+}
+
+impl<'a> Clauses<'a> {
+    /// The patterns of the parameters matching the current branch,
+    /// the parameters not introduced yet are wildcards.
+    fn counterexample(&self, tcs: &TCS) -> String {
+        let arity = self.clauses[0].patterns.len();
+        let introduced = (self.params.iter()).map(|(term, ty)| self.render(tcs, term, ty, true));
+        let wildcards = (self.params.len()..arity).map(|_| "_".to_owned());
+        introduced.chain(wildcards).collect::<Vec<_>>().join(" ")
+    }
+
+    /// The pattern of `term` in the current branch, parenthesized if `atom`.
+    fn render(&self, tcs: &TCS, term: &Val, ty: &TVal, atom: bool) -> String {
+        let refined = self.refined.iter().rev().find(|(on, ..)| on == term);
+        if let Some((_, label, args)) = refined {
+            let args =
+                (args.iter()).map(|(arg, ty)| format!(" {}", self.render(tcs, arg, ty, true)));
+            let pattern = format!("@{}{}", label, args.collect::<String>());
+            return if atom {
+                format!("({})", pattern)
+            } else {
+                pattern
+            };
+        }
+        match unfold(tcs, ty.clone()) {
+            Val::Dt(Sigma, _, _, fst_ty, closure) => {
+                let fst = term.clone().first();
+                let snd_ty = closure.instantiate(fst.clone());
+                let fst = self.render(tcs, &fst, &fst_ty, false);
+                let snd = self.render(tcs, &term.clone().second(), &snd_ty, false);
+                if fst == "_" && snd == "_" {
+                    "_".to_owned()
+                } else {
+                    format!("({}, {})", fst, snd)
+                }
+            }
+            Val::RowPoly(Record, field_types) | Val::Neut(Neutral::Row(Record, field_types, _)) => {
+                let fields = (field_types.into_iter())
+                    .map(|(label, ty)| {
+                        let field = term.clone().project(label.clone());
+                        (label, self.render(tcs, &field, &ty, false))
+                    })
+                    .filter(|(_, pattern)| pattern != "_")
+                    .map(|(label, pattern)| format!("{} = {}; ", label, pattern))
+                    .collect::<String>();
+                if fields.is_empty() {
+                    "_".to_owned()
+                } else {
+                    format!("{{| {}|}}", fields)
+                }
+            }
+            _ => "_".to_owned(),
+        }
+    }
+}
+
+/// Match the patterns that do not split: variables, wildcards, records and pairs.
+fn simplify<'a>(tcs: &TCS, row: Row<'a>) -> TCM<Row<'a>> {
+    let mut queue: VecDeque<_> = row.equations.into();
+    let mut equations = Vec::with_capacity(queue.len());
+    let mut bindings = row.bindings;
+    while let Some(Equation { pat, term, ty }) = queue.pop_front() {
+        match pat {
+            AbsPat::Wildcard(..) => {}
+            AbsPat::Var(_, uid) => {
+                bindings.insert(*uid, (term, ty));
+            }
+            AbsPat::Cons(..) => equations.push(Equation { pat, term, ty }),
+            AbsPat::Pair(info, fst, snd) => match unfold(tcs, ty) {
+                Val::Dt(Sigma, _, _, fst_ty, closure) => {
+                    let fst_term = term.clone().first();
+                    let snd_ty = closure.instantiate(fst_term.clone());
+                    let (snd_term, fst_ty) = (term.second(), *fst_ty);
+                    queue.push_front(Equation {
+                        pat: snd,
+                        term: snd_term,
+                        ty: snd_ty,
+                    });
+                    queue.push_front(Equation {
+                        pat: fst,
+                        term: fst_term,
+                        ty: fst_ty,
+                    });
+                }
+                ty => return Err(TCE::NotSigma(*info, ty)),
+            },
+            AbsPat::Rec(info, fields) => {
+                let field_types = match unfold(tcs, ty) {
+                    Val::RowPoly(Record, field_types) => field_types,
+                    Val::Neut(Neutral::Row(Record, field_types, _)) => field_types,
+                    ty => return Err(TCE::NotRowType(Record, *info, ty)),
+                };
+                for field in fields.iter().rev() {
+                    let label = &field.label.text;
+                    let ty = (field_types.get(label).cloned())
+                        .ok_or_else(|| TCE::MissingVariant(Record, label.clone()))?;
+                    let term = term.clone().project(label.clone());
+                    let pat = &field.expr;
+                    queue.push_front(Equation { pat, term, ty });
+                }
+            }
+        }
+    }
+    Ok(Row {
+        equations,
+        bindings,
+        ..row
+    })
+}
+
+/// Replace the scrutinee with the constructor of the current branch,
+/// so the types depending on the scrutinee can reduce.
+/// Only the parameters and payloads can be replaced inside other terms.
+fn refine(val: Val, scrutinee: &Val, cons: &Val) -> Val {
+    match scrutinee {
+        _ if val == *scrutinee => cons.clone(),
+        Val::Neut(Neutral::Axi(axiom)) => {
+            Closure::plain(val.abstract_axiom(axiom.unique_id())).instantiate(cons.clone())
+        }
+        _ => val,
+    }
+}
+
+fn unfold(tcs: &TCS, ty: TVal) -> TVal {
+    tcs.unfold_global(&ty).unwrap_or(ty)
+}
+
+/// The case branch of a constructor, whose arguments are the axioms `uids` in `body`.
+/// It binds the payload, see [`Val::cons_payload`](Val::cons_payload).
+fn branch(body: Val, uids: &[UID]) -> Closure {
+    let (body, uid) = match uids {
+        [uid] => (body, *uid),
+        _ => {
+            let uid = unsafe { next_uid() };
+            let args = Val::postulate(uid).cons_args(uids.len());
+            let body = (uids.iter().zip(args)).fold(body, |body, (arg_uid, arg)| {
+                Closure::plain(body.abstract_axiom(*arg_uid)).instantiate(arg)
+            });
+            (body, uid)
+        }
+    };
+    Closure::plain(body.abstract_axiom(uid))
+}
+
+/// `\x. split x { .. }` is just the case tree, if `x` is used nowhere else.
+fn lambda(body: Val, uid: UID) -> Val {
+    if let Val::Neut(Neutral::SplitOn(split, on)) = &body {
+        let tree = Val::case_tree(split.clone());
+        let on_param = **on == Neutral::Axi(Axiom::Postulated(uid));
+        if on_param && tree.clone().abstract_axiom(uid) == tree {
+            return tree;
+        }
+    }
+    Val::closure_lam(body.abstract_axiom(uid))
+}
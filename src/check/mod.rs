@@ -23,6 +23,13 @@ $$
 */
 mod unify;
 
+/**
+Coercions from subtypes to supertypes, inserted by elaboration.
+$$
+\Gamma \vdash A <: B \Rightarrow c
+$$
+*/
+mod coerce;
 /**
 Declaration relevant checking.
 $$
@@ -56,3 +63,35 @@ $$
 $$
 */
 mod expr;
+/**
+Pattern-matching functions, compiled into case trees.
+$$
+\overline{p} = e \Rightarrow \texttt{case}\ x\ \{ \overline{C\ y \Rightarrow t} \}
+$$
+
+Depends on `expr`.
+*/
+mod matching;
+/**
+Strict positivity checking of recursive types.
+$$
+\texttt{data}\ D\ \\{ C : (D \to A) \to D \\}\ \textbf{rejected}
+$$
+*/
+mod positivity;
+/**
+Size-change termination checking of recursive definitions.
+$$
+f \xrightarrow{M} g
+$$
+*/
+mod termination;
+/**
+Universe variables of the `Type`s without levels, solved by the level constraints.
+$$
+\Gamma \vdash \texttt{Type} : \texttt{Type}_{?u + 1}
+\quad
+?u \le \ ?v
+$$
+*/
+mod universe;
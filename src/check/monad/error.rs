@@ -9,6 +9,8 @@ use voile_util::uid::DBI;
 use crate::syntax::abs::Abs;
 use crate::syntax::core::{TVal, Val};
 
+use super::{Constraint, TCM};
+
 /// Type-Checking Error.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TCE {
@@ -30,6 +32,8 @@ pub enum TCE {
     NotEmpty(Loc, Val),
     NotRecVal(Loc, Val),
     NotUniverseVal(Loc, Val),
+    /// Expected a universe level.
+    NotLevel(Val),
 
     // == Elaboration ==
     TypeNotInGamma(Loc),
@@ -37,13 +41,33 @@ pub enum TCE {
     DuplicateField(Loc, String),
     UnexpectedVariant(Loc, String),
     MissingVariant(VarRec, String),
+    /// The row (or row kind) is expected to lack the label,
+    /// so it can be extended by the label.
+    RowNotLacking(VarRec, Val, String),
+    /// The field (the first `String`) cannot be removed from a record type,
+    /// because the type of another field (the second `String`) refers to it.
+    DependedField(Loc, String, String),
+    /// The types of these fields refer to each other, so none of them
+    /// can be checked first. The `Loc` is of the first one.
+    CyclicFields(Loc, Vec<String>),
+    /// The constructor is given (or bound with) a different number of
+    /// explicit arguments, expected vs. actual.
+    ConsArity(Loc, String, usize, usize),
     /// Maximum `DBI` vs. Requested `DBI`
     DbiOverflow(DBI, DBI),
     /// Expected the first level to be smaller than second.
     /// The `String` represents the expression.
     LevelMismatch(Loc, Level, Level),
+    /// The universe level constraint `lhs <= rhs` cannot be satisfied,
+    /// with the universe variables at their least levels.
+    UniverseInconsistency(Val, Val),
+    /// The universe levels form a cycle like `?a < ?b <= ?a`,
+    /// so some `Type` would be in itself.
+    UniverseCycle,
     /// Cannot find the definition.
     LookUpFailed(Ident),
+    /// No implicit parameter with this name in the function type.
+    NoSuchImplicit(Ident, TVal),
     Wrapped(Box<Self>, Loc),
 
     // == Scoping ==
@@ -51,6 +75,24 @@ pub enum TCE {
     /// hide the definition at the second `Loc`.
     ReDefine(Loc, Loc),
 
+    // == Modules ==
+    /// No file in the search path corresponds to the imported module.
+    ModuleNotFound(Ident),
+    /// The module imports itself, directly or indirectly.
+    /// The `Vec` is the chain of modules being loaded.
+    CyclicImport(Ident, Vec<String>),
+    /// The `module` header disagrees with the name used to import the file.
+    ModuleNameMismatch(Ident, String),
+    /// The definition is a postulate of the named module,
+    /// which cannot be implemented by the modules importing it.
+    ImplementImported(Ident, String),
+    /// An error found when loading the named module.
+    InModule(Box<Self>, String),
+
+    // == Recovery ==
+    /// Errors found in different declarations, never empty.
+    Errors(Vec<Self>),
+
     // == "Meta"s ==
     /// Recursive metas are disallowed.
     MetaRecursion(MI),
@@ -58,6 +100,29 @@ pub enum TCE {
     MetaWithNonVar(Loc),
     /// Unsolved metas are reported as errors.
     MetaUnsolved(MI),
+    /// Meta solution mentions a local variable the meta cannot see.
+    MetaOutOfScope(MI, Val),
+    /// Postponed constraints still blocked at the end of a declaration.
+    StuckConstraints(Vec<Constraint>),
+
+    // == Termination ==
+    /// The definition at `Loc` may not terminate,
+    /// the `Vec` is the offending call path.
+    NonTerminating(Loc, Vec<String>),
+    /// The recursive type defined at `Loc` refers to itself on the left of an arrow,
+    /// so it's not an inductive type.
+    NegativeOccurrence(Loc, String),
+
+    // == Patterns ==
+    /// The clause at `Loc` is never used, because of the clauses above it.
+    UnreachableClause(Loc),
+    /// The clause at `Loc` has a different number of patterns,
+    /// expected vs. actual.
+    ArityMismatch(Loc, usize, usize),
+    /// The pattern variable is bound more than once in a clause.
+    NonLinearPattern(Ident),
+    /// The cases at `Loc` are not covered, shown as patterns.
+    NonExhaustive(Loc, Vec<String>),
 }
 
 impl TCE {
@@ -68,6 +133,134 @@ impl TCE {
     pub fn duplicate_field(ident: Ident) -> Self {
         TCE::DuplicateField(ident.loc, ident.text)
     }
+
+    /// `Ok` if there's no error, otherwise all errors combined.
+    pub fn from_errors(mut errors: Vec<Self>) -> TCM<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(TCE::Errors(errors)),
+        }
+    }
+
+    /// The errors combined by [`TCE::Errors`], flattened.
+    pub fn into_errors(self) -> Vec<Self> {
+        match self {
+            TCE::Errors(errors) => errors.into_iter().flat_map(Self::into_errors).collect(),
+            err => vec![err],
+        }
+    }
+
+    /// Stable error code, used for looking up the documentation of errors.
+    /// Codes are never reused, new variants get new codes.
+    pub fn code(&self) -> &'static str {
+        use TCE::*;
+        match self {
+            Textual(..) => "E0001",
+            CannotInfer(..) => "E0002",
+            CannotUnify(..) => "E0003",
+            NotSigma(..) => "E0004",
+            NotPi(..) => "E0005",
+            NotSubtype(..) => "E0006",
+            NotTypeAbs(..) => "E0007",
+            NotTypeVal(..) => "E0008",
+            NotRowType(..) => "E0009",
+            NotEmpty(..) => "E0010",
+            NotRecVal(..) => "E0011",
+            NotUniverseVal(..) => "E0012",
+            TypeNotInGamma(..) => "E0013",
+            OverlappingVariant(..) => "E0014",
+            DuplicateField(..) => "E0015",
+            UnexpectedVariant(..) => "E0016",
+            MissingVariant(..) => "E0017",
+            DbiOverflow(..) => "E0018",
+            LevelMismatch(..) => "E0019",
+            LookUpFailed(..) => "E0020",
+            ReDefine(..) => "E0021",
+            ModuleNotFound(..) => "E0022",
+            CyclicImport(..) => "E0023",
+            ModuleNameMismatch(..) => "E0024",
+            MetaRecursion(..) => "E0025",
+            MetaWithNonVar(..) => "E0026",
+            MetaUnsolved(..) => "E0027",
+            NonTerminating(..) => "E0028",
+            NoSuchImplicit(..) => "E0029",
+            MetaOutOfScope(..) => "E0030",
+            StuckConstraints(..) => "E0031",
+            UnreachableClause(..) => "E0032",
+            ArityMismatch(..) => "E0033",
+            NonLinearPattern(..) => "E0034",
+            NonExhaustive(..) => "E0035",
+            // E0036 is retired, unreachable branches are warnings now.
+            RowNotLacking(..) => "E0037",
+            DependedField(..) => "E0038",
+            ConsArity(..) => "E0039",
+            UniverseInconsistency(..) => "E0040",
+            NotLevel(..) => "E0041",
+            ImplementImported(..) => "E0042",
+            UniverseCycle => "E0043",
+            CyclicFields(..) => "E0044",
+            NegativeOccurrence(..) => "E0045",
+            Wrapped(inner, ..) | InModule(inner, ..) => inner.code(),
+            Errors(errors) => errors[0].code(),
+        }
+    }
+
+    /// The most precise location of this error in the current file, if any.
+    /// Errors found in imported modules (see [`TCE::InModule`]) are located
+    /// in other files, so they have no location here.
+    pub fn loc(&self) -> Option<Loc> {
+        use TCE::*;
+        match self {
+            CannotInfer(loc, ..)
+            | NotSigma(loc, ..)
+            | NotPi(loc, ..)
+            | NotTypeAbs(loc, ..)
+            | NotTypeVal(loc, ..)
+            | NotRowType(_, loc, ..)
+            | NotEmpty(loc, ..)
+            | NotRecVal(loc, ..)
+            | NotUniverseVal(loc, ..)
+            | TypeNotInGamma(loc)
+            | OverlappingVariant(loc, ..)
+            | DuplicateField(loc, ..)
+            | UnexpectedVariant(loc, ..)
+            | DependedField(loc, ..)
+            | CyclicFields(loc, ..)
+            | ConsArity(loc, ..)
+            | LevelMismatch(loc, ..)
+            | ReDefine(loc, ..)
+            | MetaWithNonVar(loc)
+            | UnreachableClause(loc)
+            | ArityMismatch(loc, ..)
+            | NonExhaustive(loc, ..)
+            | NonTerminating(loc, ..)
+            | NegativeOccurrence(loc, ..) => Some(*loc),
+            LookUpFailed(ident)
+            | NonLinearPattern(ident)
+            | NoSuchImplicit(ident, ..)
+            | ModuleNotFound(ident)
+            | CyclicImport(ident, ..)
+            | ImplementImported(ident, ..) => Some(ident.loc),
+            Wrapped(inner, loc) => inner.loc().or(Some(*loc)),
+            Textual(..)
+            | CannotUnify(..)
+            | NotSubtype(..)
+            | NotLevel(..)
+            | MissingVariant(..)
+            | RowNotLacking(..)
+            | UniverseInconsistency(..)
+            | UniverseCycle
+            | DbiOverflow(..)
+            | ModuleNameMismatch(..)
+            | InModule(..)
+            | Errors(..)
+            | MetaRecursion(..)
+            | MetaOutOfScope(..)
+            | StuckConstraints(..)
+            | MetaUnsolved(..) => None,
+        }
+    }
 }
 
 impl Display for TCE {
@@ -118,6 +311,16 @@ impl Display for TCE {
                 write!(f, "Missing variant `{}`.", variant)
             }
             TCE::MissingVariant(VarRec::Record, field) => write!(f, "Missing field `{}`.", field),
+            TCE::RowNotLacking(VarRec::Variant, row, variant) => write!(
+                f,
+                "Expected a row lacking variant `{}`, got: `{}`.",
+                variant, row
+            ),
+            TCE::RowNotLacking(VarRec::Record, row, field) => write!(
+                f,
+                "Expected a row lacking field `{}`, got: `{}`.",
+                field, row
+            ),
             TCE::OverlappingVariant(id, variant) => {
                 write!(f, "Duplicated variant: `{}` at {}.", variant, id)
             }
@@ -127,11 +330,28 @@ impl Display for TCE {
             TCE::UnexpectedVariant(id, variant) => {
                 write!(f, "Unexpected variant: `{}` at {}.", variant, id)
             }
+            TCE::DependedField(id, field, dependent) => write!(
+                f,
+                "Cannot remove field `{}`, the type of field `{}` depends on it, at {}.",
+                field, dependent, id
+            ),
+            TCE::CyclicFields(id, fields) => write!(
+                f,
+                "The types of fields `{}` refer to each other, at {}.",
+                fields.join("`, `"),
+                id
+            ),
+            TCE::ConsArity(id, label, expected, actual) => write!(
+                f,
+                "Constructor `@{}` takes {} argument(s), got {} at {}.",
+                label, expected, actual, id
+            ),
             TCE::NotUniverseVal(id, val) => write!(
                 f,
                 "Expected an universe expression, got: `{}` at {}.",
                 val, id
             ),
+            TCE::NotLevel(val) => write!(f, "Expected a universe level, got: `{}`.", val),
             TCE::DbiOverflow(expected, actual) => write!(
                 f,
                 "DBI overflow, maximum: `{}`, got: `{}`.",
@@ -143,6 +363,14 @@ impl Display for TCE {
                 "Expression `{}` has level {}, which is not smaller than {}.",
                 expr, expected_to_be_small, big
             ),
+            TCE::UniverseInconsistency(lhs, rhs) => write!(
+                f,
+                "Universe inconsistency: level `{}` cannot be at most `{}`.",
+                lhs, rhs
+            ),
+            TCE::UniverseCycle => f.write_str(
+                "Universe inconsistency: some `Type` would have to be bigger than itself.",
+            ),
             TCE::Wrapped(inner, info) => {
                 write!(f, "{}\nWhen checking the expression at: {}.", inner, info)
             }
@@ -151,6 +379,36 @@ impl Display for TCE {
                 "The definition at {} will hide the definition at {}.",
                 new, old
             ),
+            TCE::ModuleNotFound(name) => write!(
+                f,
+                "Cannot find module `{}` (imported at {}).",
+                name.text, name.loc
+            ),
+            TCE::CyclicImport(name, chain) => write!(
+                f,
+                "Cyclic import of module `{}` at {}, import chain: {}.",
+                name.text,
+                name.loc,
+                chain.join(" -> ")
+            ),
+            TCE::ModuleNameMismatch(header, expected) => write!(
+                f,
+                "Module declared as `{}` at {}, but imported as `{}`.",
+                header.text, header.loc, expected
+            ),
+            TCE::ImplementImported(name, module) => write!(
+                f,
+                "Cannot implement `{}` at {}, it's postulated in module `{}`.",
+                name.text, name.loc, module
+            ),
+            TCE::InModule(inner, name) => write!(f, "{}\nIn module `{}`.", inner, name),
+            TCE::Errors(errors) => {
+                let mut errors = errors.iter();
+                if let Some(first) = errors.next() {
+                    write!(f, "{}", first)?;
+                }
+                errors.try_for_each(|err| write!(f, "\n\n{}", err))
+            }
             TCE::MetaRecursion(mi) => write!(
                 f,
                 "Failed to solve meta {:?}: \
@@ -158,6 +416,49 @@ impl Display for TCE {
                 mi
             ),
             TCE::MetaUnsolved(mi) => write!(f, "Failed to solve meta {:?}: No solution found.", mi),
+            TCE::MetaOutOfScope(mi, val) => write!(
+                f,
+                "Failed to solve meta ?{}: \
+                 anticipated solution contains `{}`, which is out of scope.",
+                mi, val
+            ),
+            TCE::StuckConstraints(constraints) => {
+                write!(f, "Cannot solve constraints blocked on unsolved metas:")?;
+                (constraints.iter()).try_for_each(|constraint| write!(f, "\n  {}", constraint))
+            }
+            TCE::NoSuchImplicit(name, ty) => write!(
+                f,
+                "No implicit parameter named `{}` in `{}` at {}.",
+                name.text, ty, name.loc
+            ),
+            TCE::UnreachableClause(info) => write!(f, "Unreachable clause at {}.", info),
+            TCE::ArityMismatch(info, expected, actual) => write!(
+                f,
+                "Expected {} pattern(s), got {} at {}.",
+                expected, actual, info
+            ),
+            TCE::NonLinearPattern(var) => write!(
+                f,
+                "Pattern variable `{}` is bound more than once at {}.",
+                var.text, var.loc
+            ),
+            TCE::NonExhaustive(info, missing) => write!(
+                f,
+                "Missing case(s) at {}: {}.",
+                info,
+                missing_cases(missing)
+            ),
+            TCE::NonTerminating(info, path) => write!(
+                f,
+                "The definition at {} may not terminate, call path: {}.",
+                info,
+                path.join(" -> ")
+            ),
+            TCE::NegativeOccurrence(info, name) => write!(
+                f,
+                "The type `{}` defined at {} occurs negatively in its definition.",
+                name, info
+            ),
             TCE::MetaWithNonVar(info) => write!(
                 f,
                 "Failed to solve meta at {}: \
@@ -167,3 +468,9 @@ impl Display for TCE {
         }
     }
 }
+
+/// Quoted and separated by commas, like "`@A _`, `@B _`".
+pub fn missing_cases(missing: &[String]) -> String {
+    let quoted = missing.iter().map(|case| format!("`{}`", case));
+    quoted.collect::<Vec<_>>().join(", ")
+}
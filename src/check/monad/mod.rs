@@ -2,11 +2,14 @@ use crate::syntax::core::ValInfo;
 
 pub use self::error::*;
 pub use self::state::*;
+pub use self::warning::*;
 
 /// `Control.Monad.Except`, as type-checking error.
 mod error;
 /// `Control.Monad.State`, as type-checking state.
 mod state;
+/// `Control.Monad.Writer`, as type-checking warnings.
+mod warning;
 
 /// Type-Checking Monad.
 pub type TCM<T = TCS> = Result<T, TCE>;
@@ -1,37 +1,308 @@
-use voile_util::meta::MetaContext;
-use voile_util::uid::{DBI, GI};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Error, Formatter};
+use std::mem::take;
+use std::rc::Rc;
 
-use crate::syntax::core::{Val, ValInfo};
+use voile_util::axiom::Axiom;
+use voile_util::loc::Ident;
+use voile_util::meta::{MetaContext, MetaSolution, MI};
+use voile_util::tags::VarRec;
+use voile_util::uid::{DBI, GI, UID};
+
+use crate::syntax::core::{Neutral, Val, ValInfo};
+
+use super::TCW;
 
 /// Typing context.
 pub type Gamma = Vec<ValInfo>;
 
+/// A unification constraint `lhs = rhs` that is blocked on unsolved metas.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Constraint {
+    pub lhs: Val,
+    pub rhs: Val,
+    /// The unsolved metas mentioned in this constraint, never empty.
+    pub blockers: BTreeSet<MI>,
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "`{}` = `{}`", self.lhs, self.rhs)
+    }
+}
+
+/// Meta variable solutions. The ones of the checked declarations are shared
+/// between the copies of the state, the others are kept aside until committed.
+#[derive(Debug, Clone, Default)]
+pub struct Metas {
+    committed: Rc<MetaContext<Val>>,
+    /// Solutions of the committed metas, found after the last commit.
+    solved: BTreeMap<MI, MetaSolution<Val>>,
+    /// Metas created after the last commit.
+    fresh: Vec<MetaSolution<Val>>,
+}
+
+impl Metas {
+    /// The number of metas, which is also the index of the next fresh meta.
+    pub fn meta_count(&self) -> MI {
+        MI(self.committed.solutions().len() + self.fresh.len())
+    }
+
+    pub fn solution(&self, index: MI) -> &MetaSolution<Val> {
+        let committed = self.committed.solutions();
+        match index.0.checked_sub(committed.len()) {
+            Some(i) => &self.fresh[i],
+            None => (self.solved.get(&index)).unwrap_or(&committed[index.0]),
+        }
+    }
+
+    /// Submit a solution to a meta variable.
+    pub fn solve_meta(&mut self, index: MI, solution: Val) {
+        debug_assert_eq!(self.solution(index), &MetaSolution::Unsolved);
+        let solution = MetaSolution::solved(solution);
+        match index.0.checked_sub(self.committed.solutions().len()) {
+            Some(i) => self.fresh[i] = solution,
+            None => {
+                self.solved.insert(index, solution);
+            }
+        }
+    }
+
+    /// Add many unsolved metas.
+    pub fn expand_with_fresh_meta(&mut self, meta_count: MI) {
+        debug_assert!(self.meta_count() <= meta_count);
+        let fresh = meta_count.0 - self.committed.solutions().len();
+        self.fresh.resize_with(fresh, Default::default);
+    }
+
+    pub fn fresh_meta(&mut self, new_meta: impl FnOnce(MI) -> Val) -> Val {
+        let meta = new_meta(self.meta_count());
+        self.fresh.push(MetaSolution::Unsolved);
+        meta
+    }
+
+    /// Share the metas and solutions with the copies made after this.
+    /// Nothing is copied unless the committed ones are still shared.
+    pub fn commit(&mut self) {
+        let committed = Rc::make_mut(&mut self.committed).mut_solutions();
+        for (index, solution) in take(&mut self.solved) {
+            committed[index.0] = solution;
+        }
+        committed.append(&mut self.fresh);
+    }
+
+    /// Forget the solutions found after the last commit.
+    /// The metas created after it are kept unsolved.
+    pub fn rollback(&mut self) {
+        self.solved.clear();
+        for solution in &mut self.fresh {
+            *solution = MetaSolution::Unsolved;
+        }
+    }
+}
+
+impl Display for Metas {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        for index in 0..self.meta_count().0 {
+            match self.solution(MI(index)) {
+                MetaSolution::Solved(solution) => writeln!(f, "{}: {}", index, solution),
+                MetaSolution::Unsolved => writeln!(f, "{}: ???", index),
+                MetaSolution::Inlined => writeln!(f, "<inlined out>"),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+/// Universe variables, the levels of the `Type`s written without levels,
+/// and the constraints `lhs <= rhs` between the levels mentioning them.
+/// Shared and committed like the [`Metas`](Metas).
+#[derive(Debug, Clone, Default)]
+pub struct Universes {
+    committed: Rc<(BTreeSet<MI>, Vec<(Val, Val)>)>,
+    vars: BTreeSet<MI>,
+    constraints: Vec<(Val, Val)>,
+}
+
+impl Universes {
+    pub fn is_var(&self, mi: MI) -> bool {
+        self.committed.0.contains(&mi) || self.vars.contains(&mi)
+    }
+
+    pub fn insert_var(&mut self, mi: MI) {
+        self.vars.insert(mi);
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = &MI> {
+        self.committed.0.iter().chain(&self.vars)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.committed.0.is_empty() && self.vars.is_empty()
+    }
+
+    pub fn constrain(&mut self, sub: Val, sup: Val) {
+        self.constraints.push((sub, sup));
+    }
+
+    pub fn constraints(&self) -> impl Iterator<Item = &(Val, Val)> {
+        self.committed.1.iter().chain(&self.constraints)
+    }
+
+    pub fn commit(&mut self) {
+        let (vars, constraints) = Rc::make_mut(&mut self.committed);
+        vars.append(&mut self.vars);
+        constraints.append(&mut self.constraints);
+    }
+
+    pub fn rollback(&mut self) {
+        self.vars.clear();
+        self.constraints.clear();
+    }
+}
+
 /// Type-checking state.
+///
+/// The global parts are shared between the copies of the state,
+/// which are cheap to make while checking a declaration.
 #[derive(Debug, Clone, Default)]
 pub struct TCS {
     /// Global value context.
-    pub env: Gamma,
+    pub env: Rc<Gamma>,
     /// Local value context.
     pub local_env: Gamma,
     /// Global typing context.
-    pub gamma: Gamma,
+    pub gamma: Rc<Gamma>,
     /// Local typing context.
     pub local_gamma: Gamma,
+    /// Positions of the local definitions in `local_env`.
+    /// They're unfolded instead of being bound in core terms.
+    pub local_defs: Vec<usize>,
     /// Meta variable context. Always global.
-    pub meta_context: MetaContext<Val>,
+    pub meta_context: Metas,
+    /// Local variables in scope when the metas are created,
+    /// solutions mentioning other local variables are rejected.
+    pub meta_scopes: BTreeMap<MI, Vec<UID>>,
+    /// Unification constraints blocked on unsolved metas,
+    /// woken up when one of these metas is solved.
+    pub postponed: Vec<Constraint>,
+    /// Labels the row metas should lack, from their row kinds.
+    /// Checked when these metas are solved.
+    pub lacks: BTreeMap<MI, (VarRec, Vec<String>)>,
+    /// Row metas for the unknown rest of inferred rows, like the other variants
+    /// of a constructor application. Closed with the empty row if still unsolved
+    /// at the end of a declaration.
+    pub open_rows: BTreeMap<MI, VarRec>,
+    /// Universe variables, solved when all declarations are checked,
+    /// so the later declarations can still raise them.
+    pub universes: Universes,
+    /// Names of global definitions, collected from references to them.
+    /// Only used in error messages.
+    pub names: Rc<BTreeMap<GI, String>>,
+    /// Global definitions referred to in the implementations of global definitions,
+    /// before any unfolding. Only used in error messages.
+    pub refs: Rc<BTreeMap<GI, BTreeSet<GI>>>,
+    /// Problems that do not stop the checking, like unreachable branches.
+    pub warnings: Vec<TCW>,
+    /// Pairs of different global types (with their arguments) being compared
+    /// by unfolding them, assumed to be related when they're compared again
+    /// while unfolding (coinduction). The flag tells if the assumption is used.
+    pub unfolding: Vec<(Val, Val, bool)>,
 }
 
 impl TCS {
+    /// Share the metas and universes of the checked declaration with
+    /// the copies made after this. The scopes of the solved metas are dropped.
+    pub fn commit(&mut self) {
+        self.meta_context.commit();
+        self.universes.commit();
+        let metas = &self.meta_context;
+        (self.meta_scopes).retain(|mi, _| matches!(metas.solution(*mi), MetaSolution::Unsolved));
+    }
+
+    /// Forget the meta solutions and universes of the declaration failed to check.
+    pub fn rollback(&mut self) {
+        self.meta_context.rollback();
+        self.universes.rollback();
+        self.meta_scopes.clear();
+        self.warnings.clear();
+    }
+
     /// Create a new valid but unsolved meta variable,
     /// used for generating fresh metas during elaboration.
     pub fn fresh_meta(&mut self) -> Val {
-        self.meta_context.fresh_meta(Val::meta)
+        let meta = self.meta_context.fresh_meta(Val::meta);
+        if let Val::Neut(Neutral::Meta(mi)) = meta {
+            self.record_meta_scope(mi);
+        }
+        meta
+    }
+
+    /// Like `fresh_meta`, but constrained by its type `ty`.
+    pub fn fresh_meta_of(&mut self, ty: &Val) -> Val {
+        let meta = self.fresh_meta();
+        if let Val::Neut(Neutral::Meta(mi)) = meta {
+            self.constrain_meta(mi, ty);
+        }
+        meta
+    }
+
+    /// A fresh meta for the rest of an inferred row, lacking `labels`.
+    pub fn fresh_row_meta(&mut self, kind: VarRec, labels: &[String]) -> Neutral {
+        match self.fresh_meta() {
+            Val::Neut(Neutral::Meta(mi)) => {
+                self.open_rows.insert(mi, kind);
+                self.constrain_lacks(mi, kind, labels);
+                Neutral::Meta(mi)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// If the meta `mi` is a row, it should lack the labels in its row kind `ty`.
+    pub fn constrain_meta(&mut self, mi: MI, ty: &Val) {
+        if let Val::RowKind(_, kind, labels) = ty {
+            self.constrain_lacks(mi, *kind, labels);
+        }
+    }
+
+    /// Remember that the row meta `mi` should lack `labels`.
+    pub fn constrain_lacks(&mut self, mi: MI, kind: VarRec, labels: &[String]) {
+        if labels.is_empty() {
+            return;
+        }
+        let (_, lacked) = (self.lacks.entry(mi)).or_insert_with(|| (kind, vec![]));
+        for label in labels {
+            if !lacked.contains(label) {
+                lacked.push(label.clone());
+            }
+        }
+    }
+
+    /// Remember the local variables the meta `mi` can see.
+    pub fn record_meta_scope(&mut self, mi: MI) {
+        let locals = (self.local_env.iter()).filter_map(|local| match &local.ast {
+            Val::Neut(Neutral::Axi(axiom)) => Some(axiom.unique_id()),
+            _ => None,
+        });
+        let scope = locals.collect();
+        self.meta_scopes.entry(mi).or_insert(scope);
     }
 
     pub fn local_type(&self, dbi: DBI) -> &ValInfo {
         &self.local_gamma[self.local_gamma.len() - dbi.0 - 1]
     }
 
+    /// The type of the local variable `uid` is mocked by, if any.
+    pub fn axiom_type(&self, uid: UID) -> Option<&ValInfo> {
+        let is_mocked = |local: &ValInfo| match &local.ast {
+            Val::Neut(Neutral::Axi(axiom)) => axiom.unique_id() == uid,
+            _ => false,
+        };
+        let position = self.local_env.iter().rposition(is_mocked)?;
+        self.local_gamma.get(position)
+    }
+
     pub fn glob_type(&self, index: GI) -> &ValInfo {
         &self.gamma[index.0]
     }
@@ -44,6 +315,11 @@ impl TCS {
         &self.env[index.0]
     }
 
+    /// The name of a global definition, or its index if it's unknown.
+    pub fn glob_name(&self, index: GI) -> String {
+        (self.names.get(&index).cloned()).unwrap_or_else(|| format!("[|{}|]", index))
+    }
+
     pub fn local_is_type(&self, dbi: DBI) -> bool {
         self.local_val(dbi).ast.is_type() || self.local_type(dbi).ast.is_universe()
     }
@@ -60,6 +336,92 @@ impl TCS {
         self.local_env.pop().expect("Unexpected empty local env");
     }
 
+    /// Bring a local definition into `local_env`, the type is pushed by the caller.
+    pub fn push_local_def(&mut self, value: ValInfo) {
+        self.local_defs.push(self.local_env.len());
+        self.local_env.push(value);
+    }
+
+    pub fn pop_local_def(&mut self) {
+        self.local_defs
+            .pop()
+            .expect("Unexpected empty local definitions");
+        self.local_env.pop().expect("Unexpected empty local env");
+    }
+
+    /// Bring a field of a record type into scope of the later field types,
+    /// as a local definition of the reference to the field.
+    /// The type is pushed by the caller.
+    pub fn push_field(&mut self, label: &Ident) {
+        let field = Val::field(label.text.clone());
+        self.push_local_def(field.into_info(label.loc));
+    }
+
+    /// Pop the fields pushed by [`push_field`](TCS::push_field) and their types,
+    /// then leave the scope of the fields.
+    pub fn pop_fields(&mut self, count: usize) {
+        for _ in 0..count {
+            self.local_gamma.pop();
+            self.pop_local_def();
+        }
+        self.leave_fields();
+    }
+
+    /// Enter the scope of the fields of a record type, before pushing them,
+    /// where the fields of the enclosing record types are one record type further.
+    pub fn enter_fields(&mut self) {
+        self.shift_local_fields(1);
+    }
+
+    /// Leave the scope entered by [`enter_fields`](TCS::enter_fields),
+    /// after popping the fields.
+    pub fn leave_fields(&mut self) {
+        self.shift_local_fields(-1);
+    }
+
+    fn shift_local_fields(&mut self, by: isize) {
+        let shift = |locals: Gamma| {
+            (locals.into_iter())
+                .map(|local| local.map_ast(|ast| ast.shift_fields(by)))
+                .collect()
+        };
+        self.local_env = shift(take(&mut self.local_env));
+        self.local_gamma = shift(take(&mut self.local_gamma));
+    }
+
+    /// The de Bruijn index of a local variable in core terms,
+    /// where the local definitions between are not counted.
+    pub fn core_dbi(&self, dbi: DBI) -> DBI {
+        let position = self.local_env.len() - dbi.0 - 1;
+        let defs = self.local_defs.iter().filter(|&&def| def > position);
+        DBI(dbi.0 - defs.count())
+    }
+
+    /// Turn the postulated axioms in the value (or type) of a local variable
+    /// into generated ones, which become variables in core terms.
+    pub fn attach_local_dbi(&self, val: Val, dbi: DBI) -> Val {
+        let position = self.local_env.len() - dbi.0 - 1;
+        if !self.local_defs.contains(&position) {
+            return val.attach_dbi(self.core_dbi(dbi));
+        }
+        // The axioms in a local definition belong to the parameters it mentions.
+        let len = self.local_env.len();
+        val.map_axiom(&mut |axiom| {
+            Neutral::Axi(match axiom {
+                Axiom::Postulated(uid) | Axiom::Generated(uid, _) => {
+                    let postulate = Val::postulate(uid);
+                    match (self.local_env.iter()).rposition(|local| local.ast == postulate) {
+                        Some(position) => {
+                            Axiom::Generated(uid, self.core_dbi(DBI(len - position - 1)))
+                        }
+                        None => axiom,
+                    }
+                }
+                axiom => axiom,
+            })
+        })
+    }
+
     pub fn glob_len(&self) -> usize {
         self.gamma.len()
     }
@@ -0,0 +1,40 @@
+use std::fmt::{Display, Error as FmtError, Formatter};
+
+use voile_util::loc::{Ident, Loc};
+
+/// Type-Checking Warning, a problem that does not stop the checking.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TCW {
+    /// The branch of this label is never used, because the label is
+    /// already covered by a previous branch.
+    UnreachableBranch(Ident),
+}
+
+impl TCW {
+    /// A stable identifier of the kind of warning, like `W0001`.
+    pub fn code(&self) -> &'static str {
+        use TCW::*;
+        match self {
+            UnreachableBranch(..) => "W0001",
+        }
+    }
+
+    pub fn loc(&self) -> Loc {
+        use TCW::*;
+        match self {
+            UnreachableBranch(ident) => ident.loc,
+        }
+    }
+}
+
+impl Display for TCW {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self {
+            TCW::UnreachableBranch(label) => write!(
+                f,
+                "Unreachable branch of `{}` at {}, it's already covered.",
+                label.text, label.loc
+            ),
+        }
+    }
+}
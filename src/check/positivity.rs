@@ -0,0 +1,84 @@
+use voile_util::tags::PiSig;
+use voile_util::uid::GI;
+
+use crate::syntax::core::{Closure, Neutral, Val};
+
+use super::eval::global_head;
+use super::monad::{TCE, TCM, TCS};
+
+/// Report recursive types referring to themselves not strictly positively,
+/// like `data Bad { C : Bad -> Sum {}; }`, which types non-terminating terms.
+pub fn check_positivity(tcs: &TCS, f: GI) -> TCM<()> {
+    if !is_type_family(&tcs.glob_type(f).ast) {
+        return Ok(());
+    }
+    let val = tcs.glob_val(f);
+    if positive(tcs, f, &val.ast, &mut vec![f]) {
+        Ok(())
+    } else {
+        Err(TCE::NegativeOccurrence(val.loc, tcs.glob_name(f)))
+    }
+}
+
+/// Types like `Type` and `Type -> Type`, the other definitions are not checked.
+fn is_type_family(ty: &Val) -> bool {
+    match ty {
+        Val::Dt(PiSig::Pi, _, _, _, Closure::Plain(ret)) => is_type_family(ret),
+        ty => ty.is_universe(),
+    }
+}
+
+/// `f` does not occur on the left of any arrow in `ty`.
+/// The other types `ty` refers to are unfolded to see where the arguments go,
+/// `unfolding` are the ones being unfolded, which are not unfolded again.
+fn positive(tcs: &TCS, f: GI, ty: &Val, unfolding: &mut Vec<GI>) -> bool {
+    use Val::*;
+    match ty {
+        Dt(PiSig::Pi, _, _, param, ret) => {
+            !occurs(f, param) && positive_closure(tcs, f, ret, unfolding)
+        }
+        Dt(_, _, _, param, ret) => {
+            positive(tcs, f, param, unfolding) && positive_closure(tcs, f, ret, unfolding)
+        }
+        Lam(body) => positive_closure(tcs, f, body, unfolding),
+        RowPoly(_, variants) => (variants.values()).all(|ty| positive(tcs, f, ty, unfolding)),
+        Neut(Neutral::Row(_, variants, ext)) => {
+            (variants.values()).all(|ty| positive(tcs, f, ty, unfolding))
+                && !occurs(f, &Neut(*ext.clone()))
+        }
+        Neut(Neutral::Ref(..)) => true,
+        Neut(Neutral::App(head, args)) => match global_head(ty) {
+            Some(g) if g == f => !args.iter().any(|arg| occurs(f, arg)),
+            Some(g) if unfolding.contains(&g) => true,
+            Some(g) => match tcs.unfold_global(ty) {
+                Some(unfolded) => {
+                    unfolding.push(g);
+                    let positive = positive(tcs, f, &unfolded, unfolding);
+                    unfolding.pop();
+                    positive
+                }
+                None => !args.iter().any(|arg| occurs(f, arg)),
+            },
+            None => !occurs(f, &Neut(*head.clone())) && !args.iter().any(|arg| occurs(f, arg)),
+        },
+        ty => !occurs(f, ty),
+    }
+}
+
+fn positive_closure(tcs: &TCS, f: GI, closure: &Closure, unfolding: &mut Vec<GI>) -> bool {
+    match closure {
+        Closure::Plain(body) => positive(tcs, f, body, unfolding),
+        Closure::Tree(split) => (split.values()).all(|c| positive_closure(tcs, f, c, unfolding)),
+    }
+}
+
+/// `f` is referred to in `ty`.
+fn occurs(f: GI, ty: &Val) -> bool {
+    let mut found = false;
+    ty.clone()
+        .map_atom_with_dbi(Default::default(), &mut |atom, _| {
+            found |= atom == Neutral::Ref(f);
+            atom
+        });
+    found
+}
@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use voile_util::loc::Loc;
+use voile_util::uid::GI;
+
+use crate::syntax::core::{Closure, Neutral, Val};
+
+use super::monad::{TCE, TCM, TCS};
+
+/// How an argument of a call relates to a parameter of the caller.
+/// Smaller is better.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Order {
+    /// Strictly smaller, like a constructor argument of the parameter.
+    Lt,
+    /// The parameter itself.
+    Le,
+}
+
+impl Order {
+    fn compose(self, other: Self) -> Self {
+        self.min(other)
+    }
+}
+
+/// Size-change matrix of a call, sparse.
+/// Key: (callee parameter, caller parameter).
+/// Unrelated pairs are absent.
+pub type Matrix = BTreeMap<(usize, usize), Order>;
+
+/// The matrix of a call to `g` (with matrix `second`)
+/// happening in the body of `f` (called with matrix `first`).
+fn compose(first: &Matrix, second: &Matrix) -> Matrix {
+    let mut composed = Matrix::new();
+    for (&(k, j), &a) in first {
+        for (&(i, _), &b) in second.iter().filter(|((_, l), _)| *l == k) {
+            let order = a.compose(b);
+            let entry = composed.entry((i, j)).or_insert(order);
+            *entry = (*entry).min(order);
+        }
+    }
+    composed
+}
+
+/// A recursive call, found in a function body.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Call {
+    pub callee: GI,
+    pub matrix: Matrix,
+}
+
+/// Size of a bound variable: which parameter it is derived from, and how.
+/// `None` for unrelated variables.
+type Size = Option<(usize, Order)>;
+
+/// Collects calls in a definition body.
+///
+/// `ctx` are the sizes of the bound variables, the last one is `DBI(0)`.
+/// `head` is the index of the next parameter, when we're still in the chain of
+/// lambdas (and case-splits) at the beginning of the body.
+struct Calls {
+    ctx: Vec<Size>,
+    calls: Vec<Call>,
+}
+
+impl Calls {
+    fn size(&self, val: &Val) -> Size {
+        match val {
+            Val::Neut(neut) => self.size_neutral(neut),
+            _ => None,
+        }
+    }
+
+    fn size_neutral(&self, neut: &Neutral) -> Size {
+        use Neutral::*;
+        match neut {
+            Var(dbi) => *self.ctx.iter().rev().nth(dbi.0)?,
+            // Projecting from a value doesn't make it bigger.
+            Fst(n) | Snd(n) | Proj(n, _) => self.size_neutral(n),
+            _ => None,
+        }
+    }
+
+    fn val(&mut self, val: &Val, head: Option<usize>) {
+        use Val::*;
+        match val {
+            Lam(closure) => {
+                let param = head.map(|k| (k, Order::Le));
+                self.closure(closure, param, head.map(|k| k + 1));
+            }
+            // Recursive occurrences in types are guarded by the type formers,
+            // since types are equi-recursive.
+            Dt(..) | RowPoly(..) | Type(..) | RowKind(..) => {}
+            LevelTy | Lvl(..) => {}
+            Rec(fields) => fields.values().for_each(|v| self.val(v, None)),
+            Cons(_, args) => args.iter().for_each(|a| self.val(a, None)),
+            Pair(a, b) => {
+                self.val(a, None);
+                self.val(b, None);
+            }
+            Neut(neut) => self.neutral(neut),
+        }
+    }
+
+    /// `param` is the size of the closure parameter.
+    fn closure(&mut self, closure: &Closure, param: Size, head: Option<usize>) {
+        match closure {
+            Closure::Plain(body) => {
+                self.ctx.push(param);
+                self.val(body, head);
+                self.ctx.pop();
+            }
+            // The branches bind the arguments of the constructors.
+            Closure::Tree(split) => {
+                let smaller = param.map(|(k, _)| (k, Order::Lt));
+                for branch in split.values() {
+                    self.closure(branch, smaller, head);
+                }
+            }
+        }
+    }
+
+    fn neutral(&mut self, neut: &Neutral) {
+        use Neutral::*;
+        match neut {
+            Ref(callee) => self.calls.push(Call {
+                callee: *callee,
+                matrix: Default::default(),
+            }),
+            App(f, args) => {
+                match &**f {
+                    Ref(callee) => {
+                        let matrix = (args.iter().enumerate())
+                            .filter_map(|(i, arg)| self.size(arg).map(|(j, o)| ((i, j), o)))
+                            .collect();
+                        let callee = *callee;
+                        self.calls.push(Call { callee, matrix });
+                    }
+                    f => self.neutral(f),
+                }
+                args.iter().for_each(|arg| self.val(arg, None));
+            }
+            SplitOn(split, obj) => {
+                self.neutral(obj);
+                let smaller = self.size_neutral(obj).map(|(k, _)| (k, Order::Lt));
+                for branch in split.values() {
+                    self.closure(branch, smaller, None);
+                }
+            }
+            OrSplit(split, or) => {
+                self.neutral(or);
+                for branch in split.values() {
+                    self.closure(branch, None, None);
+                }
+            }
+            Fst(n) | Snd(n) | Proj(n, _) | Lift(_, n) | Fall(_, n) => self.neutral(n),
+            Restrict(n, _) | Rename(n, ..) => self.neutral(n),
+            Rec(fields, ext) => {
+                fields.values().for_each(|v| self.val(v, None));
+                self.neutral(ext);
+            }
+            Row(..) | Type(..) | LMax(..) | Var(..) | Meta(..) | Axi(..) | Field(..) => {}
+        }
+    }
+}
+
+/// Global references in `body`, with their size-change matrices.
+pub fn calls(body: &Val) -> Vec<Call> {
+    let mut calls = Calls {
+        ctx: Vec::new(),
+        calls: Vec::new(),
+    };
+    calls.val(body, Some(0));
+    calls.calls
+}
+
+fn is_idempotent(matrix: &Matrix) -> bool {
+    &compose(matrix, matrix) == matrix
+}
+
+/**
+Size-change termination check of the (just implemented) definition `f`.
+Every call path from `f` back to `f` (composing the matrices along the path)
+whose matrix $M$ is idempotent should have some parameter strictly decreasing:
+$$
+M \circ M = M \implies \exists i. M_{i,i} = \lt
+$$
+Returns the offending call path if there's any.
+*/
+pub fn non_terminating_path(tcs: &TCS, f: GI) -> Option<Vec<GI>> {
+    let mut bodies = BTreeMap::new();
+    let mut calls_of = |g: GI| -> Vec<Call> {
+        (bodies.entry(g))
+            .or_insert_with(|| calls(&tcs.glob_val(g).ast))
+            .clone()
+    };
+    let mut visited = BTreeSet::new();
+    let mut queue = (calls_of(f).into_iter())
+        .map(|call| (call.callee, call.matrix, vec![f, call.callee]))
+        .collect::<VecDeque<_>>();
+    while let Some((g, matrix, path)) = queue.pop_front() {
+        if !visited.insert((g, matrix.clone())) {
+            continue;
+        }
+        let decreasing = (matrix.iter()).any(|(&(i, j), &o)| i == j && o == Order::Lt);
+        if g == f && !decreasing && is_idempotent(&matrix) {
+            return Some(path);
+        }
+        for call in calls_of(g) {
+            let mut path = path.clone();
+            path.push(call.callee);
+            queue.push_back((call.callee, compose(&matrix, &call.matrix), path));
+        }
+    }
+    None
+}
+
+/// The definitions from `g` to `h` in the source, excluding `g`.
+/// A call from `g` to `h` may go through the definitions unfolded in the body of `g`.
+fn source_path(tcs: &TCS, g: GI, h: GI) -> Vec<GI> {
+    let mut visited = BTreeSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(vec![g]);
+    while let Some(path) = queue.pop_front() {
+        let last = *path.last().unwrap();
+        for &next in tcs.refs.get(&last).into_iter().flatten() {
+            let mut path = path.clone();
+            path.push(next);
+            if next == h {
+                return path.split_off(1);
+            }
+            if visited.insert(next) {
+                queue.push_back(path);
+            }
+        }
+    }
+    vec![h]
+}
+
+/// Report non-terminating definitions, at `loc` where `f` is defined.
+pub fn check_termination(tcs: &TCS, f: GI, loc: Loc) -> TCM<()> {
+    match non_terminating_path(tcs, f) {
+        None => Ok(()),
+        Some(path) => {
+            let mut source = vec![path[0]];
+            for call in path.windows(2) {
+                source.append(&mut source_path(tcs, call[0], call[1]));
+            }
+            let path = source.into_iter().map(|g| tcs.glob_name(g)).collect();
+            Err(TCE::NonTerminating(loc, path))
+        }
+    }
+}
@@ -1,10 +1,16 @@
+use std::collections::BTreeSet;
+use std::mem::take;
+
+use voile_util::axiom::Axiom;
+use voile_util::level::{Level, LevelType, LiftEx};
 use voile_util::meta::{MetaSolution, MI};
 use voile_util::tags::VarRec;
+use voile_util::uid::UID;
 
-use crate::syntax::core::{CaseSplit, Closure, Neutral, TraverseNeutral, Val, Variants};
+use crate::syntax::core::{level_leq, CaseSplit, Closure, Neutral, TraverseNeutral, Val, Variants};
 
-use super::monad::{TCE, TCM, TCS};
-use std::cmp::Ordering;
+use super::eval::global_head;
+use super::monad::{Constraint, TCE, TCM, TCS};
 
 fn check_solution(meta: MI, rhs: Val) -> TCM<()> {
     rhs.try_fold_neutral((), |(), neut| match neut {
@@ -13,13 +19,118 @@ fn check_solution(meta: MI, rhs: Val) -> TCM<()> {
     })
 }
 
-/// Solve a meta with a specific value.
+/// Solve a meta with a specific value,
+/// then wake up the constraints blocked on it.
 fn solve_with(mut tcs: TCS, meta: MI, solution: Val) -> TCM {
     // TODO: remove this clone by introducing reference version of `try_fold_neutral`.
     let anticipated_solution = solution.clone().unimplemented_to_glob();
+    if let Some((kind, labels)) = tcs.lacks.remove(&meta) {
+        tcs = tcs.require_lacks(kind, &solution, &labels)?;
+    }
     check_solution(meta, solution)?;
     tcs.meta_context.solve_meta(meta, anticipated_solution);
 
+    wake_up(tcs, meta)
+}
+
+/// `a` and `b` cannot be unified, shown with the universe variables at their least levels.
+fn mismatch(tcs: &TCS, a: &Val, b: &Val) -> TCE {
+    let a = tcs.zonk_universes(a.clone());
+    TCE::CannotUnify(a, tcs.zonk_universes(b.clone()))
+}
+
+/**
+Make sure the row `row` lacks `labels`, so it's safe to extend it by them.
+Row literals are checked directly, while unsolved row metas are constrained,
+and the rigid row variables are checked against their row kinds.
+$$
+\newcommand{\Gvdash}[0]{\Gamma \vdash}
+\newcommand{\recordR}[1]{\mathbb{Rec}\  #1}
+\cfrac{
+  \Gvdash r : \recordR{ns} \quad n \in ns
+}{
+  \Gvdash r \text{ lacks } n
+}
+$$
+*/
+fn require_lacks(mut tcs: TCS, kind: VarRec, row: &Val, labels: &[String]) -> TCM {
+    let not_lacking = |label: &String| TCE::RowNotLacking(kind, row.clone(), label.clone());
+    let (fields, more) = match row {
+        Val::RowPoly(row_kind, fields) if *row_kind == kind => (fields, None),
+        Val::Neut(Neutral::Row(row_kind, fields, more)) if *row_kind == kind => {
+            (fields, Some(&**more))
+        }
+        _ if labels.is_empty() => return Ok(tcs),
+        Val::Neut(Neutral::Meta(mi)) => {
+            return match tcs.meta_context.solution(*mi) {
+                MetaSolution::Unsolved => {
+                    tcs.constrain_lacks(*mi, kind, labels);
+                    Ok(tcs)
+                }
+                MetaSolution::Solved(solution) => {
+                    let solution = *solution.clone();
+                    tcs.require_lacks(kind, &solution, labels)
+                }
+                MetaSolution::Inlined => unreachable!(),
+            };
+        }
+        Val::Neut(Neutral::Axi(axiom)) => {
+            let row_kind = tcs.axiom_type(axiom.unique_id()).map(|ty| &ty.ast);
+            let lacked = match row_kind {
+                Some(Val::RowKind(_, row_kind, lacked)) if *row_kind == kind => lacked,
+                _ => return Err(not_lacking(&labels[0])),
+            };
+            return match labels.iter().find(|label| !lacked.contains(label)) {
+                None => Ok(tcs),
+                Some(label) => Err(not_lacking(label)),
+            };
+        }
+        _ => return Err(not_lacking(&labels[0])),
+    };
+    if let Some(label) = labels.iter().find(|label| fields.contains_key(*label)) {
+        return Err(not_lacking(label));
+    }
+    match more {
+        None => Ok(tcs),
+        Some(more) => tcs.require_lacks(kind, &Val::Neut(more.clone()), labels),
+    }
+}
+
+/// Retry the postponed constraints blocked on `meta`, which is just solved.
+/// They're postponed again if they're still blocked.
+fn wake_up(mut tcs: TCS, meta: MI) -> TCM {
+    let (woken, blocked): (Vec<_>, _) = (take(&mut tcs.postponed).into_iter())
+        .partition(|constraint| constraint.blockers.contains(&meta));
+    tcs.postponed = blocked;
+    (woken.into_iter()).try_fold(tcs, |tcs, constraint| {
+        tcs.unify(&constraint.lhs, &constraint.rhs)
+    })
+}
+
+/// Postpone `a = b` until one of the unsolved metas in it is solved,
+/// fail if there's no unsolved meta at all.
+fn postpone(mut tcs: TCS, a: &Val, b: &Val) -> TCM {
+    let mut blockers = BTreeSet::new();
+    let mut collect = |atom, _| {
+        if let Neutral::Meta(mi) = atom {
+            if let MetaSolution::Unsolved = tcs.meta_context.solution(mi) {
+                blockers.insert(mi);
+            }
+        }
+        atom
+    };
+    a.clone()
+        .map_atom_with_dbi(Default::default(), &mut collect);
+    b.clone()
+        .map_atom_with_dbi(Default::default(), &mut collect);
+    if blockers.is_empty() {
+        return Err(mismatch(&tcs, a, b));
+    }
+    tcs.postponed.push(Constraint {
+        lhs: a.clone(),
+        rhs: b.clone(),
+        blockers,
+    });
     Ok(tcs)
 }
 
@@ -112,13 +223,15 @@ fn unify(tcs: TCS, a: &Val, b: &Val) -> TCM {
         }
         */
         (Neut(Ref(x)), Neut(Ref(y))) if x == y => Ok(tcs),
-        (Dt(k0, a_plicit, input_a, clos_a), Dt(k1, b_plicit, input_b, clos_b))
+        (Dt(k0, a_plicit, _, input_a, clos_a), Dt(k1, b_plicit, _, input_b, clos_b))
             if k0 == k1 && a_plicit == b_plicit =>
         {
             tcs.unify(input_a, input_b)?.unify_closure(clos_a, clos_b)
         }
         (Lam(a), Lam(b)) => unify_closure(tcs, a, b),
-        (Cons(_, a), Cons(_, b)) => tcs.unify(&**a, &**b),
+        (Cons(x, a), Cons(y, b)) if x == y && a.len() == b.len() => {
+            (a.iter().zip(b)).try_fold(tcs, |tcs, (a, b)| tcs.unify(a, b))
+        }
         (Pair(a0, a1), Pair(b0, b1)) => tcs.unify(&**a0, &**b0)?.unify(&**a1, &**b1),
         (RowPoly(a_kind, a_variants), RowPoly(b_kind, b_variants))
             if a_kind == b_kind && a_variants.len() == b_variants.len() =>
@@ -131,7 +244,7 @@ fn unify(tcs: TCS, a: &Val, b: &Val) -> TCM {
             if a_labels.iter().all(|n| b_labels.contains(n)) {
                 Ok(tcs)
             } else {
-                Err(TCE::CannotUnify(a.clone(), b.clone()))
+                Err(mismatch(&tcs, a, b))
             }
         }
         (Rec(a_fields), Rec(b_fields)) if a_fields.len() == b_fields.len() => {
@@ -148,15 +261,160 @@ fn unify(tcs: TCS, a: &Val, b: &Val) -> TCM {
             let (more, tcs) = unify_partial_variants(tcs, more.clone(), less, *kind0)?;
             tcs.unify(&RowPoly(*kind0, more), &Neut(*ext.clone()))
         }
+        (Neut(Meta(x)), Neut(Meta(y))) if x == y => Ok(tcs),
+        (a, b) if meta_app(a).is_some() || meta_app(b).is_some() => unify_meta_app(tcs, a, b),
         (term, Neut(Meta(mi))) | (Neut(Meta(mi)), term) => unify_meta_with(tcs, term, *mi),
+        (LevelTy, LevelTy) => Ok(tcs),
+        (Lvl(..), _)
+        | (_, Lvl(..))
+        | (Neut(Neutral::LMax(..)), _)
+        | (_, Neut(Neutral::LMax(..))) => unify_level(tcs, a, b),
+        (a, b) if a.univ_level().is_some() && b.univ_level().is_some() => {
+            let universes = mismatch(&tcs, a, b);
+            match unify_level(tcs, &a.univ_level().unwrap(), &b.univ_level().unwrap()) {
+                Ok(tcs) => Ok(tcs),
+                Err(TCE::CannotUnify(..)) => Err(universes),
+                Err(e) => Err(e),
+            }
+        }
+        (a, b) if global_head(a) != global_head(b) => {
+            let mut tcs = tcs;
+            if tcs.assume_unfolded(a, b) {
+                return Ok(tcs);
+            }
+            match unfold_either(&tcs, a, b) {
+                Some((a_unfolded, b_unfolded)) => {
+                    tcs.enter_unfolding(a, b);
+                    let mut tcs = tcs.unify(&a_unfolded, &b_unfolded)?;
+                    tcs.leave_unfolding(a, b);
+                    Ok(tcs)
+                }
+                None => Err(mismatch(&tcs, a, b)),
+            }
+        }
         (Neut(a), Neut(b)) => tcs.unify_neutral(a, b),
-        (e, t) => Err(TCE::CannotUnify(e.clone(), t.clone())),
+        (e, t) => unify_stuck(tcs, e, t),
     }
 }
 
+/// Unfold the global references `a` and `b` are headed by,
+/// `None` if neither of them can be unfolded.
+pub(crate) fn unfold_either(tcs: &TCS, a: &Val, b: &Val) -> Option<(Val, Val)> {
+    match (tcs.unfold_global(a), tcs.unfold_global(b)) {
+        (None, None) => None,
+        (a_unfolded, b_unfolded) => Some((
+            a_unfolded.unwrap_or_else(|| a.clone()),
+            b_unfolded.unwrap_or_else(|| b.clone()),
+        )),
+    }
+}
+
+impl TCS {
+    /// If `a` and `b` are headed by different global references, and the same pair
+    /// of types (with the same arguments) is being unfolded and compared already,
+    /// they're assumed to be related. Recursive types are unfolded forever otherwise.
+    pub(crate) fn assume_unfolded(&mut self, a: &Val, b: &Val) -> bool {
+        let (a, b) = (zonk(self, a.clone()), zonk(self, b.clone()));
+        let assumption = (self.unfolding.iter_mut()).find(|(x, y, _)| (x, y) == (&a, &b));
+        match assumption {
+            Some((_, _, used)) => {
+                *used = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start comparing the unfoldings of `a` and `b`, see [`TCS::assume_unfolded`].
+    pub(crate) fn enter_unfolding(&mut self, a: &Val, b: &Val) {
+        if global_head(a).is_some() && global_head(b).is_some() {
+            let pair = (zonk(self, a.clone()), zonk(self, b.clone()));
+            self.unfolding.push((pair.0, pair.1, false));
+        }
+    }
+
+    /// Returns if the assumption is used when comparing the unfoldings.
+    pub(crate) fn leave_unfolding(&mut self, a: &Val, b: &Val) -> bool {
+        match (global_head(a), global_head(b)) {
+            (Some(..), Some(..)) => (self.unfolding.pop()).map_or(false, |(.., used)| used),
+            _ => false,
+        }
+    }
+}
+
+/// Replace the solved metas in `val` with their solutions,
+/// so the types are compared with their arguments known so far.
+fn zonk(tcs: &TCS, val: Val) -> Val {
+    val.map_neutral(&mut |neut| match neut {
+        Neutral::Meta(mi) => match tcs.meta_context.solution(mi) {
+            MetaSolution::Solved(solution) => zonk(tcs, *solution.clone()),
+            _ => Val::meta(mi),
+        },
+        neut => Val::Neut(neut),
+    })
+}
+
+/**
+Unify two universe levels.
+Levels are compared modulo `lmax` being associative, commutative and idempotent,
+while a meta (maybe with an offset) is solved by the other side.
+$$
+\cfrac{\Gamma \vdash \alpha \simeq \beta}{
+  \Gamma \vdash \texttt{lsuc}^k\ ?m \simeq \texttt{lsuc}^k\ \beta
+  \Rightarrow ?m := \beta
+}
+$$
+*/
+fn unify_level(tcs: TCS, a: &Val, b: &Val) -> TCM {
+    let a = tcs.expand_level(a.clone());
+    let b = tcs.expand_level(b.clone());
+    if level_leq(&a, &b) && level_leq(&b, &a) {
+        return Ok(tcs);
+    }
+    match (meta_with_offset(&a), meta_with_offset(&b)) {
+        (Some((mi, k)), _) => match level_pred(b.clone(), k) {
+            Some(sol) => solve_with(tcs, mi, sol),
+            None => Err(mismatch(&tcs, &a, &b)),
+        },
+        (_, Some((mi, k))) => match level_pred(a.clone(), k) {
+            Some(sol) => solve_with(tcs, mi, sol),
+            None => Err(mismatch(&tcs, &a, &b)),
+        },
+        // Like `lmax ?a ?b`, which may be solved by other constraints.
+        _ => postpone(tcs, &a, &b),
+    }
+}
+
+/// `?m` or `lsuc (lsuc ?m)`, with the number of `lsuc`s.
+fn meta_with_offset(level: &Val) -> Option<(MI, LevelType)> {
+    match level {
+        Val::Neut(Neutral::Meta(mi)) => Some((*mi, 0)),
+        Val::Neut(Neutral::LMax(0, atoms)) => match &atoms[..] {
+            [(Neutral::Meta(mi), k)] => Some((*mi, *k)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The level `l` such that `lsuc^k l` is `level`, if there's any.
+fn level_pred(level: Val, k: LevelType) -> Option<Val> {
+    let (n, atoms) = match level.level_parts()? {
+        (Level::Omega, _) => return Some(Val::Lvl(Level::Omega)),
+        (Level::Num(n), atoms) => (n, atoms),
+    };
+    if (n < k && atoms.is_empty()) || atoms.iter().any(|(_, offset)| *offset < k) {
+        return None;
+    }
+    let atoms = (atoms.into_iter())
+        .map(|(atom, offset)| (atom, offset - k))
+        .collect();
+    Some(Val::level_from_parts(n.saturating_sub(k), atoms))
+}
+
 fn unify_meta_with(tcs: TCS, term: &Val, mi: MI) -> TCM {
     match &tcs.meta_context.solution(mi) {
-        MetaSolution::Unsolved => solve_with(tcs, mi, term.clone()),
+        MetaSolution::Unsolved => solve_pattern(tcs, mi, &[], term),
         MetaSolution::Solved(solution) => {
             let val = *solution.clone();
             tcs.unify(&val, term)
@@ -165,6 +423,238 @@ fn unify_meta_with(tcs: TCS, term: &Val, mi: MI) -> TCM {
     }
 }
 
+/// The meta and the arguments of a meta application, like `?m a b`.
+fn meta_app(val: &Val) -> Option<(MI, &[Val])> {
+    match val {
+        Val::Neut(Neutral::App(f, args)) => match &**f {
+            Neutral::Meta(mi) => Some((*mi, args)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Unify two values where at least one of them is a meta application.
+/// Solved metas are applied, patterns are solved, and others are postponed.
+fn unify_meta_app(tcs: TCS, a: &Val, b: &Val) -> TCM {
+    let ((mi, args), term) = match (meta_app(a), meta_app(b)) {
+        (Some((x, _)), Some((y, _))) if x == y => match (a, b) {
+            (Val::Neut(a), Val::Neut(b)) => return tcs.unify_neutral(a, b),
+            _ => unreachable!(),
+        },
+        (Some(app), _) => (app, b),
+        (None, Some(app)) => (app, a),
+        (None, None) => unreachable!(),
+    };
+    match tcs.meta_context.solution(mi) {
+        MetaSolution::Solved(solution) => {
+            let applied = args.iter().cloned().fold(*solution.clone(), Val::apply);
+            tcs.unify(&applied, term)
+        }
+        MetaSolution::Unsolved => match pattern_spine(args) {
+            Some(spine) => solve_pattern(tcs, mi, &spine, term),
+            None => postpone(tcs, a, b),
+        },
+        MetaSolution::Inlined => unreachable!(),
+    }
+}
+
+/// The arguments as local variables, if they're distinct local variables.
+fn pattern_spine(args: &[Val]) -> Option<Vec<UID>> {
+    let mut spine = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg {
+            Val::Neut(Neutral::Axi(axiom)) if !matches!(axiom, Axiom::Unimplemented(..)) => {
+                let uid = axiom.unique_id();
+                if spine.contains(&uid) {
+                    return None;
+                }
+                spine.push(uid);
+            }
+            _ => return None,
+        }
+    }
+    Some(spine)
+}
+
+/**
+Solve a meta applied to distinct local variables (a Miller pattern),
+by abstracting the other side over these variables.
+The other side should neither mention the meta itself,
+nor the lambda parameters not in the scope of the meta.
+$$
+\newcommand{\Gvdash}[0]{\Gamma \vdash}
+\cfrac{
+  \overline{x} \text{ distinct} \quad
+  ?m \notin t \quad
+  \text{FV}(t) \subseteq \overline{x} \cup \Delta\_{?m}
+}{
+  \Gvdash ?m\ \overline{x} \simeq t
+  \Rightarrow ?m := \lambda \overline{x}. t
+}
+$$
+*/
+fn solve_pattern(tcs: TCS, meta: MI, spine: &[UID], term: &Val) -> TCM {
+    let scope = tcs.meta_scopes.get(&meta);
+    let out_of_scope = |axiom: &Axiom| match axiom {
+        Axiom::Postulated(uid) | Axiom::Generated(uid, ..) => {
+            scope.map_or(false, |scope| !scope.contains(uid))
+        }
+        _ => false,
+    };
+    let mut error = None;
+    let body = term
+        .clone()
+        .map_atom_with_dbi(Default::default(), &mut |atom, dbi| {
+            match atom {
+                Neutral::Meta(mi) if mi == meta => {
+                    error.get_or_insert(TCE::MetaRecursion(meta));
+                }
+                Neutral::Axi(axiom) => {
+                    let uid = axiom.unique_id();
+                    match spine.iter().rposition(|x| *x == uid) {
+                        Some(i) => return Neutral::Var(dbi + (spine.len() - 1 - i)),
+                        None if out_of_scope(&axiom) => {
+                            let axiom = Val::Neut(Neutral::Axi(axiom.clone()));
+                            error.get_or_insert(TCE::MetaOutOfScope(meta, axiom));
+                        }
+                        None => {}
+                    }
+                    return Neutral::Axi(axiom);
+                }
+                _ => {}
+            }
+            atom
+        });
+    if let Some(error) = error {
+        return Err(error);
+    }
+    let solution = spine.iter().fold(body, |body, _| Val::closure_lam(body));
+    solve_with(tcs, meta, solution)
+}
+
+/// Close the open rows left unsolved at the end of a declaration with the empty row,
+/// like the open variant types of constructor applications no one extends.
+/// Other row metas, like the implicit arguments of row kinds, are left unsolved.
+fn close_rows(tcs: TCS) -> TCM {
+    let mut tcs = tcs;
+    let open = (take(&mut tcs.open_rows).into_iter())
+        .filter(|(mi, _)| matches!(tcs.meta_context.solution(*mi), MetaSolution::Unsolved))
+        .collect::<Vec<_>>();
+    (open.into_iter()).try_fold(tcs, |tcs, (mi, kind)| {
+        // Solving one of them may solve the others, by waking up the constraints.
+        match tcs.meta_context.solution(mi) {
+            MetaSolution::Unsolved => solve_with(tcs, mi, Val::RowPoly(kind, Default::default())),
+            _ => Ok(tcs),
+        }
+    })
+}
+
+/// The postponed constraints are solved when their blockers are solved,
+/// so the ones left at the end of a declaration (after closing the open rows)
+/// are stuck forever.
+fn require_no_postponed(tcs: TCS) -> TCM {
+    let mut tcs = close_rows(tcs)?;
+    if tcs.postponed.is_empty() {
+        Ok(tcs)
+    } else {
+        Err(TCE::StuckConstraints(take(&mut tcs.postponed)))
+    }
+}
+
+/// The meta that `neut` is stuck on, like `?m` in `(?m a).1`.
+fn meta_head(neut: &Neutral) -> Option<MI> {
+    use Neutral::*;
+    match neut {
+        Meta(mi) => Some(*mi),
+        App(f, ..) | Fst(f) | Snd(f) | Proj(f, ..) | Lift(_, f) | Fall(_, f) => meta_head(f),
+        Restrict(f, ..) | Rename(f, ..) => meta_head(f),
+        SplitOn(_, obj) => meta_head(obj),
+        _ => None,
+    }
+}
+
+/// Replace the solved meta at the head of `neut` with its solution,
+/// `None` if the head is not a solved meta.
+fn force_meta_head(tcs: &TCS, neut: &Neutral) -> Option<Val> {
+    use Neutral::*;
+    let go = |neut: &Neutral| force_meta_head(tcs, neut);
+    Some(match neut {
+        Meta(mi) => match tcs.meta_context.solution(*mi) {
+            MetaSolution::Solved(solution) => *solution.clone(),
+            _ => return None,
+        },
+        App(f, args) => args.iter().cloned().fold(go(f)?, Val::apply),
+        Fst(pair) => go(pair)?.first(),
+        Snd(pair) => go(pair)?.second(),
+        Proj(rec, field) => go(rec)?.project(field.clone()),
+        Restrict(rec, label) => go(rec)?.restrict(label.clone()),
+        Rename(rec, from, to) => go(rec)?.rename(from.clone(), to.clone()),
+        Lift(levels, neut) => go(neut)?.lift(*levels),
+        Fall(levels, neut) => go(neut)?.fall(*levels),
+        SplitOn(split, obj) => Val::case_tree(split.clone()).apply(go(obj)?),
+        _ => return None,
+    })
+}
+
+/**
+The last resort of unification, when `a` and `b` are structurally different.
+Solved metas at their heads are replaced with the solutions,
+and they're postponed if they're stuck on unsolved metas.
+$$
+\newcommand{\Gvdash}[0]{\Gamma \vdash}
+\cfrac{
+  ?m \text{ unsolved}
+}{
+  \Gvdash E[?m] \simeq t
+  \Rightarrow \text{postpone}
+}
+$$
+*/
+fn unify_stuck(tcs: TCS, a: &Val, b: &Val) -> TCM {
+    let force = |val: &Val| match val {
+        Val::Neut(neut) => force_meta_head(&tcs, neut),
+        _ => None,
+    };
+    match (force(a), force(b)) {
+        (None, None) => {}
+        (a_forced, b_forced) => {
+            let a = a_forced.unwrap_or_else(|| a.clone());
+            let b = b_forced.unwrap_or_else(|| b.clone());
+            return tcs.unify(&a, &b);
+        }
+    }
+    let is_stuck = |val: &Val| match val {
+        Val::Neut(neut) => meta_head(neut).is_some(),
+        _ => false,
+    };
+    if is_stuck(a) || is_stuck(b) {
+        postpone(tcs, a, b)
+    } else {
+        Err(mismatch(&tcs, a, b))
+    }
+}
+
+/**
+Unify two open rows by row rewriting.
+The labels in both rows are unified pairwise,
+and the labels in one row only go to the extension of the other one.
+If both rows have their own labels, the extensions are solved with
+the labels of the other row and a fresh row meta in common.
+$$
+\newcommand{\Gvdash}[0]{\Gamma \vdash}
+\newcommand{\record}[1]{\textbf{Rec}\\ \\{ #1 \\}}
+\newcommand{\ctyLab}[0]{\gamma}
+\cfrac{
+  \Gvdash \rho\_0 \simeq \record{\ctyLab\_1, \ldots = \tau} \quad
+  \Gvdash \rho\_1 \simeq \record{\ctyLab\_0, \ldots = \tau} \quad
+  \tau \text{ fresh}
+}{
+  \Gvdash \record{\ctyLab\_0, \ldots = \rho\_0}
+  \simeq \record{\ctyLab\_1, \ldots = \rho\_1}
+}
+$$
+*/
 fn unify_neutral_variants(
     tcs: TCS,
     a_fields: &Variants,
@@ -173,18 +663,45 @@ fn unify_neutral_variants(
     b_more: &Neutral,
     kind: VarRec,
 ) -> TCM {
-    let (more, less, more_r, less_r) = match a_fields.len().cmp(&b_fields.len()) {
-        Ordering::Equal => {
-            return tcs
-                .unify_variants(kind, a_fields, b_fields)?
-                .unify_neutral(a_more, b_more);
-        }
-        Ordering::Greater => (a_fields.clone(), b_fields, a_more, b_more),
-        Ordering::Less => (b_fields.clone(), a_fields, b_more, a_more),
+    let only_in = |fields: &Variants, others: &Variants| -> Variants {
+        (fields.iter())
+            .filter(|(label, _)| !others.contains_key(*label))
+            .map(|(label, ty)| (label.clone(), ty.clone()))
+            .collect()
     };
-    // `less` should be exhausted
-    let (rest, tcs) = unify_partial_variants(tcs, more, less, kind)?;
-    tcs.unify_neutral(&Neutral::Row(kind, rest, Box::new(more_r.clone())), less_r)
+    let a_only = only_in(a_fields, b_fields);
+    let b_only = only_in(b_fields, a_fields);
+    let mut tcs = tcs;
+    for (label, ty) in a_fields {
+        if let Some(counterpart) = b_fields.get(label) {
+            tcs = tcs.unify(ty, counterpart)?;
+        }
+    }
+    let row = |fields: Variants, more: Neutral| Val::neutral_row_type(kind, fields, more);
+    match (a_only.is_empty(), b_only.is_empty()) {
+        (true, true) => tcs.unify_neutral(a_more, b_more),
+        (true, false) => tcs.unify(&Val::Neut(a_more.clone()), &row(b_only, b_more.clone())),
+        (false, true) => tcs.unify(&row(a_only, a_more.clone()), &Val::Neut(b_more.clone())),
+        (false, false) if a_more != b_more && (is_meta(a_more) || is_meta(b_more)) => {
+            let labels: Vec<_> = (a_fields.keys().chain(b_only.keys()).cloned()).collect();
+            let common = tcs.fresh_row_meta(kind, &labels);
+            tcs.unify(&Val::Neut(a_more.clone()), &row(b_only, common.clone()))?
+                .unify(&Val::Neut(b_more.clone()), &row(a_only, common))
+        }
+        (false, false) => {
+            let less = if a_fields.len() < b_fields.len() {
+                a_only
+            } else {
+                b_only
+            };
+            let label = less.into_iter().next().unwrap().0;
+            Err(TCE::MissingVariant(kind, label))
+        }
+    }
+}
+
+fn is_meta(neut: &Neutral) -> bool {
+    matches!(neut, Neutral::Meta(..))
 }
 
 fn unify_partial_variants(
@@ -226,8 +743,9 @@ fn unify_closure(tcs: TCS, a: &Closure, b: &Closure) -> TCM {
         (Tree(split), _) | (_, Tree(split)) => {
             let mut tcs = tcs;
             for (label, branch) in split {
+                // The payload stands for the arguments, see `Val::cons_payload`.
                 let p = Val::fresh_axiom();
-                let cons = Val::cons(label.clone(), p.clone());
+                let cons = Val::cons(label.clone(), vec![p.clone()]);
                 let a = branch.instantiate_cloned(p);
                 let b = b.instantiate_cloned(cons);
                 tcs = tcs.unify(&a, &b)?;
@@ -302,6 +820,7 @@ fn unify_neutral(tcs: TCS, a: &Neutral, b: &Neutral) -> TCM {
     use Neutral::*;
     match (a, b) {
         (Ref(x), Ref(y)) if x == y => Ok(tcs),
+        (Field(i, x), Field(j, y)) if i == j && x == y => Ok(tcs),
         (Lift(x, a), Lift(y, b)) if x == y => tcs.unify_neutral(&**a, &**b),
         (App(f, a), App(g, b)) if a.len() == b.len() => (a.iter().zip(b.iter()))
             .try_fold(tcs.unify_neutral(&*f, &*g)?, |tcs, (x, y)| tcs.unify(x, y)),
@@ -312,14 +831,22 @@ fn unify_neutral(tcs: TCS, a: &Neutral, b: &Neutral) -> TCM {
             unify_neutral_variants(tcs, a_fields, b_fields, &**a_more, &**b_more, *a_kind)
         }
         (Snd(a), Snd(b)) | (Fst(a), Fst(b)) => tcs.unify_neutral(&**a, &**b),
-        (Proj(a, lab_a), Proj(b, lab_b)) if lab_a == lab_b => tcs.unify_neutral(&**a, &**b),
+        (Proj(a, lab_a), Proj(b, lab_b)) | (Restrict(a, lab_a), Restrict(b, lab_b))
+            if lab_a == lab_b =>
+        {
+            tcs.unify_neutral(&**a, &**b)
+        }
+        (Rename(a, from_a, to_a), Rename(b, from_b, to_b)) if from_a == from_b && to_a == to_b => {
+            tcs.unify_neutral(&**a, &**b)
+        }
         (SplitOn(split_a, a), SplitOn(split_b, b)) | (OrSplit(split_a, a), OrSplit(split_b, b)) => {
             tcs.unify_case_split(split_a, split_b)?
                 .unify_neutral(&**a, &**b)
         }
         (Axi(a), Axi(b)) if a.unique_id() == b.unique_id() => Ok(tcs),
+        (Meta(x), Meta(y)) if x == y => Ok(tcs),
         (Meta(mi), sol) | (sol, Meta(mi)) => unify_meta_with(tcs, &Val::Neut(sol.clone()), *mi),
-        (e, t) => Err(TCE::CannotUnify(Val::Neut(e.clone()), Val::Neut(t.clone()))),
+        (e, t) => unify_stuck(tcs, &Val::Neut(e.clone()), &Val::Neut(t.clone())),
     }
 }
 
@@ -330,6 +857,21 @@ impl TCS {
         unify(self, a, b)
     }
 
+    #[inline]
+    pub(crate) fn solve_with(self, meta: MI, solution: Val) -> TCM {
+        solve_with(self, meta, solution)
+    }
+
+    #[inline]
+    pub fn require_lacks(self, kind: VarRec, row: &Val, labels: &[String]) -> TCM {
+        require_lacks(self, kind, row, labels)
+    }
+
+    #[inline]
+    pub fn require_no_postponed(self) -> TCM {
+        require_no_postponed(self)
+    }
+
     #[inline]
     fn unify_neutral(self, a: &Neutral, b: &Neutral) -> TCM {
         unify_neutral(self, a, b)
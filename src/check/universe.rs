@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::mem::take;
+
+use voile_util::level::{Level, LevelType};
+use voile_util::meta::{MetaSolution, MI};
+
+use crate::syntax::core::{level_leq, LevelAtoms, Neutral, TraverseNeutral, Val};
+
+use super::monad::{TCE, TCM, TCS};
+
+/// The neutral parts of a level, empty for $\omega$.
+fn atoms(level: &Val) -> LevelAtoms {
+    (level.clone().finite_level_parts()).map_or_else(Vec::new, |(_, atoms)| atoms)
+}
+
+/// Remember `sub <= sup` if both levels are made of metas
+/// and some of them are universe variables, otherwise `false`.
+fn constrain_universe(tcs: &mut TCS, sub: &Val, sup: &Val) -> bool {
+    let (sub_atoms, sup_atoms) = match (
+        sub.clone().finite_level_parts(),
+        sup.clone().finite_level_parts(),
+    ) {
+        (Some((_, sub_atoms)), Some((_, sup_atoms))) => (sub_atoms, sup_atoms),
+        _ => return false,
+    };
+    let mut all = sub_atoms.iter().chain(&sup_atoms);
+    let flexible = all
+        .clone()
+        .all(|(atom, _)| matches!(atom, Neutral::Meta(..)));
+    let universe = all.any(|(atom, _)| match atom {
+        Neutral::Meta(mi) => tcs.universes.is_var(*mi),
+        _ => false,
+    });
+    if flexible && universe {
+        (tcs.universes).constrain(sub.clone(), sup.clone());
+    }
+    flexible && universe
+}
+
+/// `lmax n ?m+k`, the upper bounds that can be raised by raising `?m`.
+fn raisable(level: &Val) -> Option<(LevelType, MI, LevelType)> {
+    let (n, atoms) = level.clone().finite_level_parts()?;
+    match &atoms[..] {
+        [(Neutral::Meta(mi), k)] => Some((n, *mi, *k)),
+        _ => None,
+    }
+}
+
+/// The level of `level` under `levels`, if it's made of metas.
+fn level_under(level: &Val, levels: &BTreeMap<MI, LevelType>) -> Option<LevelType> {
+    let (n, atoms) = level.clone().finite_level_parts()?;
+    (atoms.iter()).try_fold(n, |max, (atom, k)| match atom {
+        Neutral::Meta(mi) => Some(max.max(levels.get(mi)? + k)),
+        _ => None,
+    })
+}
+
+/// Replace the metas in `level` with their levels in `levels`.
+fn assign(level: &Val, levels: &BTreeMap<MI, LevelType>) -> Val {
+    let (mut n, atoms) = match level.clone().level_parts() {
+        Some((Level::Num(n), atoms)) => (n, atoms),
+        Some((Level::Omega, _)) => return Val::Lvl(Level::Omega),
+        None => return level.clone(),
+    };
+    let mut rigid = Vec::with_capacity(atoms.len());
+    for (atom, k) in atoms {
+        match atom {
+            Neutral::Meta(mi) if levels.contains_key(&mi) => n = n.max(levels[&mi] + k),
+            atom => rigid.push((atom, k)),
+        }
+    }
+    Val::level_from_parts(n, rigid)
+}
+
+/**
+The least levels of the unsolved metas in the universe constraints.
+A constraint $\alpha \le \ ?b + j$ raises $?b$ to the level of $\alpha$ minus $j$,
+which is an edge $?a \xrightarrow{k - j} ?b$ for every $?a + k$ in $\alpha$.
+The raising stops within as many rounds as the metas,
+unless there's a cycle of positive weight (like $?a < ?b \le ?a$).
+The other constraints (like the ones with constant upper bounds) are checked at last.
+*/
+fn least_levels(tcs: &TCS) -> TCM<BTreeMap<MI, LevelType>> {
+    let constraints = (tcs.universes.constraints())
+        .map(|(lhs, rhs)| (tcs.expand_level(lhs.clone()), tcs.expand_level(rhs.clone())))
+        .collect::<Vec<_>>();
+    let mut levels = BTreeMap::new();
+    for (lhs, rhs) in &constraints {
+        for (atom, _) in atoms(lhs).into_iter().chain(atoms(rhs)) {
+            if let Neutral::Meta(mi) = atom {
+                levels.insert(mi, 0);
+            }
+        }
+    }
+    let mut raised = None;
+    for _ in 0..=levels.len() {
+        raised = None;
+        for (lhs, rhs) in &constraints {
+            let (bound, mi, k) = match raisable(rhs) {
+                Some(raisable) => raisable,
+                None => continue,
+            };
+            let least = match level_under(lhs, &levels) {
+                Some(least) => least,
+                None => continue,
+            };
+            if least > bound.max(levels[&mi] + k) {
+                levels.insert(mi, least - k);
+                raised = Some((lhs, rhs));
+            }
+        }
+        if raised.is_none() {
+            break;
+        }
+    }
+    if raised.is_some() {
+        return Err(TCE::UniverseCycle);
+    }
+    let assigned =
+        (constraints.iter()).map(|(lhs, rhs)| (assign(lhs, &levels), assign(rhs, &levels)));
+    match assigned.into_iter().find(|(lhs, rhs)| !level_leq(lhs, rhs)) {
+        Some((lhs, rhs)) => Err(TCE::UniverseInconsistency(lhs, rhs)),
+        None => Ok(levels),
+    }
+}
+
+/// The universe constraints should be satisfiable after every declaration,
+/// while the universe variables are kept unsolved.
+fn require_consistent_universes(tcs: TCS) -> TCM {
+    least_levels(&tcs)?;
+    Ok(tcs)
+}
+
+/// The least levels of the universe variables (and the level metas constrained
+/// with them), the unconstrained ones are zero.
+fn universe_levels(tcs: &TCS) -> TCM<BTreeMap<MI, LevelType>> {
+    let mut levels = least_levels(tcs)?;
+    for mi in tcs.universes.vars() {
+        if let MetaSolution::Unsolved = tcs.meta_context.solution(*mi) {
+            levels.entry(*mi).or_insert(0);
+        }
+    }
+    Ok(levels)
+}
+
+/// Solve the universe variables to their least levels.
+fn solve_universes(tcs: TCS) -> TCM {
+    let levels = universe_levels(&tcs)?;
+    let mut tcs = (levels.into_iter()).try_fold(tcs, |tcs, (mi, level)| {
+        match tcs.meta_context.solution(mi) {
+            MetaSolution::Unsolved => tcs.solve_with(mi, Val::Lvl(Level::Num(level))),
+            _ => Ok(tcs),
+        }
+    })?;
+    take(&mut tcs.universes);
+    Ok(tcs)
+}
+
+/// Show the unsolved universe variables in `val` at their least levels,
+/// so the error messages don't mention the levels no one wrote.
+fn zonk_universes(tcs: &TCS, val: Val) -> Val {
+    fn go(levels: &BTreeMap<MI, LevelType>, val: Val) -> Val {
+        val.map_neutral(&mut |neut| match neut {
+            Neutral::Meta(mi) if levels.contains_key(&mi) => Val::Lvl(Level::Num(levels[&mi])),
+            Neutral::Type(level) => {
+                let zonked = go(levels, Val::Neut(*level.clone()));
+                Val::univ(zonked).unwrap_or(Val::Neut(Neutral::Type(level)))
+            }
+            level @ Neutral::LMax(..) => assign(&Val::Neut(level), levels),
+            neut => Val::Neut(neut),
+        })
+    }
+    if tcs.universes.is_empty() {
+        return val;
+    }
+    go(&universe_levels(tcs).unwrap_or_default(), val)
+}
+
+impl TCS {
+    #[inline]
+    pub(crate) fn constrain_universe(&mut self, sub: &Val, sup: &Val) -> bool {
+        constrain_universe(self, sub, sup)
+    }
+
+    #[inline]
+    pub fn require_consistent_universes(self) -> TCM {
+        require_consistent_universes(self)
+    }
+
+    #[inline]
+    pub fn solve_universes(self) -> TCM {
+        solve_universes(self)
+    }
+
+    #[inline]
+    pub fn zonk_universes(&self, val: Val) -> Val {
+        zonk_universes(self, val)
+    }
+}
@@ -29,6 +29,21 @@ pub struct CliOptions {
     #[structopt(short = "q", long)]
     pub quiet: bool,
 
+    /// Additional directories to look for imported modules
+    /// (the directory of FILE is always searched first)
+    #[structopt(short = "I", long = "include", name = "DIR")]
+    pub include: Vec<String>,
+
+    /// Prints errors with source snippets (human) or as JSON objects,
+    /// one per line (json)
+    #[structopt(
+        long,
+        name = "FORMAT",
+        default_value = "human",
+        possible_values = &["human", "json"]
+    )]
+    pub error_format: String,
+
     /// Evaluates a standalone expression
     #[structopt(short = "e", long, name = "expression")]
     pub evaluate: Option<String>,
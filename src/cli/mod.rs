@@ -1,21 +1,29 @@
 extern crate voile;
 
-use crate::repl::code_to_abs;
+use std::mem::take;
+
+use crate::repl::{code_to_abs, evaluate_abs};
 use minitt_util::repl::ReplEnvType;
-use voile::check::check_decls;
+use voile::check::check_decls_recovering;
 use voile::check::monad::TCS;
-use voile::syntax::abs::{trans_decls_contextual, TransState};
+use voile::loader::Loader;
+use voile::syntax::abs::{trans_decls_recovering, TransState};
 
 mod args;
 mod repl;
 mod util;
 
+#[cfg(test)]
+mod tests;
+
 fn main_file(
     file_ref: Option<&String>,
+    loader: &mut Loader,
     quiet: bool,
     parse_only: bool,
+    json: bool,
 ) -> Option<(TCS, TransState)> {
-    let decls = util::parse_file(file_ref?)?;
+    let module = util::parse_file(file_ref?, &mut loader.files)?;
     if !quiet {
         println!("Parse successful.");
     }
@@ -23,22 +31,44 @@ fn main_file(
         return None;
     }
 
+    // Load imported modules
+    let (tcs, trans_state) = loader
+        .import_all(Default::default(), module.imports)
+        .unwrap_or_else(|err| {
+            util::report(err, &loader.files, json);
+            eprintln!("Nou!");
+            std::process::exit(1)
+        });
+
     // Translate to abstract syntax
-    let abs_decls = trans_decls_contextual(Default::default(), decls).unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        eprintln!("Nou!");
-        std::process::exit(1)
-    });
+    let checked_decls = trans_state.decls.len();
+    let mut trans_state = trans_state;
+    trans_state.meta_count = tcs.meta_context.meta_count();
+    let (abs_decls, trans_errors) = trans_decls_recovering(trans_state, module.decls);
+    let trans_failed = !trans_errors.is_empty();
+    for err in trans_errors {
+        util::report(err, &loader.files, json);
+    }
 
-    // Type Check
-    let mut tcs = TCS::default();
+    // Type Check, broken declarations are skipped
+    let mut tcs = tcs;
     tcs.meta_context
         .expand_with_fresh_meta(abs_decls.meta_count);
-    let checked = check_decls(tcs, abs_decls.decls.clone()).unwrap_or_else(|err| {
-        eprintln!("{}", err);
+    let new_decls = abs_decls.decls[checked_decls..].to_vec();
+    let (mut checked, check_errors) = check_decls_recovering(tcs, new_decls);
+    let check_failed = !check_errors.is_empty();
+    for err in check_errors {
+        util::report(err, &loader.files, json);
+    }
+    util::warn(take(&mut checked.warnings), &loader.files, json);
+
+    if trans_failed {
+        eprintln!("Nou!");
+        std::process::exit(1)
+    } else if check_failed {
         eprintln!("Change my mind!");
         std::process::exit(1)
-    });
+    }
 
     if !quiet {
         for (ty, val) in checked.gamma.iter().zip(checked.env.iter()) {
@@ -56,22 +86,32 @@ fn main_file(
 fn main() {
     let args = args::pre();
 
-    let mut checked =
-        main_file(args.file.as_ref(), args.quiet, args.parse_only).unwrap_or_default();
+    let search_path = util::search_path(args.file.as_ref(), &args.include);
+    let mut loader = Loader::new(search_path.clone());
+    let json = args.error_format == "json";
+    let mut checked = main_file(
+        args.file.as_ref(),
+        &mut loader,
+        args.quiet,
+        args.parse_only,
+        json,
+    )
+    .unwrap_or_default();
 
     if let Some(abs) = args
         .evaluate
         .and_then(|code| code_to_abs(&mut checked, &code))
     {
         let (tcs, trans_st) = checked;
-        let (core, tcs) = tcs.evaluate(abs);
-        println!("{}", core.ast);
+        let (core, tcs) = evaluate_abs(tcs, abs);
+        println!("{}", core);
         checked = (tcs, trans_st);
     }
 
     // REPL
     repl::repl(
         checked,
+        search_path,
         if args.interactive_plain {
             Some(ReplEnvType::Plain)
         } else if args.interactive {
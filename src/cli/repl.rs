@@ -1,17 +1,19 @@
 use std::fmt::Display;
+use std::path::PathBuf;
 
 use minitt_util::io::history_file;
 use minitt_util::repl::{repl as repl_impl, MiniHelper, ReplEnvType};
 use rustyline::Editor;
 
+use voile::check::inline_metas;
 use voile::check::monad::{TCM, TCS as TCMS};
-use voile::check::{check_decls, inline_metas};
-use voile::syntax::abs::{trans_decls_contextual, trans_expr, Abs, TransState};
-use voile::syntax::surf::{parse_expr_err_printed, parse_str_err_printed, Decl};
+use voile::loader::Loader;
+use voile::syntax::abs::{trans_expr, Abs, TransState};
+use voile::syntax::core::Val;
+use voile::syntax::surf::{parse_expr_err_printed, parse_module_err_printed, Module};
 use voile_util::level::LiftEx;
-use voile_util::meta::MI;
 
-use crate::util::parse_file;
+use crate::util::{parse_file, report};
 
 type TCS = (TCMS, TransState);
 
@@ -32,18 +34,18 @@ const EVAL_PFX: &str = ":eval ";
 const LEVEL_PFX: &str = ":level ";
 
 fn show_gamma(tcs: &TCS) {
-    for val in &tcs.0.gamma {
+    for val in tcs.0.gamma.iter() {
         println!("val {};", val.ast);
     }
 }
 
 fn show_telescope(tcs: &TCS) {
-    for val in &tcs.0.env {
+    for val in tcs.0.env.iter() {
         println!("let {};", val.ast);
     }
 }
 
-fn work(tcs: TCS, current_mode: ReplEnvType, line: &str) -> Option<TCS> {
+fn work(tcs: TCS, current_mode: ReplEnvType, line: &str, search_path: &[PathBuf]) -> Option<TCS> {
     if line == QUIT_CMD {
         None
     } else if line.is_empty() {
@@ -61,12 +63,14 @@ fn work(tcs: TCS, current_mode: ReplEnvType, line: &str) -> Option<TCS> {
         help(current_mode);
         Some(tcs)
     } else if line.starts_with(LOAD_PFX) {
-        Some(
-            match parse_file(line.trim_start_matches(LOAD_CMD).trim_start()) {
-                Some(decls) => update_tcs(tcs, decls),
+        Some({
+            let mut loader = Loader::new(search_path.to_vec());
+            let file = line.trim_start_matches(LOAD_CMD).trim_start();
+            match parse_file(file, &mut loader.files) {
+                Some(module) => update_tcs(tcs, loader, module),
                 None => tcs,
-            },
-        )
+            }
+        })
     } else if line.starts_with(INFER_PFX) {
         Some(infer(tcs, line))
     } else if line.starts_with(LEVEL_PFX) {
@@ -78,22 +82,37 @@ fn work(tcs: TCS, current_mode: ReplEnvType, line: &str) -> Option<TCS> {
         println!("Maybe you want to get some `:help`?");
         Some(tcs)
     } else {
-        Some(match parse_str_err_printed(line).ok() {
-            Some(decls) => update_tcs(tcs, decls),
+        Some(match parse_module_err_printed(line).ok() {
+            Some(module) => update_tcs(tcs, Loader::new(search_path.to_vec()), module),
             None => tcs,
         })
     }
 }
 
 fn infer(tcs: TCS, line: &str) -> TCS {
-    expression_thing(tcs, line, INFER_CMD, |tcms, abs| {
-        let (inferred, tcs) = tcms.infer(&abs)?;
-        inline_metas(tcs, inferred)
-    })
+    expression_thing(tcs, line, INFER_CMD, infer_abs)
+}
+
+/// The type of `abs`, with the metas and the universe variables solved.
+pub fn infer_abs(tcs: TCMS, abs: Abs) -> TCM<(Val, TCMS)> {
+    let (inferred, tcs) = tcs.infer(&abs)?;
+    let (inferred, tcs) = inline_metas(tcs.require_no_postponed()?, inferred)?;
+    Ok((tcs.zonk_universes(inferred.ast), tcs))
 }
 
 fn eval(tcs: TCS, line: &str) -> TCS {
-    expression_thing(tcs, line, EVAL_CMD, |tcms, abs| Ok(tcms.evaluate(abs)))
+    expression_thing(tcs, line, EVAL_CMD, |tcms, abs| Ok(evaluate_abs(tcms, abs)))
+}
+
+/// The value of `abs`, with the universe variables at their least levels.
+pub fn evaluate_abs(tcs: TCMS, abs: Abs) -> (Val, TCMS) {
+    // Inferring finds the universe variables, but `abs` may be only checkable.
+    let tcs = match tcs.clone().infer(&abs) {
+        Ok((_, inferred)) => inferred,
+        Err(_) => tcs,
+    };
+    let (value, tcs) = tcs.evaluate(abs);
+    (tcs.zonk_universes(value.ast), tcs)
 }
 
 fn level(tcs: TCS, line: &str) -> TCS {
@@ -121,33 +140,26 @@ fn expression_thing<T: Display>(
     }
 }
 
-fn update_tcs(tcs: TCS, decls: Vec<Decl>) -> TCS {
-    let mut state = tcs.1;
-    state.meta_count = MI(tcs.0.meta_context.solutions().len());
-    let state = trans_decls_contextual(state, decls)
-        .map_err(|err| eprintln!("{}", err))
-        .unwrap_or_default();
-    let mut telescope = tcs.0;
-    telescope
-        .meta_context
-        .expand_with_fresh_meta(state.meta_count);
-    let tcs = check_decls(telescope, state.decls.clone())
-        .map_err(|err| eprintln!("{}", err))
-        .unwrap_or_default();
-    (tcs, state)
+fn update_tcs(tcs: TCS, mut loader: Loader, module: Module) -> TCS {
+    loader.load_module(tcs, module).unwrap_or_else(|err| {
+        report(err, &loader.files, false);
+        Default::default()
+    })
 }
 
 pub fn code_to_abs(tcs: &mut TCS, code: &str) -> Option<Abs> {
     let trans_state = &mut tcs.1;
-    trans_state.meta_count = MI(tcs.0.meta_context.solutions().len());
-    trans_expr(
+    trans_state.meta_count = tcs.0.meta_context.meta_count();
+    let abs = trans_expr(
         parse_expr_err_printed(code).ok()?,
         &trans_state.decls,
         &mut trans_state.meta_count,
         &trans_state.context_mapping,
     )
     .map_err(|err| eprintln!("{}", err))
-    .ok()
+    .ok()?;
+    (tcs.0.meta_context).expand_with_fresh_meta(trans_state.meta_count);
+    Some(abs)
 }
 
 #[allow(clippy::print_literal)]
@@ -207,7 +219,7 @@ fn create_editor() -> Editor<MiniHelper> {
     ])
 }
 
-pub fn repl(tcs: TCS, repl_kind: Option<ReplEnvType>) {
+pub fn repl(tcs: TCS, search_path: Vec<PathBuf>, repl_kind: Option<ReplEnvType>) {
     if let Some(kind) = repl_kind {
         let history = || history_file("voilec").ok();
         repl_impl(
@@ -217,7 +229,7 @@ pub fn repl(tcs: TCS, repl_kind: Option<ReplEnvType>) {
             create_editor,
             history,
             welcome_message,
-            work,
+            |tcs, mode, line| work(tcs, mode, line, &search_path),
         );
     }
 }
@@ -0,0 +1,60 @@
+use voile::check::monad::TCS as TCMS;
+use voile::loader::Loader;
+use voile::syntax::abs::TransState;
+use voile::syntax::surf::parse_str_module;
+
+use crate::repl::{code_to_abs, evaluate_abs, infer_abs};
+
+/// Like `:infer` in the REPL, in an empty context.
+fn infer(code: &str) -> String {
+    let mut tcs: (TCMS, TransState) = Default::default();
+    let abs = code_to_abs(&mut tcs, code).unwrap();
+    let (inferred, _) = infer_abs(tcs.0, abs).unwrap();
+    inferred.to_string()
+}
+
+/// Like `:eval` in the REPL and `-e`, in an empty context.
+fn evaluate(code: &str) -> String {
+    let mut tcs: (TCMS, TransState) = Default::default();
+    let abs = code_to_abs(&mut tcs, code).unwrap();
+    evaluate_abs(tcs.0, abs).0.to_string()
+}
+
+/// Like `:eval` in the REPL, after loading `module`.
+fn evaluate_in(module: &str, code: &str) -> String {
+    let module = parse_str_module(module).unwrap();
+    let mut tcs = Loader::new(vec![])
+        .load_module(Default::default(), module)
+        .unwrap();
+    let abs = code_to_abs(&mut tcs, code).unwrap();
+    evaluate_abs(tcs.0, abs).0.to_string()
+}
+
+#[test]
+fn infer_universe() {
+    assert_eq!(infer("Type"), "set1");
+}
+
+#[test]
+fn evaluate_universe() {
+    assert_eq!(evaluate("Type"), "set0");
+}
+
+#[test]
+fn infer_ascription() {
+    assert_eq!(infer(r"((\x. x) : Type -> Type)"), "(set0 -> set0)");
+    assert_eq!(infer(r"((\x. x) : Type -> Type) Type"), "set1");
+    assert_eq!(evaluate(r"((\x. x) : Type -> Type)"), r"(\ [0])");
+}
+
+/// Applying a lambda leaves the recursive references in its body folded.
+#[test]
+fn evaluate_recursive_application() {
+    let module = "data Nat { Z : Rec {}; S : Nat; }\n\
+                  data List (A : Type) { Nil : Rec {}; Cons : A, List A; }";
+    let nat = "Sum {S: [|0|], Z: Rec {}}";
+    assert_eq!(
+        evaluate_in(module, r"(\A. List A) Nat"),
+        format!("Sum {{Cons: ({}, ([|1|] {})), Nil: Rec {{}}}}", nat, nat)
+    );
+}
@@ -1,14 +1,53 @@
+use std::path::{Path, PathBuf};
 use std::str;
 
 use minitt_util::io::read_file;
 
-use voile::syntax::surf::{parse_str_err_printed, Decl};
+use voile::check::monad::{TCE, TCW};
+use voile::diagnostic::{Diagnostic, Files};
+use voile::syntax::surf::{parse_module_in, Module};
 
-pub fn parse_file(file_arg: &str) -> Option<Vec<Decl>> {
+/// The file is registered in `files` for error reporting.
+pub fn parse_file(file_arg: &str, files: &mut Files) -> Option<Module> {
     // If cannot read input, return.
     let file_content = read_file(file_arg)?;
     // Read file
     let file_content_utf8 = str::from_utf8(file_content.as_slice()).unwrap();
+    let file = files.add(file_arg.to_owned(), file_content_utf8.to_owned());
     // Parse
-    parse_str_err_printed(file_content_utf8).ok()
+    parse_module_in(file, file_content_utf8)
+        .map_err(|err| eprintln!("{}", err))
+        .ok()
+}
+
+/// Print the error(s) with source snippets, or as JSON (one line each).
+pub fn report(err: TCE, files: &Files, json: bool) {
+    print(Diagnostic::from_tce(err), files, json)
+}
+
+/// Like [`report`], for the warnings.
+pub fn warn(warnings: Vec<TCW>, files: &Files, json: bool) {
+    let diagnostics = warnings.into_iter().map(Diagnostic::from_tcw);
+    print(diagnostics.collect(), files, json)
+}
+
+fn print(diagnostics: Vec<Diagnostic>, files: &Files, json: bool) {
+    for diagnostic in diagnostics {
+        if json {
+            eprintln!("{}", diagnostic.to_json(files));
+        } else {
+            eprintln!("{}", diagnostic.render(files));
+        }
+    }
+}
+
+/// The directory of the input file comes first, then the `--include`d ones.
+pub fn search_path(file_arg: Option<&String>, include: &[String]) -> Vec<PathBuf> {
+    let file_dir = file_arg
+        .and_then(|file| Path::new(file).parent())
+        .map(Path::to_path_buf);
+    let current_dir = || PathBuf::from(".");
+    let mut search_path = vec![file_dir.unwrap_or_else(current_dir)];
+    search_path.extend(include.iter().map(PathBuf::from));
+    search_path
 }
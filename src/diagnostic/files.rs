@@ -0,0 +1,30 @@
+use voile_util::loc::FileId;
+
+/// Names and contents of the source files.
+/// `FileId(0)` is reserved for code from nowhere, like REPL inputs.
+#[derive(Debug, Clone, Default)]
+pub struct Files {
+    files: Vec<(String, String)>,
+}
+
+impl Files {
+    /// Register a source file, returns its id.
+    pub fn add(&mut self, name: String, source: String) -> FileId {
+        self.files.push((name, source));
+        FileId(self.files.len())
+    }
+
+    fn get(&self, file: FileId) -> Option<&(String, String)> {
+        file.0
+            .checked_sub(1)
+            .and_then(|index| self.files.get(index))
+    }
+
+    pub fn name(&self, file: FileId) -> Option<&str> {
+        self.get(file).map(|(name, _)| name.as_str())
+    }
+
+    pub fn source(&self, file: FileId) -> Option<&str> {
+        self.get(file).map(|(_, source)| source.as_str())
+    }
+}
@@ -0,0 +1,13 @@
+pub use self::files::*;
+pub use self::report::*;
+
+/// Source files, identified by `FileId`s in `Loc`s.
+mod files;
+/// Showing diagnostics as source snippets with carets, or as JSON.
+mod render;
+/// Diagnostics converted from type-checking errors,
+/// with labelled locations and stable error codes.
+mod report;
+
+#[cfg(test)]
+mod tests;
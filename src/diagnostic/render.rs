@@ -0,0 +1,118 @@
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+use voile_util::loc::Loc;
+
+use super::{Diagnostic, Files, Label};
+
+impl Diagnostic {
+    /// Human-readable report, showing the labelled lines of `files` like:
+    ///
+    /// ```text
+    /// error[E0020]: Look up failed for `b`.
+    ///  --> a.voile:1:9
+    ///   |
+    /// 1 | let a = b;
+    ///   |         ^ not found
+    /// ```
+    pub fn render(&self, files: &Files) -> String {
+        let width = (self.primary.iter())
+            .chain(&self.secondary)
+            .map(|label| label.loc.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let severity = self.severity.name();
+        let mut out = format!("{}[{}]: {}\n", severity, self.code, self.message);
+        if let Some(primary) = &self.primary {
+            render_label(&mut out, files, primary, true, width);
+        }
+        for label in &self.secondary {
+            render_label(&mut out, files, label, false, width);
+        }
+        for note in &self.notes {
+            writeln!(out, "{:w$} = note: {}", "", note, w = width).unwrap();
+        }
+        out
+    }
+
+    /// Machine-readable report, a JSON object in one line.
+    pub fn to_json(&self, files: &Files) -> String {
+        let labels = (self.primary.iter().map(|label| (label, true)))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+            .map(|(label, primary)| label_json(files, label, primary))
+            .collect::<Vec<_>>();
+        let json = json!({
+            "code": self.code,
+            "message": self.message,
+            "severity": self.severity.name(),
+            "labels": labels,
+            "notes": self.notes,
+        });
+        json.to_string()
+    }
+}
+
+/// Generated locations and locations parsed from nowhere are not shown.
+fn is_known(loc: &Loc) -> bool {
+    !loc.is_generated && loc.line > 0
+}
+
+/// The line of `loc`, and how many characters to underline.
+fn snippet<'a>(files: &'a Files, loc: &Loc) -> Option<(&'a str, usize)> {
+    let source = files.source(loc.file)?;
+    let line = source.lines().nth(loc.line - 1)?;
+    let line_end = (source.get(loc.start..)?.find('\n')).map_or(source.len(), |i| loc.start + i);
+    let underlined = source.get(loc.start..loc.end.min(line_end).max(loc.start))?;
+    Some((line, underlined.chars().count().max(1)))
+}
+
+fn render_label(out: &mut String, files: &Files, label: &Label, primary: bool, width: usize) {
+    let loc = &label.loc;
+    if !is_known(loc) {
+        return;
+    }
+    let name = files.name(loc.file).unwrap_or("<input>");
+    let arrow = if primary { "-->" } else { ":::" };
+    writeln!(
+        out,
+        "{:w$}{} {}:{}:{}",
+        "",
+        arrow,
+        name,
+        loc.line,
+        loc.col,
+        w = width
+    )
+    .unwrap();
+    if let Some((line, length)) = snippet(files, loc) {
+        let marker = if primary { "^" } else { "-" };
+        let indent = " ".repeat(loc.col.saturating_sub(1));
+        writeln!(out, "{:w$} |", "", w = width).unwrap();
+        writeln!(out, "{:>w$} | {}", loc.line, line, w = width).unwrap();
+        let underline = format!("{}{} {}", indent, marker.repeat(length), label.message);
+        writeln!(out, "{:w$} | {}", "", underline.trim_end(), w = width).unwrap();
+    }
+}
+
+/// One-based line and column of the end of `loc`.
+fn end_position(files: &Files, loc: &Loc) -> Option<(usize, usize)> {
+    let before = files.source(loc.file)?.get(..loc.end)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    Some((line, before[line_start..].chars().count() + 1))
+}
+
+fn label_json(files: &Files, label: &Label, primary: bool) -> Value {
+    let loc = &label.loc;
+    let file = files.name(loc.file).filter(|_| is_known(loc));
+    let (end_line, end_col) = end_position(files, loc).unwrap_or((loc.line, loc.col));
+    json!({
+        "file": file,
+        "line": loc.line,
+        "column": loc.col,
+        "end_line": end_line,
+        "end_column": end_col,
+        "primary": primary,
+        "message": label.message,
+    })
+}
@@ -0,0 +1,245 @@
+use voile_util::loc::Loc;
+use voile_util::tags::VarRec;
+
+use crate::check::monad::{missing_cases, Constraint, TCE, TCW};
+
+/// A location with some explanation.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Label {
+    pub loc: Loc,
+    /// Can be empty.
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(loc: Loc, message: impl Into<String>) -> Self {
+        Self {
+            loc,
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors stop the checking of a declaration, warnings don't.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// An error report, converted from a [`TCE`](crate::check::monad::TCE).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// See [`TCE::code`](crate::check::monad::TCE::code).
+    pub code: &'static str,
+    /// What's wrong, without locations.
+    pub message: String,
+    /// Where it's wrong, unknown for some errors.
+    pub primary: Option<Label>,
+    /// Other relevant locations.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+const WHEN_CHECKING: &str = "when checking this expression";
+
+impl Diagnostic {
+    /// One diagnostic for each error combined in `err`.
+    pub fn from_tce(err: TCE) -> Vec<Self> {
+        let mut diagnostics = Vec::new();
+        collect(err, &mut Vec::new(), &mut diagnostics);
+        diagnostics
+    }
+
+    /// A warning, collected in [`TCS::warnings`](crate::check::monad::TCS::warnings).
+    pub fn from_tcw(warning: TCW) -> Self {
+        let message = match &warning {
+            TCW::UnreachableBranch(label) => format!(
+                "Unreachable branch of `{}`, it's already covered.",
+                label.text
+            ),
+        };
+        Self {
+            severity: Severity::Warning,
+            code: warning.code(),
+            message,
+            primary: Some(Label::new(warning.loc(), "")),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    fn from_single(err: TCE) -> Self {
+        let code = err.code();
+        // The innermost `Wrapped` is the most relevant context.
+        let mut context = None;
+        let mut err = err;
+        while let TCE::Wrapped(inner, loc) = err {
+            context = Some(loc);
+            err = *inner;
+        }
+
+        let mut secondary = Vec::new();
+        let mut notes = Vec::new();
+        let primary = match &err {
+            TCE::ReDefine(new, old) => {
+                secondary.push(Label::new(*old, "previously defined here"));
+                Some(Label::new(*new, "redefined here"))
+            }
+            TCE::LookUpFailed(ident) => Some(Label::new(ident.loc, "not found")),
+            TCE::ModuleNotFound(ident) => Some(Label::new(ident.loc, "imported here")),
+            TCE::CyclicImport(ident, chain) => {
+                notes.push(format!("import chain: {}", chain.join(" -> ")));
+                Some(Label::new(ident.loc, "imported here"))
+            }
+            TCE::ModuleNameMismatch(header, _) => Some(Label::new(header.loc, "declared here")),
+            TCE::ImplementImported(name, _) => Some(Label::new(name.loc, "implemented here")),
+            TCE::NonTerminating(loc, path) => {
+                notes.push(format!("call path: {}", path.join(" -> ")));
+                Some(Label::new(*loc, "in this definition"))
+            }
+            TCE::StuckConstraints(constraints) => {
+                let blocked = |c: &Constraint| {
+                    let metas = c.blockers.iter().map(|mi| format!("?{}", mi));
+                    metas.collect::<Vec<_>>().join(", ")
+                };
+                notes.extend(constraints.iter().map(|constraint| {
+                    format!("stuck: {}, blocked on {}", constraint, blocked(constraint))
+                }));
+                None
+            }
+            err => err.loc().map(|loc| Label::new(loc, "")),
+        };
+        let primary = match (primary, context) {
+            (Some(primary), Some(context)) => {
+                if context != primary.loc {
+                    secondary.push(Label::new(context, WHEN_CHECKING));
+                }
+                Some(primary)
+            }
+            (None, context) => context.map(|loc| Label::new(loc, WHEN_CHECKING)),
+            (primary, None) => primary,
+        };
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message(&err),
+            primary,
+            secondary,
+            notes,
+        }
+    }
+}
+
+fn collect(err: TCE, modules: &mut Vec<String>, diagnostics: &mut Vec<Diagnostic>) {
+    match err {
+        TCE::Errors(errors) => {
+            for err in errors {
+                collect(err, modules, diagnostics);
+            }
+        }
+        TCE::InModule(inner, name) => {
+            modules.push(name);
+            collect(*inner, modules, diagnostics);
+            modules.pop();
+        }
+        err => {
+            let mut diagnostic = Diagnostic::from_single(err);
+            let modules = modules.iter().rev();
+            (diagnostic.notes).extend(modules.map(|name| format!("in module `{}`", name)));
+            diagnostics.push(diagnostic);
+        }
+    }
+}
+
+/// Like the `Display` of `TCE`, but without the locations,
+/// which are shown as labels instead.
+fn message(err: &TCE) -> String {
+    use TCE::*;
+    match err {
+        Textual(text) => text.clone(),
+        CannotInfer(_, abs) => format!("Could not infer type of: `{}`.", abs),
+        CannotUnify(a, b) => format!("Cannot unify `{}` with `{}`.", a, b),
+        NotSigma(_, val) => format!("Expected a sigma type expression, got: `{}`.", val),
+        NotPi(_, val) => format!("Expected a pi type expression (function), got: `{}`.", val),
+        NotSubtype(sub, sup) => format!("Expected `{}` to be the subtype of `{}`.", sub, sup),
+        NotTypeAbs(_, abs) => format!("Expected a type expression, got: `{}`.", abs),
+        NotTypeVal(_, val) => format!("Expected a type expression, got: `{}`.", val),
+        NotRowType(VarRec::Record, _, val) => {
+            format!("Expected a record type expression, got: `{}`.", val)
+        }
+        NotRowType(VarRec::Variant, _, val) => {
+            format!("Expected a variant type expression, got: `{}`.", val)
+        }
+        NotEmpty(_, val) => format!("Expected an empty type expression, got: `{}`.", val),
+        NotRecVal(_, val) => format!("Expected a record expression, got: `{}`.", val),
+        NotUniverseVal(_, val) => format!("Expected an universe expression, got: `{}`.", val),
+        NotLevel(val) => format!("Expected a universe level, got: `{}`.", val),
+        TypeNotInGamma(..) => "Type info not in Gamma.".to_owned(),
+        OverlappingVariant(_, variant) => format!("Duplicated variant: `{}`.", variant),
+        DuplicateField(_, field) => format!("Duplicated field: `{}`.", field),
+        UnexpectedVariant(_, variant) => format!("Unexpected variant: `{}`.", variant),
+        DependedField(_, field, dependent) => format!(
+            "Cannot remove field `{}`, the type of field `{}` depends on it.",
+            field, dependent
+        ),
+        CyclicFields(_, fields) => format!(
+            "The types of fields `{}` refer to each other.",
+            fields.join("`, `")
+        ),
+        ConsArity(_, label, expected, actual) => format!(
+            "Constructor `@{}` takes {} argument(s), got {}.",
+            label, expected, actual
+        ),
+        LevelMismatch(_, small, big) => format!(
+            "Expression has level {}, which is not smaller than {}.",
+            small, big
+        ),
+        LookUpFailed(ident) => format!("Look up failed for `{}`.", ident.text),
+        NoSuchImplicit(name, ty) => {
+            format!("No implicit parameter named `{}` in `{}`.", name.text, ty)
+        }
+        ReDefine(..) => "This definition will hide a previous one.".to_owned(),
+        ModuleNotFound(ident) => format!("Cannot find module `{}`.", ident.text),
+        CyclicImport(ident, _) => format!("Cyclic import of module `{}`.", ident.text),
+        ModuleNameMismatch(header, expected) => format!(
+            "Module declared as `{}`, but imported as `{}`.",
+            header.text, expected
+        ),
+        ImplementImported(name, module) => format!(
+            "Cannot implement `{}`, it's postulated in module `{}`.",
+            name.text, module
+        ),
+        NonExhaustive(_, missing) => format!("Missing case(s): {}.", missing_cases(missing)),
+        NonTerminating(_, path) => format!("`{}` may not terminate.", path[0]),
+        NegativeOccurrence(_, name) => {
+            format!("`{}` occurs negatively in its definition.", name)
+        }
+        UnreachableClause(..) => "Unreachable clause.".to_owned(),
+        ArityMismatch(_, expected, actual) => {
+            format!("Expected {} pattern(s), got {}.", expected, actual)
+        }
+        NonLinearPattern(var) => {
+            format!("Pattern variable `{}` is bound more than once.", var.text)
+        }
+        StuckConstraints(constraints) => format!(
+            "Cannot solve {} constraint(s) blocked on unsolved metas.",
+            constraints.len()
+        ),
+        MetaWithNonVar(..) => "Failed to solve meta: \
+                               anticipated solution contains unexpected non-bound values."
+            .to_owned(),
+        // Other errors have no location in their messages.
+        err => err.to_string(),
+    }
+}
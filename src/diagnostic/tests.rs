@@ -0,0 +1,88 @@
+use serde_json::{json, Value};
+use voile_util::loc::{Ident, Loc};
+
+use crate::check::monad::{TCE, TCW};
+use crate::syntax::abs::trans_decls_recovering;
+use crate::syntax::surf::parse_module_in;
+
+use super::{Diagnostic, Files, Severity};
+
+fn diagnostics(files: &mut Files, code: &str) -> Vec<Diagnostic> {
+    let file = files.add("a.voile".to_owned(), code.to_owned());
+    let module = parse_module_in(file, code).unwrap();
+    let (_, errors) = trans_decls_recovering(Default::default(), module.decls);
+    let err = TCE::from_errors(errors).unwrap_err();
+    Diagnostic::from_tce(TCE::InModule(Box::new(err), "A".to_owned()))
+}
+
+#[test]
+fn render_snippets() {
+    let mut files = Files::default();
+    let code = "val a : Type;\nlet a = b;\nval a : Type1;";
+    let diagnostics = diagnostics(&mut files, code);
+    assert_eq!(2, diagnostics.len());
+    assert_eq!(diagnostics[0].code, "E0020");
+    assert_eq!(
+        diagnostics[0].render(&files),
+        "error[E0020]: Look up failed for `b`.\n \
+         --> a.voile:2:9\n  \
+         |\n\
+         2 | let a = b;\n  \
+         |         ^ not found\n  \
+         = note: in module `A`\n"
+    );
+    let redefine = diagnostics[1].render(&files);
+    println!("{}", redefine);
+    assert!(redefine.contains("3 | val a : Type1;\n  |     ^ redefined here"));
+    assert!(redefine.contains("::: a.voile:1:5"));
+    assert!(redefine.contains("1 | val a : Type;\n  |     - previously defined here"));
+}
+
+#[test]
+fn render_json() {
+    let mut files = Files::default();
+    let diagnostics = diagnostics(&mut files, "let a = b;");
+    let json = diagnostics[0].to_json(&files);
+    println!("{}", json);
+    assert!(!json.contains('\n'));
+    let json: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["code"], "E0020");
+    assert_eq!(json["message"], "Look up failed for `b`.");
+    let label = &json["labels"][0];
+    assert_eq!((&label["line"], &label["column"]), (&json!(1), &json!(9)));
+    assert_eq!(
+        (&label["end_line"], &label["end_column"]),
+        (&json!(1), &json!(10))
+    );
+    assert_eq!(json["notes"], json!(["in module `A`"]));
+
+    let textual = TCE::Textual("\"Quoted\"\n\tand escaped".to_owned());
+    let json = Diagnostic::from_tce(textual)[0].to_json(&files);
+    assert_eq!(
+        json,
+        r#"{"code":"E0001","labels":[],"message":"\"Quoted\"\n\tand escaped","notes":[],"severity":"error"}"#
+    );
+}
+
+#[test]
+fn render_warnings() {
+    let mut files = Files::default();
+    let code = "let a = case A u: u or case A v: v or whatever;";
+    let file = files.add("a.voile".to_owned(), code.to_owned());
+    let loc = Loc {
+        file,
+        line: 1,
+        col: 29,
+        start: 28,
+        end: 29,
+        ..Default::default()
+    };
+    let warning = TCW::UnreachableBranch(Ident::new(loc, "A".to_owned()));
+    let diagnostic = Diagnostic::from_tcw(warning);
+    assert_eq!(diagnostic.severity, Severity::Warning);
+    let rendered = diagnostic.render(&files);
+    assert!(rendered.starts_with("warning[W0001]: Unreachable branch of `A`"));
+    assert!(diagnostic
+        .to_json(&files)
+        .contains(r#""severity":"warning""#));
+}
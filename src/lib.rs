@@ -197,3 +197,9 @@ pub mod syntax;
 
 /// Type-Checking module.
 pub mod check;
+
+/// Module system: `import` resolution and loading.
+pub mod loader;
+
+/// Error reports with source snippets, error codes, or as JSON.
+pub mod diagnostic;
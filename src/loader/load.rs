@@ -0,0 +1,148 @@
+use std::fs::read_to_string;
+use std::mem::{replace, take};
+use std::path::PathBuf;
+
+use crate::check::check_decls_recovering;
+use crate::check::monad::{TCE, TCM, TCS};
+use crate::diagnostic::Files;
+use crate::syntax::abs::{trans_decls_recovering, GlobCtx, TransState};
+use crate::syntax::surf::{parse_module_in, Decl, Import, Module};
+
+/// Type-checking state together with the translation state.
+pub type LoadState = (TCS, TransState);
+
+/// Module loader.
+///
+/// Modules are identified by their dot-separated names,
+/// where `Data.Bool` is looked up as `Data/Bool.voile` in each directory of
+/// the search path.
+/// Loaded modules are recorded in [`TransState::modules`](TransState),
+/// so they're translated and type-checked only once.
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+    /// Directories to look for imported modules, in order.
+    pub search_path: Vec<PathBuf>,
+    /// Modules being loaded, used for detecting cyclic imports.
+    loading: Vec<String>,
+    /// Sources of the loaded modules, for error reporting.
+    pub files: Files,
+}
+
+impl Loader {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self {
+            search_path,
+            loading: Vec::new(),
+            files: Default::default(),
+        }
+    }
+
+    /// Find the file of the module named `name` in the search path.
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let mut relative: PathBuf = name.split('.').collect();
+        relative.set_extension("voile");
+        self.search_path
+            .iter()
+            .map(|dir| dir.join(&relative))
+            .find(|path| path.is_file())
+    }
+
+    /// Load a module (its imports and declarations) into the current scope,
+    /// used for the main file and the REPL.
+    pub fn load_module(&mut self, state: LoadState, module: Module) -> TCM<LoadState> {
+        let state = self.import_all(state, module.imports)?;
+        check_new_decls(state, module.decls)
+    }
+
+    /// Load the imported modules if they're not yet loaded,
+    /// then bring their definitions into the current scope.
+    pub fn import_all(&mut self, state: LoadState, imports: Vec<Import>) -> TCM<LoadState> {
+        imports
+            .into_iter()
+            .try_fold(state, |state, import| self.import(state, import))
+    }
+
+    fn import(&mut self, state: LoadState, import: Import) -> TCM<LoadState> {
+        let (tcs, mut trans) = if state.1.modules.contains_key(&import.name.text) {
+            state
+        } else {
+            self.load_imported(state, &import)?
+        };
+        let exports = &trans.modules[&import.name.text];
+        let mut scope = take(&mut trans.context_mapping);
+        for (name, gi) in exports {
+            match scope.get(name) {
+                Some(old) if old != gi => {
+                    return Err(TCE::ReDefine(import.name.loc, trans.names[old.0].loc));
+                }
+                _ => {}
+            }
+            scope.insert(name.clone(), *gi);
+            scope.insert(format!("{}.{}", import.name.text, name), *gi);
+            if let Some(alias) = &import.alias {
+                scope.insert(format!("{}.{}", alias.text, name), *gi);
+            }
+        }
+        trans.context_mapping = scope;
+        Ok((tcs, trans))
+    }
+
+    fn load_imported(&mut self, state: LoadState, import: &Import) -> TCM<LoadState> {
+        let name = &import.name.text;
+        if self.loading.contains(name) {
+            let mut chain = self.loading.clone();
+            chain.push(name.clone());
+            return Err(TCE::CyclicImport(import.name.clone(), chain));
+        }
+        let path = self
+            .resolve(name)
+            .ok_or_else(|| TCE::ModuleNotFound(import.name.clone()))?;
+        let source = read_to_string(&path)
+            .map_err(|err| TCE::Textual(format!("Cannot read `{}`: {}", path.display(), err)))?;
+        let file = self.files.add(path.display().to_string(), source);
+        let source = self.files.source(file).unwrap();
+        let module = parse_module_in(file, source).map_err(TCE::Textual)?;
+        match &module.name {
+            Some(header) if &header.text != name => {
+                return Err(TCE::ModuleNameMismatch(header.clone(), name.clone()));
+            }
+            _ => {}
+        }
+
+        // Every module has its own scope.
+        let (tcs, mut trans) = state;
+        let outer_scope = take(&mut trans.context_mapping);
+        self.loading.push(name.clone());
+        let Module { imports, decls, .. } = module;
+        let loaded = self.import_all((tcs, trans), imports).and_then(|state| {
+            let first_own = state.1.decl_count;
+            check_new_decls(state, decls).map(|state| (state, first_own))
+        });
+        self.loading.pop();
+        let ((mut tcs, mut trans), first_own) =
+            loaded.map_err(|err| TCE::InModule(Box::new(err), name.clone()))?;
+        // Only the warnings of the module being checked are shown.
+        tcs.warnings.clear();
+
+        let scope = replace(&mut trans.context_mapping, outer_scope);
+        let exports: GlobCtx = (scope.into_iter())
+            .filter(|(_, gi)| *gi >= first_own)
+            .collect();
+        trans.modules.insert(name.clone(), exports);
+        Ok((tcs, trans))
+    }
+}
+
+/// Translate and type-check `decls` in the current scope.
+/// Declarations already in the state are not checked again.
+/// Errors in all declarations are reported.
+pub fn check_new_decls((tcs, mut trans): LoadState, decls: Vec<Decl>) -> TCM<LoadState> {
+    trans.meta_count = tcs.meta_context.meta_count();
+    let checked = trans.decls.len();
+    let (trans, mut errors) = trans_decls_recovering(trans, decls);
+    let mut tcs = tcs;
+    tcs.meta_context.expand_with_fresh_meta(trans.meta_count);
+    let (tcs, check_errors) = check_decls_recovering(tcs, trans.decls[checked..].to_vec());
+    errors.extend(check_errors);
+    TCE::from_errors(errors).map(|()| (tcs, trans))
+}
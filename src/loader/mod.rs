@@ -0,0 +1,8 @@
+pub use self::load::*;
+
+/// The module loader, which resolves `import`s in the search path,
+/// translates and type-checks every module once.
+mod load;
+
+#[cfg(test)]
+mod tests;
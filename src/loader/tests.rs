@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use crate::check::monad::TCE;
+use crate::syntax::surf::parse_str_module;
+
+use super::{LoadState, Loader};
+
+fn loader() -> Loader {
+    Loader::new(vec![
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("samples/module")
+    ])
+}
+
+fn load(code: &str) -> Result<LoadState, TCE> {
+    let module = parse_str_module(code).unwrap();
+    loader().load_module(Default::default(), module)
+}
+
+#[test]
+fn resolve_module() {
+    let loader = loader();
+    assert!(loader.resolve("Data.Bool").is_some());
+    assert!(loader.resolve("Data.Nothing").is_none());
+}
+
+#[test]
+fn qualified_names() {
+    let (_, trans) = load("import Data.Bool as B;\nlet t = B.not (Data.Bool.not B.true);").unwrap();
+    for name in &["not", "B.not", "Data.Bool.not", "t"] {
+        assert!(trans.context_mapping.contains_key(*name), "{}", name);
+    }
+    // Not re-exported by `Data.Bool`.
+    assert!(!trans.context_mapping.contains_key("Unit"));
+}
+
+#[test]
+fn module_not_found() {
+    match load("import Data.Nothing;") {
+        Err(TCE::ModuleNotFound(name)) => assert_eq!(name.text, "Data.Nothing"),
+        other => panic!("Unexpected: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn cyclic_import() {
+    let mut err = load("import Cyclic.Ping;").map(|_| ()).unwrap_err();
+    while let TCE::InModule(inner, _) = err {
+        err = *inner;
+    }
+    match err {
+        TCE::CyclicImport(name, chain) => {
+            assert_eq!(name.text, "Cyclic.Ping");
+            assert_eq!(chain, vec!["Cyclic.Ping", "Cyclic.Pong", "Cyclic.Ping"]);
+        }
+        other => panic!("Unexpected: {:?}", other),
+    }
+}
+
+#[test]
+fn implement_imported() {
+    match load("import Data.Empty;\nlet absurd = \\A e. absurd A e;") {
+        Err(TCE::ImplementImported(name, module)) => {
+            assert_eq!(name.text, "absurd");
+            assert_eq!(module, "Data.Empty");
+        }
+        other => panic!("Unexpected: {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+use voile::check::check_decls_recovering;
+use voile::check::monad::{TCE, TCW};
+use voile::loader::Loader;
+use voile::syntax::abs::trans_decls_recovering;
+use voile::syntax::surf::{parse_str_module, Import};
+use voile_util::loc::{Ident, Loc};
+use voile_util::uid::GI;
+
+/// What we know about a document after checking it.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    /// LSP `Diagnostic` objects.
+    pub diagnostics: Vec<Value>,
+    /// Names of the declarations in this document.
+    pub names: Vec<(Ident, GI)>,
+    /// References to global definitions in this document.
+    pub refs: Vec<(Ident, GI)>,
+    /// Pretty-printed types of global definitions,
+    /// the broken ones have postulated types.
+    pub types: Vec<String>,
+    /// Key: imported definitions; Value: module name and definition name.
+    pub imported: BTreeMap<GI, (String, String)>,
+}
+
+impl Analysis {
+    /// The reference or declaration name at `offset`.
+    pub fn ident_at(&self, offset: usize) -> Option<&(Ident, GI)> {
+        (self.refs.iter())
+            .chain(self.names.iter())
+            .find(|(ident, _)| ident.loc.start <= offset && offset <= ident.loc.end)
+    }
+
+    /// The declaration name in this document which defines `gi`.
+    pub fn name_of(&self, gi: GI) -> Option<&Ident> {
+        (self.names.iter())
+            .find(|(_, index)| *index == gi)
+            .map(|(ident, _)| ident)
+    }
+
+    fn error(&mut self, text: &str, imports: &[Import], err: TCE) {
+        if let TCE::Errors(errors) = err {
+            for err in errors {
+                self.error(text, imports, err);
+            }
+            return;
+        }
+        let loc = match &err {
+            // Point at the import that brings the error in.
+            TCE::InModule(_, name) => (imports.iter())
+                .find(|import| &import.name.text == name)
+                .map(|import| import.name.loc),
+            err => err.loc(),
+        };
+        let range = match loc {
+            Some(loc) => range(text, loc),
+            None => json!({ "start": origin(), "end": origin() }),
+        };
+        let diagnostic = self.diagnostic(range, err.to_string());
+        diagnostic["code"] = json!(err.code());
+    }
+
+    fn warning(&mut self, text: &str, warning: TCW) {
+        let range = range(text, warning.loc());
+        let diagnostic = self.diagnostic(range, warning.to_string());
+        diagnostic["severity"] = json!(2);
+        diagnostic["code"] = json!(warning.code());
+    }
+
+    fn diagnostic(&mut self, range: Value, message: String) -> &mut Value {
+        self.diagnostics.push(json!({
+            "range": range,
+            "severity": 1,
+            "source": "voile",
+            "message": message,
+        }));
+        self.diagnostics.last_mut().unwrap()
+    }
+}
+
+/// Parse, translate and type-check `text`,
+/// looking for imported modules in `search_path`.
+pub fn analyze(text: &str, search_path: Vec<PathBuf>) -> Analysis {
+    let mut analysis = Analysis::default();
+    let module = match parse_str_module(text) {
+        Ok(module) => module,
+        Err(err) => {
+            let position = parse_error_position(text, &err).unwrap_or_else(origin);
+            let range = json!({ "start": position, "end": position });
+            analysis.diagnostic(range, err);
+            return analysis;
+        }
+    };
+
+    let imports = module.imports.clone();
+    let mut loader = Loader::new(search_path);
+    let (mut tcs, mut trans) = match loader.import_all(Default::default(), module.imports) {
+        Ok(state) => state,
+        Err(err) => {
+            analysis.error(text, &imports, err);
+            return analysis;
+        }
+    };
+    for (module_name, exports) in &trans.modules {
+        for (name, gi) in exports {
+            let entry = (module_name.clone(), name.clone());
+            analysis.imported.insert(*gi, entry);
+        }
+    }
+
+    let first_own = trans.decls.len();
+    trans.meta_count = tcs.meta_context.meta_count();
+    let (trans, errors) = trans_decls_recovering(trans, module.decls.clone());
+    for err in errors {
+        analysis.error(text, &imports, err);
+    }
+    for decl in module.decls {
+        if let Some(gi) = trans.context_mapping.get(&decl.name.text) {
+            analysis.names.push((decl.name, *gi));
+        }
+    }
+    for decl in &trans.decls[first_own..] {
+        let refs = decl.refs().into_iter();
+        (analysis.refs).extend(refs.map(|(ident, gi)| (ident.clone(), gi)));
+    }
+
+    tcs.meta_context.expand_with_fresh_meta(trans.meta_count);
+    let (tcs, errors) = check_decls_recovering(tcs, trans.decls[first_own..].to_vec());
+    for err in errors {
+        analysis.error(text, &imports, err);
+    }
+    for warning in tcs.warnings.iter().cloned() {
+        analysis.warning(text, warning);
+    }
+    analysis.types = tcs.gamma.iter().map(|ty| ty.ast.to_string()).collect();
+    analysis
+}
+
+fn origin() -> Value {
+    json!({ "line": 0, "character": 0 })
+}
+
+/// Pest reports errors like ` --> 3:14` (one-based line and column),
+/// otherwise it's the code not consumed by the parser.
+fn parse_error_position(text: &str, message: &str) -> Option<Value> {
+    let unconsumed = "Does not consume the following code: '";
+    if let Some(rest) = message.strip_prefix(unconsumed) {
+        let rest = rest.strip_suffix('\'').unwrap_or(rest);
+        return Some(position(text, text.len().saturating_sub(rest.len())));
+    }
+    let position = message.split("--> ").nth(1)?.lines().next()?;
+    let mut numbers = position.trim().split(':').map(str::parse::<usize>);
+    let line = numbers.next()?.ok()?;
+    let column = numbers.next()?.ok()?;
+    Some(json!({ "line": line.saturating_sub(1), "character": column.saturating_sub(1) }))
+}
+
+/// LSP position (zero-based line and UTF-16 column) of the byte `offset`.
+pub fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+pub fn range(text: &str, loc: Loc) -> Value {
+    json!({ "start": position(text, loc.start), "end": position(text, loc.end) })
+}
+
+/// Byte offset of an LSP position, the inverse of [`position`].
+pub fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        }
+    };
+    let mut column = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if column >= character || c == '\n' {
+            return line_start + i;
+        }
+        column += c.len_utf16();
+    }
+    text.len()
+}
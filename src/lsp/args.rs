@@ -0,0 +1,19 @@
+use clap::AppSettings;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(
+    about = "Language server for Voile, communicating over stdio",
+    name = "voile-lsp",
+    global_settings(&[AppSettings::ColoredHelp])
+)]
+pub struct LspOptions {
+    /// Additional directories to look for imported modules
+    /// (the directory of the opened document is always searched first)
+    #[structopt(short = "I", long = "include", name = "DIR")]
+    pub include: Vec<String>,
+}
+
+pub fn pre() -> LspOptions {
+    LspOptions::from_args()
+}
@@ -0,0 +1,25 @@
+extern crate voile;
+
+use std::io::{stdin, stdout};
+use std::path::PathBuf;
+
+/// Inspecting documents: diagnostics, references and definitions.
+mod analysis;
+mod args;
+/// JSON-RPC messages with the `Content-Length` framing.
+mod protocol;
+/// Request handling and document management.
+mod server;
+
+#[cfg(test)]
+mod tests;
+
+fn main() {
+    let args = args::pre();
+    let include = args.include.iter().map(PathBuf::from).collect();
+    let (stdin, stdout) = (stdin(), stdout());
+    if let Err(err) = server::serve(stdin.lock(), stdout.lock(), include) {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use voile::loader::Loader;
+use voile::syntax::surf::parse_str_module;
+use voile_util::uid::GI;
+
+use crate::analysis::{analyze, offset, range, Analysis};
+use crate::protocol::*;
+
+/// An opened document.
+#[derive(Debug, Clone)]
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Server {
+    /// Additional directories to look for imported modules.
+    include: Vec<PathBuf>,
+    /// Key: document URI.
+    documents: BTreeMap<String, Document>,
+}
+
+/// Handle messages from `input` until the client sends `exit`.
+pub fn serve(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    include: Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut server = Server::new(include);
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(())
+}
+
+/// The path of a `file://` URI, with the percent-encoded bytes decoded.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded.get(i + 1..i + 3).filter(|_| encoded[i] == b'%');
+        match hex.and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// The `file://` URI of a path, the bytes not allowed in a path segment are percent-encoded.
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_owned();
+    for &byte in path.to_string_lossy().as_bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+impl Server {
+    pub fn new(include: Vec<PathBuf>) -> Self {
+        Self {
+            include,
+            documents: Default::default(),
+        }
+    }
+
+    /// The directory of the document comes first, then the included ones.
+    fn search_path(&self, uri: &str) -> Vec<PathBuf> {
+        let dir = uri_to_path(uri).and_then(|path| path.parent().map(Path::to_path_buf));
+        let mut search_path = vec![dir.unwrap_or_else(|| PathBuf::from("."))];
+        search_path.extend(self.include.iter().cloned());
+        search_path
+    }
+
+    /// Replies (responses and notifications) to the message.
+    pub fn handle(&mut self, message: Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // Full document synchronization.
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": {
+                    "name": "voile-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/hover" => self.hover(uri, params),
+            "textDocument/definition" => self.definition(uri, params),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                return vec![self.update(uri, text.to_owned())];
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                // No changes, the document is unchanged.
+                return match changes.and_then(|changes| changes.last()?["text"].as_str()) {
+                    Some(text) => vec![self.update(uri, text.to_owned())],
+                    None => Vec::new(),
+                };
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            }
+            _ => match message.get("id") {
+                Some(id) => {
+                    let err = format!("Unsupported method `{}`.", method);
+                    return vec![error_response(id.clone(), METHOD_NOT_FOUND, err)];
+                }
+                // Unknown notifications are ignored.
+                None => return Vec::new(),
+            },
+        };
+        match message.get("id") {
+            Some(id) => vec![response(id.clone(), result)],
+            None => Vec::new(),
+        }
+    }
+
+    /// Re-check the document and publish its diagnostics.
+    fn update(&mut self, uri: &str, text: String) -> Value {
+        let analysis = analyze(&text, self.search_path(uri));
+        let diagnostics = analysis.diagnostics.clone();
+        self.documents
+            .insert(uri.to_owned(), Document { text, analysis });
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    /// The document and the reference (or declaration name) under the cursor.
+    fn lookup(&self, uri: &str, params: &Value) -> Option<(&Document, usize)> {
+        let document = self.documents.get(uri)?;
+        let position = &params["position"];
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        Some((document, offset(&document.text, line, character)))
+    }
+
+    fn hover(&self, uri: &str, params: &Value) -> Value {
+        let hover = self.lookup(uri, params).and_then(|(document, offset)| {
+            let analysis = &document.analysis;
+            let (ident, gi) = analysis.ident_at(offset)?;
+            let ty = analysis.types.get(gi.0)?;
+            Some(json!({
+                "contents": {
+                    "kind": "plaintext",
+                    "value": format!("{} : {}", ident.text, ty),
+                },
+                "range": range(&document.text, ident.loc),
+            }))
+        });
+        hover.unwrap_or(Value::Null)
+    }
+
+    fn definition(&self, uri: &str, params: &Value) -> Value {
+        let location = self.lookup(uri, params).and_then(|(document, offset)| {
+            let (_, gi) = document.analysis.ident_at(offset)?;
+            match document.analysis.name_of(*gi) {
+                Some(name) => Some(json!({
+                    "uri": uri,
+                    "range": range(&document.text, name.loc),
+                })),
+                None => self.imported_definition(uri, &document.analysis, *gi),
+            }
+        });
+        location.unwrap_or(Value::Null)
+    }
+
+    /// Find the definition in the file of the imported module.
+    fn imported_definition(&self, uri: &str, analysis: &Analysis, gi: GI) -> Option<Value> {
+        let (module, name) = analysis.imported.get(&gi)?;
+        let path = Loader::new(self.search_path(uri)).resolve(module)?;
+        let text = read_to_string(&path).ok()?;
+        let decl = (parse_str_module(&text).ok()?.decls.into_iter())
+            .find(|decl| &decl.name.text == name)?;
+        Some(json!({
+            "uri": path_to_uri(&path),
+            "range": range(&text, decl.name.loc),
+        }))
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    let params = json!({ "uri": uri, "diagnostics": diagnostics });
+    notification("textDocument/publishDiagnostics", params)
+}
@@ -0,0 +1,177 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::protocol::{read_message, write_message};
+use crate::server::{path_to_uri, serve, uri_to_path};
+
+/// Send the messages to the server, followed by `shutdown` and `exit`,
+/// and collect everything the server replies.
+fn session(messages: Vec<Value>) -> Vec<Value> {
+    let mut input = Vec::new();
+    let shutdown = json!({ "jsonrpc": "2.0", "id": 233, "method": "shutdown" });
+    let exit = json!({ "jsonrpc": "2.0", "method": "exit" });
+    for message in messages.iter().chain(&[shutdown, exit]) {
+        write_message(&mut input, message).unwrap();
+    }
+    let mut output = Vec::new();
+    serve(Cursor::new(input), &mut output, Vec::new()).unwrap();
+    let mut output = Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Some(reply) = read_message(&mut output).unwrap() {
+        replies.push(reply);
+    }
+    assert_eq!(replies.last().unwrap()["id"], 233);
+    replies
+}
+
+fn sample_uri(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("samples/module");
+    format!("file://{}", path.join(name).display())
+}
+
+fn open(uri: &str, text: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": { "uri": uri, "languageId": "voile", "version": 1, "text": text },
+        },
+    })
+}
+
+fn request(id: u64, method: &str, uri: &str, line: u64, character: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": {
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character },
+        },
+    })
+}
+
+fn reply(replies: &[Value], id: u64) -> &Value {
+    let reply = replies.iter().find(|reply| reply["id"] == id).unwrap();
+    &reply["result"]
+}
+
+#[test]
+fn initialize() {
+    let initialize = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "capabilities": {} },
+    });
+    let unknown = json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol" });
+    let replies = session(vec![initialize, unknown]);
+    let capabilities = &reply(&replies, 1)["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["definitionProvider"], true);
+    let unknown = replies.iter().find(|reply| reply["id"] == 2).unwrap();
+    assert_eq!(unknown["error"]["code"], -32601);
+}
+
+#[test]
+fn diagnostics() {
+    let uri = sample_uri("broken.voile");
+    let code = "val a : Type;\nlet a = Typo;\n";
+    let replies = session(vec![
+        open(&uri, code),
+        open(&uri, "val a : Type;\nlet a = ;"),
+    ]);
+    let published = (replies.iter())
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .collect::<Vec<_>>();
+    assert_eq!(published.len(), 2);
+
+    let lookup = &published[0]["params"]["diagnostics"][0];
+    assert!(lookup["message"].as_str().unwrap().contains("Typo"));
+    assert_eq!(lookup["code"], "E0020");
+    let range = json!({
+        "start": { "line": 1, "character": 8 },
+        "end": { "line": 1, "character": 12 },
+    });
+    assert_eq!(lookup["range"], range);
+
+    let syntax = &published[1]["params"]["diagnostics"][0];
+    assert_eq!(
+        syntax["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+}
+
+#[test]
+fn hover_and_definition() {
+    let uri = sample_uri("editing.voile");
+    let code = "import Data.Bool;\nval id : Bool -> Bool;\nlet id = \\x. not x;\n";
+    let replies = session(vec![
+        open(&uri, code),
+        // `Bool` in the signature
+        request(1, "textDocument/hover", &uri, 1, 10),
+        // `id` in the implementation
+        request(2, "textDocument/definition", &uri, 2, 4),
+        // `not`, defined in `Data/Bool.voile`
+        request(3, "textDocument/definition", &uri, 2, 14),
+        // Nothing here
+        request(4, "textDocument/hover", &uri, 0, 0),
+    ]);
+    let diagnostics = &replies[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics, &json!([]));
+
+    let hover = reply(&replies, 1);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("Bool : "));
+
+    let definition = reply(&replies, 2);
+    assert_eq!(definition["uri"], uri);
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 1, "character": 4 })
+    );
+
+    let definition = reply(&replies, 3);
+    assert_eq!(definition["uri"], sample_uri("Data/Bool.voile"));
+
+    assert_eq!(reply(&replies, 4), &Value::Null);
+}
+
+#[test]
+fn percent_encoded_uri() {
+    let path = PathBuf::from("/tmp/a b/\u{4f60}%.voile");
+    let uri = "file:///tmp/a%20b/%E4%BD%A0%25.voile";
+    assert_eq!(path_to_uri(&path), uri);
+    assert_eq!(uri_to_path(uri), Some(path));
+}
+
+#[test]
+fn empty_change() {
+    let uri = sample_uri("editing.voile");
+    let change = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [],
+        },
+    });
+    let replies = session(vec![
+        open(&uri, "val a : Type;\nlet a = Type;\n"),
+        change,
+        request(1, "textDocument/hover", &uri, 1, 4),
+    ]);
+    let published = (replies.iter())
+        .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+        .count();
+    assert_eq!(published, 1);
+    let hover = reply(&replies, 1);
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("a : "));
+}
@@ -4,11 +4,17 @@ use voile_util::meta::MI;
 use voile_util::tags::*;
 use voile_util::uid::*;
 
+use crate::syntax::core::LevelPrim;
+
 pub type LabAbs = Labelled<Abs>;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Abs {
     Type(Loc, Level),
+    /// `Type` without a level, at the level of the universe variable
+    Universe(Loc, MI),
+    /// Universe level primitives
+    LevelPrim(Loc, LevelPrim),
     /// Local variable
     Var(Ident, UID, DBI),
     /// Global variable
@@ -21,10 +27,16 @@ pub enum Abs {
     Cons(Ident),
     /// Record projection
     Proj(Loc, Box<Self>, Ident),
-    /// Apply or Pipeline in surface
-    App(Loc, Box<Self>, Plicit, Box<Self>),
-    /// Dependent Type, `(a -> b -> c)` as `Dt(_, DtKind::Pi, _, _, a, Dt(_, DtKind::Pi, _, _, b, c))`
-    Dt(Loc, PiSig, UID, Plicit, Box<Self>, Box<Self>),
+    /// Record (or row) restriction
+    Restrict(Loc, Box<Self>, Ident),
+    /// Label renaming, from the first label to the second
+    Rename(Loc, Box<Self>, Ident, Ident),
+    /// Apply or Pipeline in surface,
+    /// with the parameter name for named implicit arguments like `f {A = a}`
+    App(Loc, Box<Self>, Option<Ident>, Box<Self>),
+    /// Dependent Type, `(a -> b -> c)` as `Dt(_, DtKind::Pi, _, _, _, a, Dt(_, DtKind::Pi, _, _, _, b, c))`,
+    /// the `Option<Ident>` is the parameter name.
+    Dt(Loc, PiSig, UID, Option<Ident>, Plicit, Box<Self>, Box<Self>),
     /// The first `Loc` is the syntax info of this whole lambda,
     /// while the second is about its parameter
     Lam(Loc, Ident, UID, Box<Self>),
@@ -37,16 +49,98 @@ pub enum Abs {
     Rec(Loc, Vec<LabAbs>, Option<Box<Self>>),
     /// Empty type eliminator,
     Whatever(Loc),
-    /// Case-split expressions.
-    CaseOr(Ident, Ident, UID, Box<Self>, Box<Self>),
+    /// Case-split expressions, with the bindings of the constructor arguments.
+    /// The branch binds the payload of the constructor (the `UID`), and each of the
+    /// arguments is a local definition of its part, unless there's only one.
+    CaseOr(Ident, Vec<Ident>, UID, Box<Self>, Box<Self>),
     /// Row-polymorphic kinds, corresponds to [RowKind](crate::syntax::surf::Expr::RowKind)
     RowKind(Loc, VarRec, Vec<Ident>),
+    /// Type ascription, the expression and its type.
+    Ann(Loc, Box<Self>, Box<Self>),
+    /// Local definition, the name, its type signature (if any), its value and the body.
+    Let(Loc, Ident, UID, Option<Box<Self>>, Box<Self>, Box<Self>),
+    /// Pattern-matching function, compiled into case trees during type-checking.
+    Match(Loc, Vec<AbsClause>),
+}
+
+/// Patterns, corresponds to [Pattern](crate::syntax::surf::Pattern).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AbsPat {
+    Wildcard(Loc),
+    /// Pattern variable
+    Var(Ident, UID),
+    /// Constructor pattern, the label still has its `@`,
+    /// the arguments are wildcards if there's none
+    Cons(Ident, Vec<Self>),
+    /// Record pattern, fields not mentioned are ignored
+    Rec(Loc, Vec<Labelled<Self>>),
+    Pair(Loc, Box<Self>, Box<Self>),
+}
+
+impl ToLoc for AbsPat {
+    fn loc(&self) -> Loc {
+        match self {
+            AbsPat::Wildcard(info) | AbsPat::Rec(info, ..) | AbsPat::Pair(info, ..) => *info,
+            AbsPat::Var(ident, ..) => ident.loc,
+            AbsPat::Cons(ident, pats) => match pats.last() {
+                Some(pat) => merge_info(ident, pat),
+                None => ident.loc,
+            },
+        }
+    }
+}
+
+impl AbsPat {
+    /// Pattern variables in this pattern, in order of introduction.
+    pub fn vars(&self) -> Vec<(&Ident, UID)> {
+        let mut vars = Vec::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars<'a>(&'a self, vars: &mut Vec<(&'a Ident, UID)>) {
+        match self {
+            AbsPat::Wildcard(..) => {}
+            AbsPat::Var(ident, uid) => vars.push((ident, *uid)),
+            AbsPat::Cons(_, pats) => pats.iter().for_each(|pat| pat.collect_vars(vars)),
+            AbsPat::Rec(_, fields) => {
+                for field in fields {
+                    field.expr.collect_vars(vars);
+                }
+            }
+            AbsPat::Pair(_, fst, snd) => {
+                fst.collect_vars(vars);
+                snd.collect_vars(vars);
+            }
+        }
+    }
+}
+
+/// A clause of a pattern-matching function, one pattern for each parameter.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AbsClause {
+    pub loc: Loc,
+    pub patterns: Vec<AbsPat>,
+    pub body: Abs,
+}
+
+impl AbsClause {
+    /// Pattern variables of all the patterns, in order of introduction.
+    pub fn vars(&self) -> Vec<(&Ident, UID)> {
+        let mut vars = Vec::new();
+        for pattern in &self.patterns {
+            pattern.collect_vars(&mut vars);
+        }
+        vars
+    }
 }
 
 impl ToLoc for Abs {
     fn loc(&self) -> Loc {
         match self {
             Abs::Type(info, ..)
+            | Abs::Universe(info, ..)
+            | Abs::LevelPrim(info, ..)
             | Abs::App(info, ..)
             | Abs::Dt(info, ..)
             | Abs::Pair(info, ..)
@@ -55,9 +149,14 @@ impl ToLoc for Abs {
             | Abs::RowPoly(info, ..)
             | Abs::Rec(info, ..)
             | Abs::Proj(info, ..)
+            | Abs::Restrict(info, ..)
+            | Abs::Rename(info, ..)
             | Abs::RowKind(info, ..)
             | Abs::Lift(info, ..)
             | Abs::Whatever(info)
+            | Abs::Ann(info, ..)
+            | Abs::Let(info, ..)
+            | Abs::Match(info, ..)
             | Abs::Lam(info, ..) => (*info).clone(),
             Abs::CaseOr(ident, _, _, _, last) => merge_info(ident, &**last),
             Abs::Var(ident, ..) | Abs::Ref(ident, ..) | Abs::Meta(ident, ..) | Abs::Cons(ident) => {
@@ -72,11 +171,20 @@ impl Abs {
         info: Loc,
         kind: PiSig,
         name: UID,
+        param_name: Option<Ident>,
         plicit: Plicit,
         a: Self,
         b: Self,
     ) -> Self {
-        Abs::Dt(info, kind, name, plicit, Box::new(a), Box::new(b))
+        Abs::Dt(
+            info,
+            kind,
+            name,
+            param_name,
+            plicit,
+            Box::new(a),
+            Box::new(b),
+        )
     }
 
     pub fn row_polymorphic_type(
@@ -92,14 +200,22 @@ impl Abs {
         Abs::Rec(info, fields, rest.map(Box::new))
     }
 
-    pub fn app(info: Loc, function: Self, plicit: Plicit, argument: Self) -> Self {
-        Abs::App(info, Box::new(function), plicit, Box::new(argument))
+    pub fn app(info: Loc, function: Self, name: Option<Ident>, argument: Self) -> Self {
+        Abs::App(info, Box::new(function), name, Box::new(argument))
     }
 
     pub fn proj(info: Loc, record: Self, field: Ident) -> Self {
         Abs::Proj(info, Box::new(record), field)
     }
 
+    pub fn restrict(info: Loc, record: Self, label: Ident) -> Self {
+        Abs::Restrict(info, Box::new(record), label)
+    }
+
+    pub fn rename(info: Loc, record: Self, from: Ident, to: Ident) -> Self {
+        Abs::Rename(info, Box::new(record), from, to)
+    }
+
     pub fn fst(info: Loc, of: Self) -> Self {
         Abs::Fst(info, Box::new(of))
     }
@@ -116,8 +232,24 @@ impl Abs {
         Abs::Pair(info, Box::new(first), Box::new(second))
     }
 
-    pub fn case_or(label: Ident, binding: Ident, uid: UID, clause: Self, or: Self) -> Self {
-        Abs::CaseOr(label, binding, uid, Box::new(clause), Box::new(or))
+    pub fn case_or(label: Ident, bindings: Vec<Ident>, uid: UID, clause: Self, or: Self) -> Self {
+        Abs::CaseOr(label, bindings, uid, Box::new(clause), Box::new(or))
+    }
+
+    pub fn ann(info: Loc, expr: Self, ty: Self) -> Self {
+        Abs::Ann(info, Box::new(expr), Box::new(ty))
+    }
+
+    pub fn let_in(
+        info: Loc,
+        name: Ident,
+        uid: UID,
+        sig: Option<Self>,
+        value: Self,
+        body: Self,
+    ) -> Self {
+        let sig = sig.map(Box::new);
+        Abs::Let(info, name, uid, sig, Box::new(value), Box::new(body))
     }
 
     pub fn lift(info: Loc, lift_count: LevelType, expr: Self) -> Self {
@@ -125,11 +257,62 @@ impl Abs {
     }
 
     pub fn pi(info: Loc, name: UID, plicit: Plicit, input: Self, output: Self) -> Self {
-        Self::dependent_type(info, PiSig::Pi, name, plicit, input, output)
+        Self::dependent_type(info, PiSig::Pi, name, None, plicit, input, output)
     }
 
     pub fn sig(info: Loc, name: UID, plicit: Plicit, first: Self, second: Self) -> Self {
-        Self::dependent_type(info, PiSig::Sigma, name, plicit, first, second)
+        Self::dependent_type(info, PiSig::Sigma, name, None, plicit, first, second)
+    }
+
+    /// Global references in this expression, in order of appearance.
+    pub fn refs(&self) -> Vec<(&Ident, GI)> {
+        let mut refs = Vec::new();
+        self.collect_refs(&mut refs);
+        refs
+    }
+
+    fn collect_refs<'a>(&'a self, refs: &mut Vec<(&'a Ident, GI)>) {
+        use Abs::*;
+        match self {
+            Ref(ident, gi) => refs.push((ident, *gi)),
+            Type(..) | Universe(..) | LevelPrim(..) | Var(..) | Meta(..) | Cons(..)
+            | Whatever(..) | RowKind(..) => {}
+            Lift(_, _, abs)
+            | Proj(_, abs, _)
+            | Restrict(_, abs, _)
+            | Rename(_, abs, ..)
+            | Lam(_, _, _, abs)
+            | Fst(_, abs)
+            | Snd(_, abs) => abs.collect_refs(refs),
+            App(_, a, _, b)
+            | Dt(_, _, _, _, _, a, b)
+            | Pair(_, a, b)
+            | CaseOr(_, _, _, a, b)
+            | Ann(_, a, b) => {
+                a.collect_refs(refs);
+                b.collect_refs(refs);
+            }
+            Let(_, _, _, sig, value, body) => {
+                if let Some(sig) = sig {
+                    sig.collect_refs(refs);
+                }
+                value.collect_refs(refs);
+                body.collect_refs(refs);
+            }
+            Match(_, clauses) => {
+                for clause in clauses {
+                    clause.body.collect_refs(refs);
+                }
+            }
+            RowPoly(_, _, labels, rest) | Rec(_, labels, rest) => {
+                for labelled in labels {
+                    labelled.expr.collect_refs(refs);
+                }
+                if let Some(rest) = rest {
+                    rest.collect_refs(refs);
+                }
+            }
+        }
     }
 }
 
@@ -141,8 +324,11 @@ pub enum AbsDecl {
     Sign(Abs, GI),
     /// Function body without a signature.
     Decl(Abs),
-    /// Function body with a signature.
-    Impl(Abs, GI),
+    /// Function body with a signature, and the name it's defined with.
+    Impl(Abs, GI, Ident),
+    /// A declaration failed to translate, the error is already reported.
+    /// Everything depending on it will not be checked.
+    Poisoned(Ident, GI),
 }
 
 impl ToLoc for AbsDecl {
@@ -150,6 +336,18 @@ impl ToLoc for AbsDecl {
         use AbsDecl::*;
         match self {
             Sign(abs, ..) | Decl(abs) | Impl(abs, ..) => abs.loc(),
+            Poisoned(name, ..) => name.loc,
+        }
+    }
+}
+
+impl AbsDecl {
+    /// Global references in this declaration.
+    pub fn refs(&self) -> Vec<(&Ident, GI)> {
+        use AbsDecl::*;
+        match self {
+            Sign(abs, ..) | Decl(abs) | Impl(abs, ..) => abs.refs(),
+            Poisoned(..) => Vec::new(),
         }
     }
 }
@@ -3,7 +3,7 @@ use std::fmt::{Display, Error, Formatter};
 use voile_util::tags::{PiSig, Plicit};
 use PiSig::*;
 
-use super::{Abs, AbsDecl, LabAbs};
+use super::{Abs, AbsDecl, AbsPat, LabAbs};
 
 type MonadFmt = Result<(), Error>;
 
@@ -11,30 +11,62 @@ impl Display for Abs {
     fn fmt(&self, f: &mut Formatter) -> MonadFmt {
         match self {
             Abs::Type(_, level) => write!(f, "set{}", level),
+            Abs::Universe(_, mi) => write!(f, "(set ?{:?})", mi),
+            Abs::LevelPrim(_, prim) => prim.fmt(f),
             Abs::Var(info, name, dbi) => write!(f, "{}[{:?},{:?}]", info.text, name, dbi),
             Abs::Ref(_, dbi) => write!(f, "<{:?}>", dbi),
             Abs::Meta(_, mi) => write!(f, "?{:?}", mi),
             Abs::Cons(name) => write!(f, "@{}", name.text),
             Abs::Lift(_, levels, expr) => write!(f, "(^[{:?}] {})", levels, expr),
             Abs::App(_, a, _, b) => write!(f, "({} {})", a, b),
-            Abs::Dt(_, Pi, name, Plicit::Ex, param, ret) => {
+            Abs::Dt(_, Pi, name, _, Plicit::Ex, param, ret) => {
                 write!(f, "({:?} : {}) -> {}", name, param, ret)
             }
-            Abs::Dt(_, Pi, name, Plicit::Im, param, ret) => {
+            Abs::Dt(_, Pi, name, _, Plicit::Im, param, ret) => {
                 write!(f, "{{{:?} : {}}} -> {}", name, param, ret)
             }
-            Abs::Dt(_, Sigma, name, _, fst, snd) => write!(f, "(<{:?}> : {}) * {}", name, fst, snd),
+            Abs::Dt(_, Sigma, name, _, _, fst, snd) => {
+                write!(f, "(<{:?}> : {}) * {}", name, fst, snd)
+            }
+            Abs::Dt(_, Tele, name, _, _, fst, snd) => {
+                write!(f, "(<{:?}> : {}), {}", name, fst, snd)
+            }
             Abs::Lam(_, param, name, body) => write!(f, "(\\{}[{:?}]. {})", param.text, name, body),
             Abs::Pair(_, a, b) => write!(f, "({}, {})", a, b),
             Abs::Fst(_, p) => write!(f, "({}.1)", p),
             Abs::Snd(_, p) => write!(f, "({}.2)", p),
             Abs::Proj(_, rec, field) => write!(f, "({}.{})", rec, field.text),
+            Abs::Restrict(_, rec, label) => write!(f, "({} \\ {})", rec, label.text),
+            Abs::Rename(_, rec, from, to) => {
+                write!(f, "({} [{} -> {}])", rec, from.text, to.text)
+            }
             Abs::Whatever(..) => f.write_str("whatever"),
-            Abs::CaseOr(label, binding, _, body, or) => write!(
-                f,
-                "(case {} {}: {} or {})",
-                label.text, binding.text, body, or
-            ),
+            Abs::Ann(_, expr, ty) => write!(f, "({} : {})", expr, ty),
+            Abs::Let(_, name, uid, sig, value, body) => {
+                write!(f, "(let {}[{:?}]", name.text, uid)?;
+                if let Some(sig) = sig {
+                    write!(f, " : {}", sig)?;
+                }
+                write!(f, " = {} in {})", value, body)
+            }
+            Abs::Match(_, clauses) => {
+                f.write_str("(match")?;
+                for clause in clauses {
+                    f.write_str(" |")?;
+                    for pattern in &clause.patterns {
+                        write!(f, " {}", pattern)?;
+                    }
+                    write!(f, " = {}", clause.body)?;
+                }
+                f.write_str(")")
+            }
+            Abs::CaseOr(label, bindings, _, body, or) => {
+                write!(f, "(case {}", label.text)?;
+                for binding in bindings {
+                    write!(f, " {}", binding.text)?;
+                }
+                write!(f, ": {} or {})", body, or)
+            }
             Abs::RowKind(_, kind, labels) => {
                 write!(f, "{} [ ", kind)?;
                 for ident in labels {
@@ -62,6 +94,30 @@ impl Display for Abs {
     }
 }
 
+impl Display for AbsPat {
+    fn fmt(&self, f: &mut Formatter) -> MonadFmt {
+        match self {
+            AbsPat::Wildcard(..) => f.write_str("_"),
+            AbsPat::Var(name, uid) => write!(f, "{}[{:?}]", name.text, uid),
+            AbsPat::Cons(label, pats) => {
+                write!(f, "({}", label.text)?;
+                for pat in pats {
+                    write!(f, " {}", pat)?;
+                }
+                f.write_str(")")
+            }
+            AbsPat::Rec(_, fields) => {
+                f.write_str("{|")?;
+                for field in fields {
+                    write!(f, " {} = {};", field.label.text, field.expr)?;
+                }
+                f.write_str(" |}")
+            }
+            AbsPat::Pair(_, fst, snd) => write!(f, "({}, {})", fst, snd),
+        }
+    }
+}
+
 fn pretty_labels(f: &mut Formatter, labels: &[LabAbs], sep: &str) -> MonadFmt {
     for label in labels {
         writeln!(f, "{}{} {}; ", label.label.text, sep, label.expr)?;
@@ -74,7 +130,8 @@ impl Display for AbsDecl {
         match self {
             AbsDecl::Sign(abs, dbi) => write!(f, "[{}] {}", dbi, abs),
             AbsDecl::Decl(abs) => write!(f, "_ : {}", abs),
-            AbsDecl::Impl(abs, ty_dbi) => write!(f, "{} : [{}]", abs, ty_dbi),
+            AbsDecl::Impl(abs, ty_dbi, _) => write!(f, "{} : [{}]", abs, ty_dbi),
+            AbsDecl::Poisoned(name, dbi) => write!(f, "[{}] {} (poisoned)", dbi, name.text),
         }
     }
 }
@@ -7,7 +7,7 @@ use crate::check::monad::TCE;
 use crate::syntax::abs::{trans_expr, Abs};
 use crate::syntax::surf::parse_str_err_printed;
 
-use super::{trans_decls, AbsDecl};
+use super::{trans_decls, trans_decls_recovering, AbsDecl};
 
 #[test]
 fn many_decls() {
@@ -23,7 +23,7 @@ fn many_decls() {
     let decl = ctx.pop().unwrap();
     println!("{:?}", decl);
     match decl {
-        AbsDecl::Impl(abs, ty_dbi) => {
+        AbsDecl::Impl(abs, ty_dbi, _) => {
             println!("val {};", ty_dbi);
             println!("let {};", abs);
         }
@@ -33,7 +33,7 @@ fn many_decls() {
     let decl = ctx.pop().unwrap();
     println!("{:?}", decl);
     match decl {
-        AbsDecl::Impl(abs, ty_dbi) => {
+        AbsDecl::Impl(abs, ty_dbi, _) => {
             println!("val {};", ty_dbi);
             println!("let {};", abs);
         }
@@ -52,7 +52,7 @@ fn must_be_app(abs: Abs) -> Abs {
 
 fn must_be_pi(abs: Abs) -> (Abs, Abs) {
     match abs {
-        Abs::Dt(_, PiSig::Pi, _, _, param, abs) => (*param, *abs),
+        Abs::Dt(_, PiSig::Pi, _, _, _, param, abs) => (*param, *abs),
         e => panic!("`{:?}` is not an `Abs::Dt(_, Pi, _, _)`.", e),
     }
 }
@@ -89,6 +89,23 @@ fn trans_pi_env() {
     assert_eq!(DBI(0), must_be_local(c));
 }
 
+#[test]
+fn trans_ambiguous_type() {
+    let code = "val t : (l : Level) -> Type -> Type0 -> Type l;";
+    let pi_expr = parse_str_err_printed(code).unwrap().remove(0).body;
+    let mut meta_count = MI(0);
+    let pi_abs = trans_expr(pi_expr, &[], &mut meta_count, &Default::default()).unwrap();
+    println!("{}", pi_abs);
+    let (_, rest) = must_be_pi(pi_abs);
+    let (ambiguous, rest) = must_be_pi(rest);
+    let (explicit, applied) = must_be_pi(rest);
+    // Only the `Type` without a level gets a universe variable
+    assert!(matches!(ambiguous, Abs::Universe(_, MI(0))));
+    assert!(matches!(explicit, Abs::Type(..)));
+    assert!(matches!(applied, Abs::App(_, f, ..) if matches!(*f, Abs::Type(..))));
+    assert_eq!(MI(1), meta_count);
+}
+
 #[test]
 fn trans_pi_shadowing() {
     let code = "val t : ((a : Type) -> (b : Type(a)) -> (b: Type(b)) -> Type(a));";
@@ -178,3 +195,41 @@ fn trans_lam_global() {
         _ => panic!(),
     }
 }
+
+#[test]
+fn trans_decls_recover() {
+    let surf = parse_str_err_printed(
+        "val a : b;\n\
+         let a = Type;\n\
+         let c = a;\n\
+         let c = Type;",
+    )
+    .unwrap();
+    let (state, errors) = trans_decls_recovering(Default::default(), surf);
+    assert_eq!(2, errors.len());
+    match &errors[0] {
+        TCE::LookUpFailed(ident) => assert_eq!(ident.text, "b"),
+        e => panic!("Unexpected error: `{}`.", e),
+    }
+    match &errors[1] {
+        TCE::ReDefine(..) => {}
+        e => panic!("Unexpected error: `{}`.", e),
+    }
+    // The re-definition is dropped.
+    assert_eq!(3, state.decls.len());
+    match &state.decls[1] {
+        AbsDecl::Poisoned(ident, gi) => {
+            assert_eq!(ident.text, "a");
+            assert_eq!(GI(0), *gi);
+        }
+        _ => panic!(),
+    }
+    assert_eq!(
+        vec![GI(0)],
+        state.decls[2]
+            .refs()
+            .into_iter()
+            .map(|(_, gi)| gi)
+            .collect::<Vec<_>>()
+    );
+}
@@ -6,12 +6,12 @@ use voile_util::tags::{PiSig::*, *};
 use voile_util::uid::*;
 
 use crate::check::monad::{TCE, TCM};
-use crate::syntax::surf::{Decl, DeclKind, Expr, Param};
+use crate::syntax::surf::{Clause, Decl, DeclKind, Expr, Param, Pattern};
 
 use super::ast::*;
 
 /// Key: global declaration name; Value: global declaration index.
-type GlobCtx = BTreeMap<String, GI>;
+pub type GlobCtx = BTreeMap<String, GI>;
 
 /// Key: local declaration name; Value: de-bruijn indices.
 type LocalCtx = BTreeMap<String, (DBI, Plicit)>;
@@ -21,7 +21,16 @@ pub fn trans_decls(decls: Vec<Decl>) -> TCM<Vec<AbsDecl>> {
 }
 
 pub fn trans_decls_contextual(tcs: TransState, decls: Vec<Decl>) -> TCM<TransState> {
-    decls.into_iter().try_fold(tcs, trans_one_decl)
+    let (tcs, errors) = trans_decls_recovering(tcs, decls);
+    TCE::from_errors(errors).map(|()| tcs)
+}
+
+/// Translate every declaration, even if some of them are broken.
+/// Broken declarations are translated into [`AbsDecl::Poisoned`].
+pub fn trans_decls_recovering(tcs: TransState, decls: Vec<Decl>) -> (TransState, Vec<TCE>) {
+    let mut errors = Vec::new();
+    let tcs = (decls.into_iter()).fold(tcs, |tcs, decl| trans_one_decl(tcs, decl, &mut errors));
+    (tcs, errors)
 }
 
 /// Translation state.
@@ -32,18 +41,35 @@ pub struct TransState {
     /// (`Impl`s are not counted as valid declarations because they're just
     /// attachments to existing declarations).
     pub signature_indices: Vec<DBI>,
+    /// The names of the valid declarations where they're first declared,
+    /// indexed like `signature_indices`.
+    pub names: Vec<Ident>,
     pub context_mapping: GlobCtx,
     pub decl_count: GI,
     pub meta_count: MI,
+    /// Key: module name; Value: definitions exported by that module.
+    /// Loaded modules are recorded here so they're translated only once.
+    pub modules: BTreeMap<String, GlobCtx>,
+}
+
+impl TransState {
+    /// The module exporting the definition `gi`, if it's imported.
+    pub fn imported_from(&self, gi: GI) -> Option<&str> {
+        (self.modules.iter())
+            .find(|(_, exports)| exports.values().any(|exported| *exported == gi))
+            .map(|(name, _)| name.as_str())
+    }
 }
 
-fn trans_one_decl(mut tcs: TransState, decl: Decl) -> TCM<TransState> {
+fn trans_one_decl(mut tcs: TransState, decl: Decl, errors: &mut Vec<TCE>) -> TransState {
     let abs = trans_expr(
         decl.body,
         &tcs.decls,
         &mut tcs.meta_count,
         &tcs.context_mapping,
-    )?;
+    )
+    .map_err(|err| errors.push(err))
+    .ok();
 
     let decl_total = tcs.decl_count;
     let dbi = *tcs
@@ -55,30 +81,52 @@ fn trans_one_decl(mut tcs: TransState, decl: Decl) -> TCM<TransState> {
     } else {
         None
     };
+    let name = decl.name;
     let modified = match (decl.kind, original) {
         (DeclKind::Sign, None) => {
-            let abs = AbsDecl::Sign(abs, tcs.decl_count);
+            let index = tcs.decl_count;
             tcs.signature_indices.push(DBI(tcs.decls.len()));
+            tcs.names.push(name.clone());
             tcs.decl_count += 1;
-            abs
+            match abs {
+                Some(abs) => AbsDecl::Sign(abs, index),
+                None => AbsDecl::Poisoned(name, index),
+            }
         }
         // Re-type-signaturing something, should give error
-        (DeclKind::Sign, Some(thing)) => {
-            return Err(TCE::ReDefine(decl.name.loc, thing.loc()));
+        (DeclKind::Sign, Some(_)) => {
+            errors.push(TCE::ReDefine(name.loc, tcs.names[dbi.0].loc));
+            return tcs;
         }
         // Re-defining something, should give error
-        (_, Some(AbsDecl::Impl(thing, ..))) | (_, Some(AbsDecl::Decl(thing))) => {
-            return Err(TCE::ReDefine(decl.name.loc, thing.loc()));
+        (_, Some(AbsDecl::Impl(..))) | (_, Some(AbsDecl::Decl(..))) => {
+            errors.push(TCE::ReDefine(name.loc, tcs.names[dbi.0].loc));
+            return tcs;
         }
         (DeclKind::Impl, None) => {
+            let index = tcs.decl_count;
             tcs.decl_count += 1;
             tcs.signature_indices.push(DBI(tcs.decls.len()));
-            AbsDecl::Decl(abs)
+            tcs.names.push(name.clone());
+            match abs {
+                Some(abs) => AbsDecl::Decl(abs),
+                None => AbsDecl::Poisoned(name, index),
+            }
         }
-        (DeclKind::Impl, Some(AbsDecl::Sign(_, dbi))) => AbsDecl::Impl(abs, *dbi),
+        (DeclKind::Impl, Some(AbsDecl::Sign(_, dbi))) => match (tcs.imported_from(*dbi), abs) {
+            // Implementing a postulate of another module, should give error
+            (Some(module), _) => {
+                errors.push(TCE::ImplementImported(name, module.to_owned()));
+                return tcs;
+            }
+            (None, Some(abs)) => AbsDecl::Impl(abs, *dbi, name),
+            (None, None) => AbsDecl::Poisoned(name, *dbi),
+        },
+        // Implementing a broken signature.
+        (DeclKind::Impl, Some(AbsDecl::Poisoned(_, dbi))) => AbsDecl::Poisoned(name, *dbi),
     };
     tcs.decls.push(modified);
-    Ok(tcs)
+    tcs
 }
 
 pub fn trans_expr(expr: Expr, env: &[AbsDecl], meta_count: &mut MI, map: &GlobCtx) -> TCM<Abs> {
@@ -98,7 +146,13 @@ fn trans_expr_inner(
     let map_labels =
         |Labelled { expr, label }| recursion(expr).map(|expr| Labelled { label, expr });
     match expr {
-        Expr::Type(syntax, level) => Ok(Abs::Type(syntax, level)),
+        Expr::Type(syntax, Some(level)) => Ok(Abs::Type(syntax, level)),
+        Expr::Type(syntax, None) => {
+            let ret = Ok(Abs::Universe(syntax, *meta_count));
+            *meta_count += 1;
+            ret
+        }
+        Expr::LevelPrim(info, prim) => Ok(Abs::LevelPrim(info, prim)),
         Expr::Var(ident) => {
             let name = &ident.text;
             if local_map.contains_key(name) {
@@ -110,13 +164,25 @@ fn trans_expr_inner(
                 Err(TCE::LookUpFailed(ident.clone()))
             }
         }
-        Expr::App(app_vec) => Ok(app_vec.try_map(recursion)?.fold1(|result: Abs, abs: Abs| {
-            Abs::app(merge_info(&result, &abs), result, Plicit::Ex, abs)
-        })),
+        Expr::App(mut app_vec) => {
+            explicit_universe(app_vec.head_mut());
+            let named = |e: Expr| match e {
+                Expr::Named(name, arg) => recursion(*arg).map(|arg| (Some(name), arg)),
+                e => recursion(e).map(|e| (None, e)),
+            };
+            let (_, app) = app_vec.try_map(named)?.fold1(|(_, result), (name, abs)| {
+                (None, Abs::app(merge_info(&result, &abs), result, name, abs))
+            });
+            Ok(app)
+        }
+        Expr::Named(..) => unreachable!("Named arguments only appear in applications."),
         // I really hope I can reuse the code with `App` here :(
-        Expr::Pipe(pipe_vec) => Ok(pipe_vec
-            .try_map(recursion)?
-            .rev_fold1(|result, abs| Abs::app(merge_info(&result, &abs), result, Plicit::Ex, abs))),
+        Expr::Pipe(mut pipe_vec) => {
+            explicit_universe(pipe_vec.last_mut());
+            Ok(pipe_vec
+                .try_map(recursion)?
+                .rev_fold1(|result, abs| Abs::app(merge_info(&result, &abs), result, None, abs)))
+        }
         Expr::Meta(ident) => {
             let ret = Ok(Abs::Meta(ident.clone(), *meta_count));
             *meta_count += 1;
@@ -125,9 +191,52 @@ fn trans_expr_inner(
         Expr::Cons(ident) => Ok(Abs::Cons(ident.clone())),
         // TODO: check uniqueness?
         Expr::RowKind(info, kind, labels) => Ok(Abs::RowKind(info, kind, labels)),
-        Expr::Proj(expr, projections) => Ok(projections.fold(recursion(*expr)?, |abs, label| {
-            Abs::proj(merge_info(&abs, &label), abs, label)
-        })),
+        Expr::Proj(expr, projections) => {
+            let (expr, projections) = match *expr {
+                Expr::Var(ident) if !local_map.contains_key(&ident.text) => {
+                    match resolve_qualified(ident, projections.into_vec(), global_map) {
+                        Ok((abs, projections)) => (abs, projections),
+                        Err((ident, projections)) => (recursion(Expr::Var(ident))?, projections),
+                    }
+                }
+                expr => (recursion(expr)?, projections.into_vec()),
+            };
+            Ok(projections.into_iter().fold(expr, |abs, label| {
+                Abs::proj(merge_info(&abs, &label), abs, label)
+            }))
+        }
+        Expr::Restrict(expr, label) => {
+            let expr = recursion(*expr)?;
+            Ok(Abs::restrict(merge_info(&expr, &label), expr, label))
+        }
+        Expr::Rename(expr, info, from, to) => {
+            let expr = recursion(*expr)?;
+            Ok(Abs::rename(expr.loc() + info, expr, from, to))
+        }
+        Expr::RowPoly(info, VarRec::Record, fields, rest) => {
+            // The earlier fields are in scope of the later field types.
+            let mut local = local_env.to_vec();
+            local.reserve_exact(local.len() + fields.len());
+            let mut field_map = local_map.clone();
+            let mut abs_fields = Vec::with_capacity(fields.len());
+            for Labelled { label, expr } in fields {
+                let expr = trans_expr_inner(expr, meta_count, env, global_map, &local, &field_map)?;
+                // Fields are not postulated, so they don't need unique IDs.
+                field_map.values_mut().for_each(|(dbi, _)| *dbi += 1);
+                field_map.insert(label.text.clone(), (Default::default(), Plicit::Ex));
+                local.insert(0, Default::default());
+                abs_fields.push(Labelled { label, expr });
+            }
+            let rest = (rest
+                .map(|e| trans_expr_inner(*e, meta_count, env, global_map, local_env, local_map)))
+            .transpose()?;
+            Ok(Abs::row_polymorphic_type(
+                info,
+                VarRec::Record,
+                abs_fields,
+                rest,
+            ))
+        }
         Expr::RowPoly(info, kind, labels, rest) => {
             let labels: Result<_, _> = labels.into_iter().map(map_labels).collect();
             let rest = rest.map(|e| recursion(*e)).transpose()?;
@@ -144,17 +253,41 @@ fn trans_expr_inner(
         Expr::Sig(initial, last) => trans_dependent_type(
             meta_count, env, global_map, local_env, local_map, initial, *last, Sigma,
         ),
-        Expr::Cases(label, binding, body, or) => {
+        Expr::Tele(initial, last) => trans_dependent_type(
+            meta_count, env, global_map, local_env, local_map, initial, *last, Tele,
+        ),
+        Expr::Cases(label, bindings, body, or) => {
             let or = trans_expr_inner(*or, meta_count, env, global_map, local_env, local_map)?;
+            // The payload is bound before the arguments, named after the constructor
+            // so it can't be referred to.
+            let mut params = Vec::with_capacity(bindings.len() + 1);
+            if bindings.len() > 1 {
+                params.push(Ident {
+                    text: format!("@{}", label.text),
+                    loc: label.loc,
+                });
+            }
+            params.extend(bindings.iter().cloned());
+            let mut local = local_env.to_vec();
+            local.reserve_exact(local.len() + params.len());
+            let mut local_map = local_map.clone();
+            let mut names = Vec::with_capacity(params.len());
+            introduce_abstractions(&params, &mut local, &mut local_map, &mut names);
+            let body = trans_expr_inner(*body, meta_count, env, global_map, &local, &local_map)?;
+            Ok(Abs::case_or(label, bindings, names[0], body, or))
+        }
+        Expr::Whatever(info) => Ok(Abs::Whatever(info)),
+        Expr::Let(info, name, sig, value, body) => {
+            let sig = sig.map(|e| recursion(*e)).transpose()?;
+            let value = recursion(*value)?;
             let mut local = local_env.to_vec();
             local.reserve_exact(local.len() + 1);
             let mut local_map = local_map.clone();
             let mut names = Vec::with_capacity(1);
-            introduce_abstractions(&[binding.clone()], &mut local, &mut local_map, &mut names);
+            introduce_abstractions(&[name.clone()], &mut local, &mut local_map, &mut names);
             let body = trans_expr_inner(*body, meta_count, env, global_map, &local, &local_map)?;
-            Ok(Abs::case_or(label, binding, names[0], body, or))
+            Ok(Abs::let_in(info, name, names[0], sig, value, body))
         }
-        Expr::Whatever(info) => Ok(Abs::Whatever(info)),
         Expr::Lam(info, params, body) => {
             let mut local = local_env.to_vec();
             local.reserve_exact(local.len() + params.len() + 1);
@@ -172,9 +305,110 @@ fn trans_expr_inner(
             meta_count, env, global_map, local_env, local_map, params, *result, Pi,
         ),
         Expr::Lift(info, levels, inner) => Ok(Abs::lift(info, levels, recursion(*inner)?)),
+        Expr::Ann(info, expr, ty) => Ok(Abs::ann(info, recursion(*expr)?, recursion(*ty)?)),
+        Expr::Match(info, clauses) => {
+            let mut abs_clauses = Vec::with_capacity(clauses.len());
+            for Clause {
+                loc,
+                patterns,
+                body,
+            } in clauses
+            {
+                let mut local = local_env.to_vec();
+                let mut local_map = local_map.clone();
+                let mut bound = Vec::new();
+                let patterns = (patterns.into_iter())
+                    .map(|pat| trans_pattern(pat, &mut local, &mut local_map, &mut bound))
+                    .collect::<TCM<_>>()?;
+                let body = trans_expr_inner(body, meta_count, env, global_map, &local, &local_map)?;
+                abs_clauses.push(AbsClause {
+                    loc,
+                    patterns,
+                    body,
+                });
+            }
+            Ok(Abs::Match(info, abs_clauses))
+        }
+    }
+}
+
+/// Pattern variables are introduced from left to right,
+/// and each of them can only be bound once in a clause.
+fn trans_pattern(
+    pattern: Pattern,
+    local_env: &mut Vec<UID>,
+    local_map: &mut LocalCtx,
+    bound: &mut Vec<String>,
+) -> TCM<AbsPat> {
+    let mut recursion = |pat: Pattern| trans_pattern(pat, local_env, local_map, bound);
+    match pattern {
+        Pattern::Wildcard(info) => Ok(AbsPat::Wildcard(info)),
+        Pattern::Var(ident) => {
+            if bound.contains(&ident.text) {
+                return Err(TCE::NonLinearPattern(ident));
+            }
+            bound.push(ident.text.clone());
+            let mut names = Vec::with_capacity(1);
+            introduce_abstractions(&[ident.clone()], local_env, local_map, &mut names);
+            Ok(AbsPat::Var(ident, names[0]))
+        }
+        Pattern::Cons(label, pats) => {
+            let pats = pats.into_iter().map(recursion).collect::<TCM<_>>()?;
+            Ok(AbsPat::Cons(label, pats))
+        }
+        Pattern::Rec(info, fields) => {
+            let mut labels: Vec<&str> = Vec::with_capacity(fields.len());
+            for field in &fields {
+                if labels.contains(&field.label.text.as_str()) {
+                    return Err(TCE::duplicate_field(field.label.clone()));
+                }
+                labels.push(&field.label.text);
+            }
+            let fields = (fields.into_iter())
+                .map(|Labelled { expr, label }| {
+                    recursion(expr).map(|expr| Labelled { label, expr })
+                })
+                .collect::<TCM<_>>()?;
+            Ok(AbsPat::Rec(info, fields))
+        }
+        Pattern::Pair(info, fst, snd) => {
+            let fst = recursion(*fst)?;
+            let snd = recursion(*snd)?;
+            Ok(AbsPat::Pair(info, Box::new(fst), Box::new(snd)))
+        }
+    }
+}
+
+/// `Type l` is the universe at level `l`, so the applied `Type` is not ambiguous.
+fn explicit_universe(f: &mut Expr) {
+    if let Expr::Type(_, level @ None) = f {
+        *level = Some(Default::default());
     }
 }
 
+/// Try to interpret `a.b.c` as a qualified name (like `Data.Bool.not`),
+/// preferring the longest one found in `global_map`.
+/// Returns the unused projections as well.
+fn resolve_qualified(
+    head: Ident,
+    mut projections: Vec<Ident>,
+    global_map: &GlobCtx,
+) -> Result<(Abs, Vec<Ident>), (Ident, Vec<Ident>)> {
+    for taken in (1..=projections.len()).rev() {
+        let mut name = head.text.clone();
+        for projection in &projections[..taken] {
+            name.push('.');
+            name.push_str(&projection.text);
+        }
+        if let Some(gi) = global_map.get(&name) {
+            let rest = projections.split_off(taken);
+            let loc = merge_info(&head, &projections[taken - 1]);
+            return Ok((Abs::Ref(Ident::new(loc, name), *gi), rest));
+        }
+    }
+    Err((head, projections))
+}
+
 fn introduce_abstractions(
     params: &[Ident],
     local_env: &mut Vec<UID>,
@@ -182,13 +416,9 @@ fn introduce_abstractions(
     names: &mut Vec<UID>,
 ) {
     for param in params {
-        let shadowing = local_map.get(&param.text).cloned().map(|(dbi, _)| dbi);
+        // Shadowed variables are still bound, so they're kept in `local_env`.
         for (_name, (dbi, _)) in local_map.iter_mut() {
-            let dbi_value = *dbi;
             *dbi += 1;
-            if shadowing == Some(dbi_value) {
-                local_env.remove(dbi_value.0);
-            }
         }
         local_map.insert(param.text.clone(), (Default::default(), Plicit::Ex));
         let new_name = unsafe { next_uid() };
@@ -229,14 +459,8 @@ fn trans_dependent_type(
         |pi_abs, (param, plicit)| {
             let info = param.loc() + pi_abs.loc();
             let pop_empty = "The stack `names` is empty. Please report this as a bug.";
-            Abs::dependent_type(
-                info,
-                kind,
-                names.pop().expect(pop_empty),
-                plicit,
-                param,
-                pi_abs,
-            )
+            let (uid, param_name) = names.pop().expect(pop_empty);
+            Abs::dependent_type(info, kind, uid, param_name, plicit, param, pi_abs)
         },
     ))
 }
@@ -247,7 +471,7 @@ fn introduce_telescope(
     global_map: &GlobCtx,
     dt_env: &mut Vec<UID>,
     dt_map: &mut LocalCtx,
-    names: &mut Vec<UID>,
+    names: &mut Vec<(UID, Option<Ident>)>,
     mut dt_vec: Vec<(Abs, Plicit)>,
     param: Param,
 ) -> TCM<Vec<(Abs, Plicit)>> {
@@ -270,14 +494,14 @@ fn introduce_telescope(
         dt_map.insert(param_name, (Default::default(), param.plicit));
         let new_name = unsafe { next_uid() };
         dt_env.insert(0, new_name);
-        names.push(new_name);
+        names.push((new_name, Some(name.clone())));
         dt_vec.push((param_ty.clone(), param.plicit));
     }
     if param.names.is_empty() {
         let new_name = unsafe { next_uid() };
         dt_map.iter_mut().for_each(|(_name, (dbi, _))| *dbi += 1);
         dt_env.insert(0, new_name);
-        names.push(new_name);
+        names.push((new_name, None));
         dt_vec.push((param_ty, param.plicit));
     }
 
@@ -17,19 +17,38 @@ pub type Fields = BTreeMap<String, Val>;
 /// Case-split expression.
 pub type CaseSplit = BTreeMap<String, Closure>;
 
+/// No label is in both rows.
+fn disjoint<T>(a: &BTreeMap<String, T>, b: &BTreeMap<String, T>) -> bool {
+    a.keys().all(|label| !b.contains_key(label))
+}
+
+/// Rename the references to the field `from` in the field types,
+/// see [`Neutral::Field`](Neutral::Field).
+fn rename_field_refs(variants: Variants, from: &str, to: &str) -> Variants {
+    let mut rename = |label: &String| Some(Val::field(to.to_owned())).filter(|_| label == from);
+    (variants.into_iter())
+        .map(|(label, ty)| (label, ty.instantiate_fields_with(&mut rename)))
+        .collect()
+}
+
 /// Reduction functions.
 impl Val {
     pub fn apply(self, arg: Val) -> Self {
         match self {
-            Val::Lam(closure) => closure.instantiate(arg),
+            Val::Lam(closure) => closure.beta(arg),
             Val::Neut(Neutral::OrSplit(split, or)) => Closure::Tree(split)
-                .instantiate_safe(arg)
+                .beta_safe(arg)
                 .unwrap_or_else(|e| Val::app(*or, vec![e])),
             Val::Neut(Neutral::App(f, mut a)) => {
                 a.push(arg);
                 Val::app(*f, a)
             }
             Val::Neut(otherwise) => Val::app(otherwise, vec![arg]),
+            // Constructors evaluated without their types take the arguments one by one.
+            Val::Cons(label, mut args) => {
+                args.push(arg);
+                Val::Cons(label, args)
+            }
             e => panic!("Cannot apply on `{}`.", e),
         }
     }
@@ -50,38 +69,165 @@ impl Val {
         }
     }
 
+    /// The arguments of a constructor as the payload bound by a case branch,
+    /// which is the only argument or right-nested pairs of them.
+    pub fn cons_payload(mut args: Vec<Val>) -> Val {
+        let last = args
+            .pop()
+            .expect("Cannot split on an unapplied constructor.");
+        args.into_iter().rfold(last, |snd, fst| Val::pair(fst, snd))
+    }
+
+    /// The `arity` arguments of a constructor in the payload bound by a case branch,
+    /// see [`cons_payload`](Val::cons_payload).
+    pub fn cons_args(self, arity: usize) -> Vec<Val> {
+        let mut args = Vec::with_capacity(arity);
+        let last = (1..arity).fold(self, |payload, _| {
+            args.push(payload.clone().first());
+            payload.second()
+        });
+        args.push(last);
+        args
+    }
+
+    /// The number of explicit arguments of a constructor of this payload type.
+    /// The components of a telescope are the arguments, the implicit ones
+    /// are inferred when it's applied, while any other type (a Sigma type included)
+    /// is the type of the only argument.
+    pub fn cons_arity(&self) -> usize {
+        match self {
+            Val::Dt(PiSig::Tele, plicit, _, _, Closure::Plain(rest)) => {
+                rest.cons_arity() + (*plicit == Plicit::Ex) as usize
+            }
+            _ => 1,
+        }
+    }
+
     pub fn project(self, field: String) -> Val {
         match self {
             Val::Rec(mut fields) => fields
                 .remove(&field)
                 .expect(&format!("Missing essential field with name `{}`.", field)),
-            Val::Neut(Neutral::Rec(mut fields, ..)) => fields
-                .remove(&field)
-                .expect(&format!("Missing essential field with name `{}`.", field)),
+            Val::Neut(Neutral::Rec(mut fields, more)) => match fields.remove(&field) {
+                Some(field) => field,
+                None => Val::Neut(*more).project(field),
+            },
             Val::Neut(otherwise) => Val::proj(otherwise, field),
             e => panic!("Cannot project on `{}`.", e),
         }
     }
 
+    /// Restriction for records and row-polymorphic types, `r \ x`.
+    pub fn restrict(self, label: String) -> Val {
+        use Val::*;
+        match self {
+            Rec(mut fields) => {
+                fields.remove(&label);
+                Rec(fields)
+            }
+            RowPoly(kind, mut variants) => {
+                variants.remove(&label);
+                RowPoly(kind, variants)
+            }
+            Neut(Neutral::Rec(mut fields, more)) => {
+                let more = Neut(*more);
+                if fields.remove(&label).is_some() {
+                    Rec(fields).rec_extend(more)
+                } else {
+                    Rec(fields).rec_extend(more.restrict(label))
+                }
+            }
+            Neut(Neutral::Row(kind, mut variants, more)) => {
+                let more = Neut(*more);
+                if variants.remove(&label).is_some() {
+                    RowPoly(kind, variants).row_extend(more)
+                } else {
+                    RowPoly(kind, variants).row_extend(more.restrict(label))
+                }
+            }
+            Neut(otherwise) => Val::restriction(otherwise, label),
+            e => panic!("Cannot restrict `{}` from `{}`.", label, e),
+        }
+    }
+
+    /// Renaming a label of a record or a row-polymorphic type, `r [x -> y]`.
+    /// The renamed record is typed as lacking `to`,
+    /// so a field `to` in it is hidden by width subtyping, and it's dropped.
+    pub fn rename(self, from: String, to: String) -> Val {
+        use Val::*;
+        if from == to {
+            return self;
+        }
+        match self {
+            Rec(mut fields) => {
+                fields.remove(&to);
+                if let Some(field) = fields.remove(&from) {
+                    fields.insert(to, field);
+                }
+                Rec(fields)
+            }
+            RowPoly(kind, variants) => {
+                let mut variants = rename_field_refs(variants, &from, &to);
+                if let Some(variant) = variants.remove(&from) {
+                    variants.insert(to, variant);
+                }
+                RowPoly(kind, variants)
+            }
+            Neut(Neutral::Rec(mut fields, more)) => {
+                fields.remove(&to);
+                let more = Neut(*more).restrict(to.clone());
+                match fields.remove(&from) {
+                    Some(field) => {
+                        fields.insert(to, field);
+                        Rec(fields).rec_extend(more)
+                    }
+                    None => Rec(fields).rec_extend(more.rename(from, to)),
+                }
+            }
+            Neut(Neutral::Row(kind, variants, more)) => {
+                let mut variants = rename_field_refs(variants, &from, &to);
+                match variants.remove(&from) {
+                    Some(variant) => {
+                        variants.insert(to, variant);
+                        RowPoly(kind, variants).row_extend(Neut(*more))
+                    }
+                    None => RowPoly(kind, variants).row_extend(Neut(*more).rename(from, to)),
+                }
+            }
+            Neut(otherwise) => Val::renaming(otherwise, from, to),
+            e => panic!("Cannot rename `{}` in `{}`.", from, e),
+        }
+    }
+
     /// Extension for records.
     pub fn rec_extend(self, ext: Self) -> Self {
         let err = format!("Cannot extend `{}` by `{}`.", self, ext);
         self.rec_extend_safe(ext).expect(&err)
     }
 
+    /// The extension is typed as lacking the fields of `self`,
+    /// so its fields with the same labels are hidden by width subtyping,
+    /// and they're dropped.
+    /// Fails if the extension is not a record.
     pub fn rec_extend_safe(self, ext: Self) -> Result<Self, (Self, Self)> {
         use Val::*;
+        let hide = |mut fields: Fields, hidden: Fields| {
+            for (label, field) in hidden {
+                fields.entry(label).or_insert(field);
+            }
+            fields
+        };
         match (self, ext) {
-            (Rec(mut fields), Rec(mut ext)) => {
-                fields.append(&mut ext);
-                Ok(Rec(fields))
+            (Rec(fields), Rec(ext)) => Ok(Rec(hide(fields, ext))),
+            (Rec(fields), Neut(Neutral::Rec(more, ext))) => {
+                Rec(hide(fields, more)).rec_extend_safe(Neut(*ext))
             }
-            (Rec(mut fields), Neut(Neutral::Rec(mut more, ext)))
-            | (Neut(Neutral::Rec(mut more, ext)), Rec(mut fields)) => {
-                fields.append(&mut more);
-                Rec(fields).rec_extend_safe(Neut(*ext))
+            (Neut(Neutral::Rec(more, ext)), Rec(fields)) => {
+                Rec(hide(more, fields)).rec_extend_safe(Neut(*ext))
             }
-            (Rec(fields), Neut(otherwise)) | (Neut(otherwise), Rec(fields)) => {
+            (Rec(fields), Neut(otherwise)) | (Neut(otherwise), Rec(fields))
+                if !matches!(otherwise, Neutral::Rec(..)) =>
+            {
                 Ok(Self::neutral_record(fields, otherwise))
             }
             (a, b) => Err((a, b)),
@@ -114,36 +260,24 @@ impl Val {
         self.row_extend_safe(ext).expect(&err)
     }
 
+    /// Fails if the kinds mismatch or the labels overlap,
+    /// the latter is ruled out by the "lacks" constraints in row kinds.
     pub fn row_extend_safe(self, ext: Self) -> Result<Self, (Self, Self)> {
-        use {Neutral::Row, Val::*, VarRec::*};
+        use {Neutral::Row, Val::*};
         match (self, ext) {
-            (RowPoly(Record, mut fields), RowPoly(Record, mut ext)) => {
-                fields.append(&mut ext);
-                Ok(Self::record_type(fields))
-            }
-            (RowPoly(Record, mut fields), Neut(Row(Record, mut ext, more))) => {
-                fields.append(&mut ext);
-                Self::record_type(fields).row_extend_safe(Neut(*more))
-            }
-            (RowPoly(Variant, mut variants), RowPoly(Variant, mut ext)) => {
-                variants.append(&mut ext);
-                Ok(Self::variant_type(variants))
-            }
-            (RowPoly(Variant, mut variants), Neut(Row(Variant, mut ext, more))) => {
-                variants.append(&mut ext);
-                Self::variant_type(variants).row_extend_safe(Neut(*more))
-            }
-            (RowPoly(kind, mut variants), RowPoly(_, mut ext)) => {
-                eprintln!("Warning: incorrect row extension!");
+            (RowPoly(kind, mut variants), RowPoly(ext_kind, mut ext))
+                if kind == ext_kind && disjoint(&variants, &ext) =>
+            {
                 variants.append(&mut ext);
                 Ok(RowPoly(kind, variants))
             }
-            (RowPoly(kind, mut variants), Neut(Row(_, mut ext, more))) => {
-                eprintln!("Warning: incorrect row extension!");
+            (RowPoly(kind, mut variants), Neut(Row(ext_kind, mut ext, more)))
+                if kind == ext_kind && disjoint(&variants, &ext) =>
+            {
                 variants.append(&mut ext);
                 RowPoly(kind, variants).row_extend_safe(Neut(*more))
             }
-            (RowPoly(kind, variants), Neut(otherwise)) => {
+            (RowPoly(kind, variants), Neut(otherwise)) if !matches!(otherwise, Row(..)) => {
                 if variants.is_empty() {
                     Ok(Neut(otherwise))
                 } else {
@@ -201,6 +335,11 @@ pub enum Neutral {
     Fall(LevelType, Box<Self>),
     /// Postulated value, aka axioms.
     Axi(Axiom),
+    /// An earlier field of the record type it's in, referred to by the types of the later fields.
+    /// It's instantiated with the record when projecting.
+    /// The index counts the record types between the reference and the record type
+    /// of the field, like a de Bruijn index, so nested record types can bind the same labels.
+    Field(DBI, String),
     /// Function application, with all arguments collected
     /// (so we have easy access to application arguments).<br/>
     /// This is convenient for meta resolution and termination check.
@@ -213,6 +352,10 @@ pub enum Neutral {
     Snd(Box<Self>),
     /// Projecting a named element of a record.
     Proj(Box<Self>, String),
+    /// Removing a label from a record (or a row type).
+    Restrict(Box<Self>, String),
+    /// Renaming a label of a record (or a row type), from the first to the second.
+    Rename(Box<Self>, String, String),
     /// Row-polymorphic types.
     Row(VarRec, Variants, Box<Self>),
     /// Record literal, with extension.
@@ -221,6 +364,11 @@ pub enum Neutral {
     SplitOn(CaseSplit, Box<Self>),
     /// Splitting with unknown branches.
     OrSplit(CaseSplit, Box<Self>),
+    /// Type universe at a neutral level.
+    Type(Box<Self>),
+    /// Maximum of a constant level and neutral levels with offsets,
+    /// `max(n, l + k, ...)`. See [`Val::level_max`](Val::level_max).
+    LMax(LevelType, Vec<(Self, LevelType)>),
 }
 
 impl Neutral {
@@ -239,10 +387,20 @@ impl Neutral {
             Fst(p) => Fst(Box::new(p.map_axiom(f))),
             Snd(p) => Snd(Box::new(p.map_axiom(f))),
             Proj(p, s) => Proj(Box::new(p.map_axiom(f)), s),
+            Restrict(p, s) => Restrict(Box::new(p.map_axiom(f)), s),
+            Rename(p, a, b) => Rename(Box::new(p.map_axiom(f)), a, b),
             Var(n) => Var(n),
             Ref(n) => Ref(n),
             Meta(n) => Meta(n),
+            Field(dbi, label) => Field(dbi, label),
             Lift(levels, expr) => Lift(levels, Box::new(expr.map_axiom(f))),
+            Type(level) => Type(Box::new(level.map_axiom(f))),
+            LMax(n, levels) => LMax(
+                n,
+                (levels.into_iter())
+                    .map(|(level, k)| (level.map_axiom(f), k))
+                    .collect(),
+            ),
             Fall(levels, expr) => Fall(levels, Box::new(expr.map_axiom(f))),
             Row(kind, variants, ext) => {
                 let variants = variants.into_iter().map(map_val).collect();
@@ -279,17 +437,23 @@ pub type TVal = Val;
 pub enum Val {
     /// Type universe.
     Type(Level),
+    /// The type of universe levels.
+    LevelTy,
+    /// Universe level literal.
+    Lvl(Level),
     /// Closure with parameter typed.
     /// For untyped closures, it can be represented as `Neut` directly.
     Lam(Closure),
     /// Pi-like types (dependent types), with parameter explicitly typed.
-    Dt(PiSig, Plicit, Box<Self>, Closure),
+    /// The parameter name is kept for passing implicit arguments by name.
+    Dt(PiSig, Plicit, Option<String>, Box<Self>, Closure),
     /// Row-polymorphic type literal.
     RowPoly(VarRec, Variants),
     /// Row kind literals -- subtype of `Type`.
+    /// Rows of this kind lack the labels, so they can be extended by them.
     RowKind(Level, VarRec, Vec<String>),
-    /// Constructor invocation.
-    Cons(String, Box<Self>),
+    /// Constructor invocation, with the explicit arguments.
+    Cons(String, Vec<Self>),
     /// Record literal, without extension.
     Rec(Fields),
     /// Sigma instance.
@@ -318,6 +482,33 @@ impl Default for Closure {
 }
 
 impl Closure {
+    /// Apply the closure to `arg`, which is outside of the closure.
+    /// The binder is removed, so the generated axioms bound outside are one binder closer.
+    pub fn beta(self, arg: Val) -> Val {
+        self.beta_safe(arg)
+            .unwrap_or_else(|e| panic!("Cannot split on `{}`.", e))
+    }
+
+    pub fn beta_safe(self, arg: Val) -> Result<Val, Val> {
+        match self {
+            Closure::Plain(body) => {
+                let body = body.bind_generated();
+                let arg = arg.shift_generated(1, 0);
+                Ok(body
+                    .reduce_with_dbi(arg, Default::default())
+                    .shift_generated(-1, 1))
+            }
+            Closure::Tree(mut split) => match arg {
+                Val::Cons(label, args) => match split.remove(&label) {
+                    Some(body) => body.beta_safe(Val::cons_payload(args)),
+                    None => Err(Val::Cons(label, args)),
+                },
+                Val::Neut(neutral) => Ok(Val::split_on(split, neutral)),
+                a => Err(a),
+            },
+        }
+    }
+
     pub fn instantiate(self, arg: Val) -> Val {
         self.instantiate_safe(arg)
             .unwrap_or_else(|e| panic!("Cannot split on `{}`.", e))
@@ -327,9 +518,9 @@ impl Closure {
         match self {
             Closure::Plain(body) => Ok(body.reduce_with_dbi(arg, Default::default())),
             Closure::Tree(mut split) => match arg {
-                Val::Cons(label, arg) => match split.remove(&label) {
-                    Some(body) => body.instantiate_safe(*arg),
-                    None => Err(Val::Cons(label, arg)),
+                Val::Cons(label, args) => match split.remove(&label) {
+                    Some(body) => body.instantiate_safe(Val::cons_payload(args)),
+                    None => Err(Val::Cons(label, args)),
                 },
                 Val::Neut(neutral) => Ok(Val::split_on(split, neutral)),
                 a => Err(a),
@@ -341,8 +532,8 @@ impl Closure {
         match self {
             Closure::Plain(body) => body.clone().reduce_with_dbi(arg, Default::default()),
             Closure::Tree(split) => match arg {
-                Val::Cons(label, arg) => match split.get(&label) {
-                    Some(body) => body.instantiate_cloned(*arg),
+                Val::Cons(label, args) => match split.get(&label) {
+                    Some(body) => body.instantiate_cloned(Val::cons_payload(args)),
                     None => panic!("Cannot find clause for label `{}`.", label),
                 },
                 Val::Neut(neutral) => Val::split_on(split.clone(), neutral),
@@ -355,8 +546,8 @@ impl Closure {
         match self {
             Closure::Plain(body) => body.clone().reduce_with_dbi_borrow(arg, Default::default()),
             Closure::Tree(split) => match arg {
-                Val::Cons(label, arg) => match split.get(label) {
-                    Some(body) => body.instantiate_borrow(arg),
+                Val::Cons(label, args) => match split.get(label) {
+                    Some(body) => body.instantiate_cloned(Val::cons_payload(args.clone())),
                     None => panic!("Cannot find clause for label `{}`.", label),
                 },
                 Val::Neut(neutral) => Val::split_on(split.clone(), neutral.clone()),
@@ -11,16 +11,17 @@ impl Val {
     pub fn is_type(&self) -> bool {
         use Val::*;
         match self {
-            Type(..) | Dt(..) | RowPoly(..) | RowKind(..) | Neut(Neutral::Row(..)) => true,
+            Type(..) | Dt(..) | RowPoly(..) | RowKind(..) | LevelTy => true,
+            Neut(Neutral::Row(..)) | Neut(Neutral::Type(..)) => true,
             // In case it's neutral, we use `is_universe` on its type.
             // In case it's a meta, we're supposed to solve it.
-            Lam(..) | Cons(..) | Rec(..) | Pair(..) | Neut(..) => false,
+            Lam(..) | Cons(..) | Rec(..) | Pair(..) | Lvl(..) | Neut(..) => false,
         }
     }
 
     pub fn is_universe(&self) -> bool {
         match self {
-            Val::Type(..) | Val::RowKind(..) => true,
+            Val::Type(..) | Val::RowKind(..) | Val::Neut(Neutral::Type(..)) => true,
             _ => false,
         }
     }
@@ -29,8 +30,8 @@ impl Val {
         Val::Pair(Box::new(first), Box::new(second))
     }
 
-    pub fn cons(name: String, param: Self) -> Self {
-        Val::Cons(name, Box::new(param))
+    pub fn cons(name: String, args: Vec<Self>) -> Self {
+        Val::Cons(name, args)
     }
 
     pub fn case_tree(tree: CaseSplit) -> Self {
@@ -99,12 +100,36 @@ impl Val {
         Val::Neut(Neutral::Proj(Box::new(record), field))
     }
 
-    pub fn closure_dependent_type(kind: PiSig, visib: Plicit, param_ty: TVal, body: TVal) -> TVal {
-        Self::dependent_type(kind, visib, param_ty, Closure::plain(body))
+    pub fn field(label: String) -> Self {
+        Val::Neut(Neutral::Field(Default::default(), label))
     }
 
-    pub fn dependent_type(kind: PiSig, plicit: Plicit, param_type: TVal, closure: Closure) -> TVal {
-        Val::Dt(kind, plicit, Box::new(param_type), closure)
+    pub fn restriction(record: Neutral, label: String) -> Self {
+        Val::Neut(Neutral::Restrict(Box::new(record), label))
+    }
+
+    pub fn renaming(record: Neutral, from: String, to: String) -> Self {
+        Val::Neut(Neutral::Rename(Box::new(record), from, to))
+    }
+
+    pub fn closure_dependent_type(
+        kind: PiSig,
+        visib: Plicit,
+        name: Option<String>,
+        param_ty: TVal,
+        body: TVal,
+    ) -> TVal {
+        Self::dependent_type(kind, visib, name, param_ty, Closure::plain(body))
+    }
+
+    pub fn dependent_type(
+        kind: PiSig,
+        plicit: Plicit,
+        name: Option<String>,
+        param_type: TVal,
+        closure: Closure,
+    ) -> TVal {
+        Val::Dt(kind, plicit, name, Box::new(param_type), closure)
     }
 
     pub fn variant_type(variants: Variants) -> TVal {
@@ -132,11 +157,11 @@ impl Val {
     }
 
     pub fn pi(param_plicit: Plicit, param_type: TVal, body: Closure) -> TVal {
-        Self::dependent_type(PiSig::Pi, param_plicit, param_type, body)
+        Self::dependent_type(PiSig::Pi, param_plicit, None, param_type, body)
     }
 
     pub fn sig(param_type: TVal, body: Closure) -> TVal {
-        Self::dependent_type(PiSig::Sigma, Plicit::Ex, param_type, body)
+        Self::dependent_type(PiSig::Sigma, Plicit::Ex, None, param_type, body)
     }
 
     pub fn into_neutral(self) -> Result<Neutral, Self> {
@@ -0,0 +1,141 @@
+use voile_util::axiom::Axiom;
+use voile_util::uid::{DBI, UID};
+
+use super::{CaseSplit, Closure, Neutral, Val, Variants};
+
+impl Val {
+    /// Map the atoms (variables, references, metas and axioms) in this value,
+    /// `f` also takes `dbi` plus the number of binders the atom is under.
+    /// Unlike [`map_axiom`](Val::map_axiom), this does not reduce anything.
+    pub fn map_atom_with_dbi(self, dbi: DBI, f: &mut impl FnMut(Neutral, DBI) -> Neutral) -> Self {
+        use Val::*;
+        match self {
+            Type(..) | LevelTy | Lvl(..) | RowKind(..) => self,
+            Lam(closure) => Lam(closure.map_atom_with_dbi(dbi + 1, f)),
+            Dt(kind, plicit, name, param_type, closure) => Dt(
+                kind,
+                plicit,
+                name,
+                Box::new(param_type.map_atom_with_dbi(dbi, f)),
+                closure.map_atom_with_dbi(dbi + 1, f),
+            ),
+            RowPoly(kind, variants) => RowPoly(kind, map_variants(variants, dbi, f)),
+            Rec(fields) => Rec(map_variants(fields, dbi, f)),
+            Cons(name, args) => {
+                let args = (args.into_iter())
+                    .map(|arg| arg.map_atom_with_dbi(dbi, f))
+                    .collect();
+                Cons(name, args)
+            }
+            Pair(a, b) => Pair(
+                Box::new(a.map_atom_with_dbi(dbi, f)),
+                Box::new(b.map_atom_with_dbi(dbi, f)),
+            ),
+            Neut(neut) => Neut(neut.map_atom_with_dbi(dbi, f)),
+        }
+    }
+
+    /// Replace the axiom `uid` with the variable bound right outside,
+    /// so the result can be used as a lambda body.
+    pub fn abstract_axiom(self, uid: UID) -> Self {
+        self.map_atom_with_dbi(Default::default(), &mut |atom, dbi| match atom {
+            Neutral::Axi(axiom) if axiom.unique_id() == uid => Neutral::Var(dbi),
+            atom => atom,
+        })
+    }
+
+    /// Shift the generated axioms bound at least `cutoff` binders outside by `by`.
+    /// They're the local variables during type-checking, referred by indices
+    /// relative to where they are, like variables.
+    pub fn shift_generated(self, by: isize, cutoff: usize) -> Self {
+        if by == 0 {
+            return self;
+        }
+        self.map_atom_with_dbi(Default::default(), &mut |atom, depth| match atom {
+            Neutral::Axi(Axiom::Generated(uid, dbi)) if dbi.0 >= depth.0 + cutoff => {
+                let dbi = DBI((dbi.0 as isize + by) as usize);
+                Neutral::Axi(Axiom::Generated(uid, dbi))
+            }
+            atom => atom,
+        })
+    }
+
+    /// Make the generated axioms referring to the binder right outside variables,
+    /// like the parameters of the lambdas produced by type-checking.
+    pub(crate) fn bind_generated(self) -> Self {
+        self.map_atom_with_dbi(Default::default(), &mut |atom, depth| match atom {
+            Neutral::Axi(Axiom::Generated(_, dbi)) if dbi == depth => Neutral::Var(dbi),
+            atom => atom,
+        })
+    }
+}
+
+impl Neutral {
+    /// See [`Val::map_atom_with_dbi`](Val::map_atom_with_dbi).
+    pub fn map_atom_with_dbi(self, dbi: DBI, f: &mut impl FnMut(Neutral, DBI) -> Neutral) -> Self {
+        use Neutral::*;
+        let go = |neut: Box<Self>, f: &mut _| Box::new(neut.map_atom_with_dbi(dbi, f));
+        match self {
+            Var(..) | Ref(..) | Meta(..) | Axi(..) => f(self, dbi),
+            Field(..) => self,
+            App(fun, args) => {
+                let fun = go(fun, f);
+                let args = (args.into_iter())
+                    .map(|arg| arg.map_atom_with_dbi(dbi, f))
+                    .collect();
+                App(fun, args)
+            }
+            Fst(pair) => Fst(go(pair, f)),
+            Snd(pair) => Snd(go(pair, f)),
+            Proj(rec, field) => Proj(go(rec, f), field),
+            Restrict(rec, label) => Restrict(go(rec, f), label),
+            Rename(rec, from, to) => Rename(go(rec, f), from, to),
+            Lift(levels, neut) => Lift(levels, go(neut, f)),
+            Fall(levels, neut) => Fall(levels, go(neut, f)),
+            Type(level) => Type(go(level, f)),
+            LMax(n, levels) => LMax(
+                n,
+                (levels.into_iter())
+                    .map(|(level, k)| (level.map_atom_with_dbi(dbi, f), k))
+                    .collect(),
+            ),
+            Row(kind, variants, ext) => {
+                let variants = map_variants(variants, dbi, f);
+                Row(kind, variants, go(ext, f))
+            }
+            Rec(fields, ext) => {
+                let fields = map_variants(fields, dbi, f);
+                Rec(fields, go(ext, f))
+            }
+            // Like in reduction, the branches are under a binder.
+            SplitOn(split, obj) => SplitOn(map_split(split, dbi + 1, f), go(obj, f)),
+            OrSplit(split, or) => OrSplit(map_split(split, dbi + 1, f), go(or, f)),
+        }
+    }
+}
+
+impl Closure {
+    /// See [`Val::map_atom_with_dbi`](Val::map_atom_with_dbi).
+    pub fn map_atom_with_dbi(self, dbi: DBI, f: &mut impl FnMut(Neutral, DBI) -> Neutral) -> Self {
+        match self {
+            Closure::Plain(body) => Closure::plain(body.map_atom_with_dbi(dbi, f)),
+            Closure::Tree(split) => Closure::Tree(map_split(split, dbi, f)),
+        }
+    }
+}
+
+fn map_variants(
+    variants: Variants,
+    dbi: DBI,
+    f: &mut impl FnMut(Neutral, DBI) -> Neutral,
+) -> Variants {
+    (variants.into_iter())
+        .map(|(name, ty)| (name, ty.map_atom_with_dbi(dbi, f)))
+        .collect()
+}
+
+fn map_split(split: CaseSplit, dbi: DBI, f: &mut impl FnMut(Neutral, DBI) -> Neutral) -> CaseSplit {
+    (split.into_iter())
+        .map(|(name, closure)| (name, closure.map_atom_with_dbi(dbi, f)))
+        .collect()
+}
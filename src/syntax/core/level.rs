@@ -1,25 +1,55 @@
 use super::{Closure, Neutral, Val};
 use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
 use voile_util::level::{
-    calc_slice_plus_one_level, calc_tree_map_level, calc_tree_map_plus_one_level, fall_tree_map,
-    lift_tree_map, Level, LevelCalcState, LevelType, LiftEx,
+    calc_slice_level, calc_slice_plus_one_level, calc_tree_map_level, calc_tree_map_plus_one_level,
+    fall_tree_map, lift_tree_map, Level, LevelCalcState, LevelType, LiftEx,
 };
 
 pub const TYPE_OMEGA: Val = Val::Type(Level::Omega);
 
+/// Built-in universe level primitives.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Ord, PartialOrd, Hash)]
+pub enum LevelPrim {
+    /// The type of levels.
+    Level,
+    Zero,
+    Suc,
+    Max,
+}
+
+impl Display for LevelPrim {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(match self {
+            LevelPrim::Level => "Level",
+            LevelPrim::Zero => "lzero",
+            LevelPrim::Suc => "lsuc",
+            LevelPrim::Max => "lmax",
+        })
+    }
+}
+
 macro_rules! define_val_lift {
     ($lift:ident, $lift_tree:ident, $op:expr) => {
         fn $lift(self, levels: LevelType) -> Val {
             match self {
                 Val::Type(l) => Val::Type($op(l, levels)),
+                Val::LevelTy => Val::LevelTy,
+                Val::Lvl(l) => Val::Lvl(l),
                 Val::RowKind(l, k, ls) => Val::RowKind($op(l, levels), k, ls),
                 Val::Lam(closure) => Val::Lam(closure.$lift(levels)),
-                Val::Dt(kind, plicit, param_type, closure) => {
-                    Val::dependent_type(kind, plicit, param_type.$lift(levels), closure.$lift(levels))
-                }
+                Val::Dt(kind, plicit, name, param_type, closure) => Val::dependent_type(
+                    kind,
+                    plicit,
+                    name,
+                    param_type.$lift(levels),
+                    closure.$lift(levels),
+                ),
                 Val::RowPoly(kind, variants) => Val::RowPoly(kind, $lift_tree(levels, variants)),
                 Val::Rec(fields) => Val::Rec($lift_tree(levels, fields)),
-                Val::Cons(name, e) => Val::cons(name, e.$lift(levels)),
+                Val::Cons(name, args) => {
+                    Val::cons(name, args.into_iter().map(|a| a.$lift(levels)).collect())
+                }
                 Val::Pair(l, r) => Val::pair(l.$lift(levels), r.$lift(levels)),
                 Val::Neut(neut) => Val::Neut(neut.$lift(levels)),
             }
@@ -34,22 +64,23 @@ impl LiftEx for Val {
     fn calc_level(&self) -> LevelCalcState {
         match self {
             Val::Type(level) | Val::RowKind(level, ..) => Some(*level + 1),
+            Val::LevelTy | Val::Lvl(..) => Some(Default::default()),
             Val::RowPoly(_, variants) => calc_tree_map_level(variants),
             Val::Rec(fields) => calc_tree_map_level(fields),
-            Val::Dt(_, _, param_ty, closure) => {
+            Val::Dt(_, _, _, param_ty, closure) => {
                 Some(param_ty.calc_level()?.max(closure.calc_level()?))
             }
             Val::Lam(closure) => closure.calc_level(),
             Val::Neut(neut) => neut.calc_level(),
             Val::Pair(l, r) => Some(l.calc_level()?.max(r.calc_level()?)),
-            Val::Cons(_, e) => e.calc_level(),
+            Val::Cons(_, args) => calc_slice_level(args),
         }
     }
 }
 
 // I wish this can be type-directed :(
 macro_rules! define_neut_lift {
-    ($lift:ident, $lift_tree:ident, $ref_op:ident, $lift_op:expr, $fall_op:expr) => {
+    ($lift:ident, $lift_tree:ident, $ref_op:ident, $lift_op:expr, $fall_op:expr, $type_op:expr) => {
         fn $lift(self, levels: LevelType) -> Self {
             use super::Neutral::*;
             match self {
@@ -59,6 +90,7 @@ macro_rules! define_neut_lift {
                 Ref(n) => $ref_op(levels, Box::new(Ref(n))),
                 Meta(n) => Meta(n),
                 Axi(x) => Axi(x),
+                Field(dbi, label) => Field(dbi, label),
                 App(f, args) => App(
                     Box::new(f.$lift(levels)),
                     args.into_iter().map(|a| a.$lift(levels)).collect(),
@@ -66,13 +98,21 @@ macro_rules! define_neut_lift {
                 Fst(p) => Fst(Box::new(p.$lift(levels))),
                 Snd(p) => Snd(Box::new(p.$lift(levels))),
                 Proj(r, n) => Proj(Box::new(r.$lift(levels)), n),
+                Restrict(r, n) => Restrict(Box::new(r.$lift(levels)), n),
+                Rename(r, a, b) => Rename(Box::new(r.$lift(levels)), a, b),
                 Row(kind, v, e) => Row(kind, $lift_tree(levels, v), Box::new(e.$lift(levels))),
                 Rec(v, e) => Rec($lift_tree(levels, v), Box::new(e.$lift(levels))),
-                SplitOn(split, on) => SplitOn($lift_tree(levels, split), Box::new(on.$lift(levels))),
-                OrSplit(split, or) => OrSplit($lift_tree(levels, split), Box::new(or.$lift(levels))),
+                SplitOn(split, on) => {
+                    SplitOn($lift_tree(levels, split), Box::new(on.$lift(levels)))
+                }
+                OrSplit(split, or) => {
+                    OrSplit($lift_tree(levels, split), Box::new(or.$lift(levels)))
+                }
+                Type(level) => $type_op(level, levels),
+                LMax(n, ls) => LMax(n, ls),
             }
         }
-    }
+    };
 }
 
 impl LiftEx for Neutral {
@@ -85,6 +125,10 @@ impl LiftEx for Neutral {
             Ordering::Less => Lift(levels - n, expr),
             Ordering::Equal => *expr,
             Ordering::Greater => Fall(n - levels, expr),
+        },
+        |level: Box<Neutral>, levels: LevelType| match Val::Neut(*level).level_suc(levels) {
+            Some(Val::Neut(level)) => Type(Box::new(level)),
+            _ => unreachable!(),
         }
     );
     define_neut_lift!(
@@ -96,7 +140,8 @@ impl LiftEx for Neutral {
             Ordering::Equal => *expr,
             Ordering::Greater => Lift(n - levels, expr),
         },
-        |n: LevelType, expr: Box<Neutral>, levels: LevelType| Lift(n + levels, expr)
+        |n: LevelType, expr: Box<Neutral>, levels: LevelType| Lift(n + levels, expr),
+        |level: Box<Neutral>, levels: LevelType| Fall(levels, Box::new(Type(level)))
     );
 
     fn calc_level(&self) -> LevelCalcState {
@@ -112,11 +157,13 @@ impl LiftEx for Neutral {
                 None => unreachable!(),
             },
             // Level is zero by default
-            Var(..) | Axi(..) | Meta(..) => Some(Default::default()),
+            Var(..) | Axi(..) | Meta(..) | Field(..) | LMax(..) => Some(Default::default()),
+            // We don't know how large the level is.
+            Type(..) => Some(Level::Omega),
             Ref(..) => None,
             Fst(expr) => expr.calc_level(),
             Snd(expr) => expr.calc_level(),
-            Proj(expr, ..) => expr.calc_level(),
+            Proj(expr, ..) | Restrict(expr, ..) | Rename(expr, ..) => expr.calc_level(),
             App(f, args) => calc_slice_plus_one_level(&**f, args),
             Rec(vs, ext) | Row(_, vs, ext) => calc_tree_map_plus_one_level(&**ext, vs),
             SplitOn(split, on) | OrSplit(split, on) => calc_tree_map_plus_one_level(&**on, split),
@@ -133,7 +180,7 @@ macro_rules! define_clos_lift {
                 Tree(split) => Tree($lift_tree(levels, split)),
             }
         }
-    }
+    };
 }
 
 impl LiftEx for Closure {
@@ -148,3 +195,119 @@ impl LiftEx for Closure {
         }
     }
 }
+
+/// Neutral levels with offsets, `l + k`.
+pub type LevelAtoms = Vec<(Neutral, LevelType)>;
+
+/// Neutral levels are compared by identity, like in `unify`,
+/// since the same variable can be attached to different de Bruijn indices.
+fn same_atom(a: &Neutral, b: &Neutral) -> bool {
+    match (a, b) {
+        (Neutral::Axi(a), Neutral::Axi(b)) => a.unique_id() == b.unique_id(),
+        (a, b) => a == b,
+    }
+}
+
+/// Universe level values.
+impl Val {
+    /// Split a level into the constant part and the neutral parts,
+    /// the constant part is $\omega$ for $\omega$. `None` if it's not a level.
+    pub fn level_parts(self) -> Option<(Level, LevelAtoms)> {
+        match self {
+            Val::Lvl(level) => Some((level, Vec::new())),
+            Val::Neut(Neutral::LMax(n, atoms)) => Some((Level::Num(n), atoms)),
+            Val::Neut(neut) => Some((Default::default(), vec![(neut, 0)])),
+            _ => None,
+        }
+    }
+
+    /// Like [`level_parts`](Val::level_parts), but `None` for $\omega$ as well.
+    pub fn finite_level_parts(self) -> Option<(LevelType, LevelAtoms)> {
+        match self.level_parts()? {
+            (Level::Num(n), atoms) => Some((n, atoms)),
+            (Level::Omega, _) => None,
+        }
+    }
+
+    /// Build a level in normal form: neutral levels are unique,
+    /// and the constant is dropped if some offset is larger.
+    pub fn level_from_parts(n: LevelType, atoms: LevelAtoms) -> Self {
+        let mut merged: LevelAtoms = Vec::with_capacity(atoms.len());
+        for (atom, k) in atoms {
+            match merged.iter_mut().find(|(a, _)| same_atom(a, &atom)) {
+                Some((_, old)) => *old = (*old).max(k),
+                None => merged.push((atom, k)),
+            }
+        }
+        let n = match merged.iter().map(|(_, k)| *k).max() {
+            Some(k) if k >= n => 0,
+            _ => n,
+        };
+        match merged.len() {
+            0 => Val::Lvl(Level::Num(n)),
+            1 if n == 0 && merged[0].1 == 0 => Val::Neut(merged.remove(0).0),
+            _ => Val::Neut(Neutral::LMax(n, merged)),
+        }
+    }
+
+    /// `lsuc` for `levels` times, `None` if it's not a level.
+    pub fn level_suc(self, levels: LevelType) -> Option<Self> {
+        match self.level_parts()? {
+            (Level::Omega, _) => Some(Val::Lvl(Level::Omega)),
+            (Level::Num(n), atoms) => {
+                let atoms = (atoms.into_iter())
+                    .map(|(atom, k)| (atom, k + levels))
+                    .collect();
+                Some(Self::level_from_parts(n + levels, atoms))
+            }
+        }
+    }
+
+    /// `lmax`, `None` if either of them is not a level.
+    pub fn level_max(self, other: Self) -> Option<Self> {
+        match (self.level_parts()?, other.level_parts()?) {
+            ((Level::Num(n), mut atoms), (Level::Num(m), mut more)) => {
+                atoms.append(&mut more);
+                Some(Self::level_from_parts(n.max(m), atoms))
+            }
+            _ => Some(Val::Lvl(Level::Omega)),
+        }
+    }
+
+    /// Type universe at level `level`, `None` if it's not a level.
+    pub fn univ(level: Self) -> Option<Self> {
+        match level {
+            Val::Lvl(level) => Some(Val::Type(level)),
+            Val::Neut(level) => Some(Val::Neut(Neutral::Type(Box::new(level)))),
+            _ => None,
+        }
+    }
+
+    /// The level of a universe, `None` if it's not a universe.
+    pub fn univ_level(&self) -> Option<Self> {
+        match self {
+            Val::Type(level) => Some(Val::Lvl(*level)),
+            Val::Neut(Neutral::Type(level)) => Some(Val::Neut(*level.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Syntactic level comparison,
+/// every part of `small` should be bounded by some part of `big`.
+/// Values that are not levels are only bounded by themselves.
+pub fn level_leq(small: &Val, big: &Val) -> bool {
+    match (small.clone().level_parts(), big.clone().level_parts()) {
+        (Some(_), Some((Level::Omega, _))) => true,
+        (Some((Level::Omega, _)), Some(_)) => false,
+        (Some((Level::Num(n), atoms)), Some((Level::Num(m), big_atoms))) => {
+            let bound = big_atoms.iter().map(|(_, k)| *k).fold(m, LevelType::max);
+            n <= bound
+                && atoms.iter().all(|(atom, k)| {
+                    (big_atoms.iter())
+                        .any(|(big_atom, big_k)| same_atom(atom, big_atom) && k <= big_k)
+                })
+        }
+        _ => small == big,
+    }
+}
@@ -11,6 +11,8 @@ pub use self::redex::*;
 mod ast;
 /// Constructor functions.
 mod ast_cons;
+/// Traversal over the atoms, used for abstracting over axioms.
+mod atom;
 /// Implementations for `Level`.
 mod level;
 /// Definition and implementations for `TraverseNeutral`.
@@ -40,13 +40,17 @@ impl TraverseNeutral for Val {
                 .collect::<Result<_, _>>()
                 .map(Val::Rec),
             Val::Lam(closure) => closure.try_map_neutral(f).map(Self::Lam),
-            Val::Dt(kind, param_plicit, param_type, closure) => Ok(Self::dependent_type(
+            Val::Dt(kind, param_plicit, name, param_type, closure) => Ok(Self::dependent_type(
                 kind,
                 param_plicit,
+                name,
                 param_type.try_map_neutral(f)?,
                 closure.try_map_neutral(f)?,
             )),
-            Val::Cons(name, a) => Ok(Self::cons(name, a.try_map_neutral(f)?)),
+            Val::Cons(name, args) => (args.into_iter())
+                .map(|a| a.try_map_neutral(f))
+                .collect::<Result<_, _>>()
+                .map(|args| Self::cons(name, args)),
             e => Ok(e),
         }
     }
@@ -68,11 +72,13 @@ impl TraverseNeutral for Val {
                 .into_iter()
                 .try_fold(init, |a, (_, v)| v.try_fold_neutral(a, f)),
             Val::Lam(closure) => closure.try_fold_neutral(init, f),
-            Val::Dt(_, _, param_ty, closure) => closure
+            Val::Dt(_, _, _, param_ty, closure) => closure
                 .try_fold_neutral(init, f)
                 .and_then(|r| param_ty.try_fold_neutral(r, f)),
-            Val::Cons(_, a) => a.try_fold_neutral(init, f),
-            Val::Type(..) | Val::RowKind(..) => Ok(init),
+            Val::Cons(_, args) => args
+                .into_iter()
+                .try_fold(init, |a, v| v.try_fold_neutral(a, f)),
+            Val::Type(..) | Val::RowKind(..) | Val::LevelTy | Val::Lvl(..) => Ok(init),
         }
     }
 }
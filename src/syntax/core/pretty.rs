@@ -13,6 +13,7 @@ impl Display for Neutral {
             Ref(dbi) => write!(f, "[|{}|]", dbi),
             Axi(a) => a.fmt(f),
             Meta(mi) => write!(f, "?{}", mi),
+            Field(_, label) => label.fmt(f),
             App(fun, a) => {
                 write!(f, "({}", fun)?;
                 for x in a {
@@ -33,7 +34,23 @@ impl Display for Neutral {
             Fst(p) => write!(f, "({}.1)", p),
             Snd(p) => write!(f, "({}.2)", p),
             Proj(rec, field) => write!(f, "({}.{})", rec, field),
+            Restrict(rec, label) => write!(f, "({} \\ {})", rec, label),
+            Rename(rec, from, to) => write!(f, "({} [{} -> {}])", rec, from, to),
             Lift(levels, p) => write!(f, "(^[{:?}] {})", levels, p),
+            Type(level) => write!(f, "(set {})", level),
+            LMax(n, levels) => {
+                f.write_str("(lmax")?;
+                if *n > 0 {
+                    write!(f, " {}", n)?;
+                }
+                for (level, k) in levels {
+                    match k {
+                        0 => write!(f, " {}", level)?,
+                        k => write!(f, " {}+{}", level, k)?,
+                    }
+                }
+                f.write_str(")")
+            }
             Fall(levels, p) => write!(f, "(_[{:?}] {})", levels, p),
             Row(kind, variants, ext) => {
                 write!(f, "{} {{", kind)?;
@@ -74,6 +91,8 @@ impl Display for Val {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         match self {
             Val::Type(l) => write!(f, "set{}", l),
+            Val::LevelTy => f.write_str("Level"),
+            Val::Lvl(l) => l.fmt(f),
             Val::RowKind(l, kind, labels) => {
                 write!(f, "{}{} {{", kind, l)?;
                 let mut started = false;
@@ -98,12 +117,25 @@ impl Display for Val {
                 write_variants(f, fields, " =")?;
                 f.write_str("|}")
             }
-            Val::Dt(Pi, Plicit::Ex, param_ty, clos) => write!(f, "({} -> {})", param_ty, clos),
-            Val::Dt(Pi, Plicit::Im, param_ty, clos) => write!(f, "({{{}}} -> {})", param_ty, clos),
-            Val::Dt(Sigma, _, param_ty, clos) => write!(f, "({} * {})", param_ty, clos),
+            Val::Dt(Pi, Plicit::Ex, _, param_ty, clos) => write!(f, "({} -> {})", param_ty, clos),
+            Val::Dt(Pi, Plicit::Im, _, param_ty, clos) => {
+                write!(f, "({{{}}} -> {})", param_ty, clos)
+            }
+            Val::Dt(Sigma, _, _, param_ty, clos) => write!(f, "({} * {})", param_ty, clos),
+            Val::Dt(Tele, Plicit::Ex, _, param_ty, clos) => write!(f, "({}, {})", param_ty, clos),
+            Val::Dt(Tele, Plicit::Im, _, param_ty, clos) => {
+                write!(f, "({{{}}}, {})", param_ty, clos)
+            }
             Val::Pair(fst, snd) => write!(f, "({}, {})", fst, snd),
             Val::Neut(neut) => neut.fmt(f),
-            Val::Cons(name, a) => write!(f, "(@{} {})", name, a),
+            Val::Cons(name, args) if args.is_empty() => write!(f, "@{}", name),
+            Val::Cons(name, args) => {
+                write!(f, "(@{}", name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                f.write_str(")")
+            }
         }
     }
 }
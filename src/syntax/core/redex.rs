@@ -1,7 +1,10 @@
-use voile_util::level::LiftEx;
+use std::cmp::Ordering;
+
+use voile_util::level::{LevelType, LiftEx};
+use voile_util::tags::VarRec;
 use voile_util::uid::DBI;
 
-use super::{CaseSplit, Closure, Neutral, Val, Variants};
+use super::{CaseSplit, Closure, LevelAtoms, Neutral, Val, Variants};
 
 /// Reducible expressions.
 pub trait RedEx<T: Sized = Val>: Sized {
@@ -23,18 +26,26 @@ impl RedEx for Val {
             ),
             Val::Neut(neutral_value) => neutral_value.reduce_with_dbi(arg, dbi),
             Val::Lam(closure) => Val::Lam(closure.reduce_with_dbi(arg, dbi + 1)),
-            Val::Dt(kind, param_plicit, param_type, closure) => Val::dependent_type(
+            Val::Dt(kind, param_plicit, name, param_type, closure) => Val::dependent_type(
                 kind,
                 param_plicit,
+                name,
                 param_type.reduce_with_dbi_borrow(&arg, dbi),
                 closure.reduce_with_dbi(arg, dbi + 1),
             ),
             Val::RowPoly(kind, variants) => {
-                Val::RowPoly(kind, reduce_variants_with_dbi(variants, dbi, &arg))
+                Val::RowPoly(kind, reduce_row_with_dbi(kind, variants, dbi, &arg))
             }
             Val::Rec(fields) => Val::Rec(reduce_variants_with_dbi(fields, dbi, &arg)),
-            Val::Cons(name, a) => Self::cons(name, a.reduce_with_dbi(arg, dbi)),
+            Val::Cons(name, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|a| a.reduce_with_dbi_borrow(&arg, dbi));
+                Self::cons(name, args.collect())
+            }
             Val::Type(n) => Val::Type(n),
+            Val::LevelTy => Val::LevelTy,
+            Val::Lvl(n) => Val::Lvl(n),
             Val::RowKind(l, k, ls) => Val::RowKind(l, k, ls),
         }
     }
@@ -47,18 +58,24 @@ impl RedEx for Val {
             ),
             Val::Neut(neutral_value) => neutral_value.reduce_with_dbi_borrow(arg, dbi),
             Val::Lam(closure) => Val::Lam(closure.reduce_with_dbi_borrow(arg, dbi + 1)),
-            Val::Dt(kind, param_plicit, param_type, closure) => Val::dependent_type(
+            Val::Dt(kind, param_plicit, name, param_type, closure) => Val::dependent_type(
                 kind,
                 param_plicit,
+                name,
                 param_type.reduce_with_dbi_borrow(arg, dbi),
                 closure.reduce_with_dbi_borrow(arg, dbi + 1),
             ),
             Val::RowPoly(kind, variants) => {
-                Val::RowPoly(kind, reduce_variants_with_dbi(variants, dbi, arg))
+                Val::RowPoly(kind, reduce_row_with_dbi(kind, variants, dbi, arg))
             }
             Val::Rec(fields) => Val::Rec(reduce_variants_with_dbi(fields, dbi, arg)),
-            Val::Cons(name, a) => Self::cons(name, a.reduce_with_dbi_borrow(arg, dbi)),
+            Val::Cons(name, args) => {
+                let args = args.into_iter().map(|a| a.reduce_with_dbi_borrow(arg, dbi));
+                Self::cons(name, args.collect())
+            }
             Val::Type(n) => Val::Type(n),
+            Val::LevelTy => Val::LevelTy,
+            Val::Lvl(n) => Val::Lvl(n),
             Val::RowKind(l, k, ls) => Val::RowKind(l, k, ls),
         }
     }
@@ -68,31 +85,37 @@ impl RedEx for Neutral {
     fn reduce_with_dbi(self, arg: Val, dbi: DBI) -> Val {
         use Neutral::*;
         match self {
-            Var(n) if dbi == n => arg.attach_dbi(dbi),
+            Var(n) if dbi == n => arg.shift_generated(dbi.0 as isize, 0).attach_dbi(dbi),
             Var(n) => Val::var(n),
             Ref(n) => Val::glob(n),
             Meta(mi) => Val::meta(mi),
             Axi(a) => Val::Neut(Axi(a)),
+            Field(dbi, label) => Val::Neut(Field(dbi, label)),
             App(f, args) => args
                 .into_iter()
                 .fold(f.reduce_with_dbi_borrow(&arg, dbi), |f, a| {
                     // Do we need to `reduce` after `apply` again?
                     f.apply(a.reduce_with_dbi_borrow(&arg, dbi))
                 }),
-            SplitOn(split, obj) => Val::case_tree(split)
-                .apply(obj.reduce_with_dbi_borrow(&arg, dbi))
-                // further reduce because the `split` is not yet reduced
-                .reduce_with_dbi(arg, dbi),
-            OrSplit(split, or) => Val::case_tree(split)
-                .split_extend(or.reduce_with_dbi_borrow(&arg, dbi))
-                .reduce_with_dbi(arg, dbi),
+            // Reduce the branches first, reducing after `apply` loops
+            // when the object is still neutral.
+            SplitOn(split, obj) => {
+                let split = reduce_case_tree_with_dbi(split, dbi + 1, &arg);
+                Val::case_tree(split).apply(obj.reduce_with_dbi(arg, dbi))
+            }
+            OrSplit(split, or) => {
+                let split = reduce_case_tree_with_dbi(split, dbi + 1, &arg);
+                Val::case_tree(split).split_extend(or.reduce_with_dbi(arg, dbi))
+            }
             Fst(pair) => pair.reduce_with_dbi(arg, dbi).first(),
             Snd(pair) => pair.reduce_with_dbi(arg, dbi).second(),
             Proj(rec, field) => rec.reduce_with_dbi(arg, dbi).project(field),
+            Restrict(rec, label) => rec.reduce_with_dbi(arg, dbi).restrict(label),
+            Rename(rec, from, to) => rec.reduce_with_dbi(arg, dbi).rename(from, to),
             Lift(levels, neut) => neut.reduce_with_dbi(arg, dbi).lift(levels),
             Fall(levels, neut) => neut.reduce_with_dbi(arg, dbi).fall(levels),
             Row(kind, variants, ext) => {
-                let variants = reduce_variants_with_dbi(variants, dbi, &arg);
+                let variants = reduce_row_with_dbi(kind, variants, dbi, &arg);
                 let ext = ext.reduce_with_dbi(arg, dbi);
                 Val::RowPoly(kind, variants).row_extend(ext)
             }
@@ -101,37 +124,46 @@ impl RedEx for Neutral {
                 let ext = ext.reduce_with_dbi(arg, dbi);
                 Val::Rec(fields).rec_extend(ext)
             }
+            Type(level) => universe(level.reduce_with_dbi(arg, dbi)),
+            LMax(n, levels) => reduce_level_max_with_dbi(n, levels, dbi, &arg),
         }
     }
 
     fn reduce_with_dbi_borrow(self, arg: &Val, dbi: DBI) -> Val {
         use Neutral::*;
         match self {
-            Var(n) if dbi == n => arg.clone().attach_dbi(dbi),
+            Var(n) if dbi == n => arg
+                .clone()
+                .shift_generated(dbi.0 as isize, 0)
+                .attach_dbi(dbi),
             Var(n) => Val::var(n),
             Ref(n) => Val::glob(n),
             Meta(mi) => Val::meta(mi),
             Axi(a) => Val::Neut(Axi(a)),
+            Field(dbi, label) => Val::Neut(Field(dbi, label)),
             App(f, args) => args
                 .into_iter()
                 .fold(f.reduce_with_dbi_borrow(arg, dbi), |f, a| {
                     // Do we need to `reduce` after `apply` again?
                     f.apply(a.reduce_with_dbi_borrow(arg, dbi))
                 }),
-            SplitOn(split, obj) => Val::case_tree(split)
-                .apply(obj.reduce_with_dbi_borrow(&arg, dbi))
-                // further reduce because the `split` is not yet reduced
-                .reduce_with_dbi_borrow(arg, dbi),
-            OrSplit(split, or) => Val::case_tree(split)
-                .split_extend(or.reduce_with_dbi_borrow(arg, dbi))
-                .reduce_with_dbi_borrow(arg, dbi),
+            SplitOn(split, obj) => {
+                let split = reduce_case_tree_with_dbi(split, dbi + 1, arg);
+                Val::case_tree(split).apply(obj.reduce_with_dbi_borrow(arg, dbi))
+            }
+            OrSplit(split, or) => {
+                let split = reduce_case_tree_with_dbi(split, dbi + 1, arg);
+                Val::case_tree(split).split_extend(or.reduce_with_dbi_borrow(arg, dbi))
+            }
             Fst(pair) => pair.reduce_with_dbi_borrow(arg, dbi).first(),
             Snd(pair) => pair.reduce_with_dbi_borrow(arg, dbi).second(),
             Proj(pair, field) => pair.reduce_with_dbi_borrow(arg, dbi).project(field),
+            Restrict(rec, label) => rec.reduce_with_dbi_borrow(arg, dbi).restrict(label),
+            Rename(rec, from, to) => rec.reduce_with_dbi_borrow(arg, dbi).rename(from, to),
             Lift(levels, neut) => neut.reduce_with_dbi_borrow(arg, dbi).lift(levels),
             Fall(levels, neut) => neut.reduce_with_dbi_borrow(arg, dbi).fall(levels),
             Row(kind, variants, ext) => {
-                let variants = reduce_variants_with_dbi(variants, dbi, arg);
+                let variants = reduce_row_with_dbi(kind, variants, dbi, arg);
                 let ext = ext.reduce_with_dbi_borrow(&arg, dbi);
                 Val::RowPoly(kind, variants).row_extend(ext)
             }
@@ -140,6 +172,8 @@ impl RedEx for Neutral {
                 let ext = ext.reduce_with_dbi_borrow(&arg, dbi);
                 Val::Rec(fields).rec_extend(ext)
             }
+            Type(level) => universe(level.reduce_with_dbi_borrow(arg, dbi)),
+            LMax(n, levels) => reduce_level_max_with_dbi(n, levels, dbi, arg),
         }
     }
 }
@@ -162,6 +196,17 @@ impl RedEx<Closure> for Closure {
     }
 }
 
+/// The field types of a record type are in scope of its fields,
+/// so the fields referred to in `arg` are one record type further.
+fn reduce_row_with_dbi(kind: VarRec, variants: Variants, dbi: DBI, arg: &Val) -> Variants {
+    match kind {
+        VarRec::Record if !variants.is_empty() => {
+            reduce_variants_with_dbi(variants, dbi, &arg.clone().shift_fields(1))
+        }
+        _ => reduce_variants_with_dbi(variants, dbi, arg),
+    }
+}
+
 fn reduce_variants_with_dbi(variants: Variants, dbi: DBI, arg: &Val) -> Variants {
     variants
         .into_iter()
@@ -175,3 +220,189 @@ fn reduce_case_tree_with_dbi(cases: CaseSplit, dbi: DBI, arg: &Val) -> CaseSplit
         .map(|(name, ty)| (name, ty.reduce_with_dbi_borrow(&arg, dbi)))
         .collect()
 }
+
+fn reduce_level_max_with_dbi(n: LevelType, levels: LevelAtoms, dbi: DBI, arg: &Val) -> Val {
+    level_max(n, levels, |level| level.reduce_with_dbi_borrow(arg, dbi))
+}
+
+const NOT_LEVEL: &str = "Cannot use a value that's not a level as a level.";
+
+/// Reduction preserves types, so the levels are still levels.
+fn universe(level: Val) -> Val {
+    Val::univ(level).expect(NOT_LEVEL)
+}
+
+/// `max(n, l + k, ...)` with the neutral levels `l` reduced by `f`.
+fn level_max(n: LevelType, levels: LevelAtoms, mut f: impl FnMut(Neutral) -> Val) -> Val {
+    (levels.into_iter()).fold(Val::Lvl(From::from(n)), |max, (level, k)| {
+        let level = f(level).level_suc(k).expect(NOT_LEVEL);
+        max.level_max(level).expect(NOT_LEVEL)
+    })
+}
+
+impl Val {
+    /// Instantiate the references to the fields ([`Neutral::Field`]) in a field type
+    /// with the fields of `record`, taking the field type out of its record type.
+    pub fn instantiate_fields(self, record: &Val) -> Val {
+        self.map_fields(
+            &mut |dbi, label, depth| match dbi.0.cmp(&depth) {
+                Ordering::Equal => (record.clone().project(label)).shift_fields(depth as isize),
+                // The record type enclosing this one is one record type closer.
+                Ordering::Greater => Val::Neut(Neutral::Field(DBI(dbi.0 - 1), label)),
+                Ordering::Less => Val::Neut(Neutral::Field(dbi, label)),
+            },
+            0,
+        )
+    }
+
+    /// Instantiate the references to the fields with `f`, or keep them if `f` gives `None`.
+    /// The field type is still in its record type.
+    pub fn instantiate_fields_with(self, f: &mut impl FnMut(&String) -> Option<Val>) -> Val {
+        self.map_fields(
+            &mut |dbi, label, depth| {
+                let field = if dbi.0 == depth { f(&label) } else { None };
+                match field {
+                    Some(field) => field.shift_fields(depth as isize),
+                    None => Val::Neut(Neutral::Field(dbi, label)),
+                }
+            },
+            0,
+        )
+    }
+
+    /// The fields referred to in a field type.
+    pub fn field_refs(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        self.clone().map_fields(
+            &mut |dbi, label, depth| {
+                if dbi.0 == depth {
+                    labels.push(label.clone());
+                }
+                Val::Neut(Neutral::Field(dbi, label))
+            },
+            0,
+        );
+        labels
+    }
+
+    /// Move the references to the fields of the enclosing record types
+    /// `by` record types further (or closer, if it's negative).
+    pub fn shift_fields(self, by: isize) -> Val {
+        self.map_fields(
+            &mut |dbi, label, depth| {
+                let dbi = if dbi.0 >= depth {
+                    DBI((dbi.0 as isize + by) as usize)
+                } else {
+                    dbi
+                };
+                Val::Neut(Neutral::Field(dbi, label))
+            },
+            0,
+        )
+    }
+
+    /// Replace the references to the fields with `f`, which is also given
+    /// the number of record types the reference is in.
+    fn map_fields(self, f: &mut impl FnMut(DBI, String, usize) -> Val, depth: usize) -> Val {
+        use Val::*;
+        match self {
+            Pair(a, b) => Val::pair(a.map_fields(f, depth), b.map_fields(f, depth)),
+            Neut(neut) => neut.map_fields(f, depth),
+            Lam(closure) => Lam(closure.map_fields(f, depth)),
+            Dt(kind, plicit, name, param_type, closure) => Val::dependent_type(
+                kind,
+                plicit,
+                name,
+                param_type.map_fields(f, depth),
+                closure.map_fields(f, depth),
+            ),
+            RowPoly(kind, variants) => RowPoly(kind, map_row_fields(kind, variants, f, depth)),
+            Rec(fields) => Rec(map_variants_fields(fields, f, depth)),
+            Cons(name, args) => {
+                let args = args.into_iter().map(|a| a.map_fields(f, depth));
+                Val::cons(name, args.collect())
+            }
+            Type(..) | LevelTy | Lvl(..) | RowKind(..) => self,
+        }
+    }
+}
+
+impl Neutral {
+    fn map_fields(self, f: &mut impl FnMut(DBI, String, usize) -> Val, depth: usize) -> Val {
+        use Neutral::*;
+        match self {
+            Field(dbi, label) => f(dbi, label, depth),
+            Var(..) | Ref(..) | Meta(..) | Axi(..) => Val::Neut(self),
+            App(fun, args) => args.into_iter().fold(fun.map_fields(f, depth), |fun, a| {
+                fun.apply(a.map_fields(f, depth))
+            }),
+            SplitOn(split, obj) => {
+                let split = map_case_tree_fields(split, f, depth);
+                Val::case_tree(split).apply(obj.map_fields(f, depth))
+            }
+            OrSplit(split, or) => {
+                let split = map_case_tree_fields(split, f, depth);
+                Val::case_tree(split).split_extend(or.map_fields(f, depth))
+            }
+            Fst(pair) => pair.map_fields(f, depth).first(),
+            Snd(pair) => pair.map_fields(f, depth).second(),
+            Proj(rec, field) => rec.map_fields(f, depth).project(field),
+            Restrict(rec, label) => rec.map_fields(f, depth).restrict(label),
+            Rename(rec, from, to) => rec.map_fields(f, depth).rename(from, to),
+            Lift(levels, neut) => neut.map_fields(f, depth).lift(levels),
+            Fall(levels, neut) => neut.map_fields(f, depth).fall(levels),
+            Row(kind, variants, ext) => {
+                let variants = map_row_fields(kind, variants, f, depth);
+                Val::RowPoly(kind, variants).row_extend(ext.map_fields(f, depth))
+            }
+            Rec(fields, ext) => {
+                let fields = map_variants_fields(fields, f, depth);
+                Val::Rec(fields).rec_extend(ext.map_fields(f, depth))
+            }
+            Type(level) => universe(level.map_fields(f, depth)),
+            LMax(n, levels) => level_max(n, levels, |level| level.map_fields(f, depth)),
+        }
+    }
+}
+
+impl Closure {
+    fn map_fields(self, f: &mut impl FnMut(DBI, String, usize) -> Val, depth: usize) -> Self {
+        match self {
+            Closure::Plain(body) => Self::plain(body.map_fields(f, depth)),
+            Closure::Tree(split) => Closure::Tree(map_case_tree_fields(split, f, depth)),
+        }
+    }
+}
+
+/// The field types of a record type are one record type deeper.
+fn map_row_fields(
+    kind: VarRec,
+    variants: Variants,
+    f: &mut impl FnMut(DBI, String, usize) -> Val,
+    depth: usize,
+) -> Variants {
+    match kind {
+        VarRec::Record => map_variants_fields(variants, f, depth + 1),
+        VarRec::Variant => map_variants_fields(variants, f, depth),
+    }
+}
+
+fn map_variants_fields(
+    variants: Variants,
+    f: &mut impl FnMut(DBI, String, usize) -> Val,
+    depth: usize,
+) -> Variants {
+    (variants.into_iter())
+        .map(|(name, ty)| (name, ty.map_fields(f, depth)))
+        .collect()
+}
+
+fn map_case_tree_fields(
+    cases: CaseSplit,
+    f: &mut impl FnMut(DBI, String, usize) -> Val,
+    depth: usize,
+) -> CaseSplit {
+    (cases.into_iter())
+        .map(|(name, closure)| (name, closure.map_fields(f, depth)))
+        .collect()
+}
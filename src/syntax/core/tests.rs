@@ -1,8 +1,9 @@
 use voile_util::level::LiftEx;
 use voile_util::lisp::{self, Lisp};
+use voile_util::tags::Plicit;
 use voile_util::uid::UID;
 
-use crate::syntax::core::Val;
+use crate::syntax::core::{CaseSplit, Closure, Fields, Neutral, Val, Variants};
 
 fn from_str(s: &str) -> Val {
     let lisp = lisp::parse_str(s).unwrap_or_else(|err| panic!("Syntax error: `{}`.", err));
@@ -27,6 +28,10 @@ fn many_to_val(block: &[Lisp], lisp: &Lisp) -> Val {
         [Sym("fst"), arg] => lisp_to_val(arg).first(),
         [Sym("snd"), arg] => lisp_to_val(arg).second(),
         [Sym("type"), arg] => Val::Type(From::from(arg.as_dbi().unwrap().0)),
+        [Sym("lvl"), arg] => Val::Lvl(From::from(arg.as_dbi().unwrap().0)),
+        [Sym("lsuc"), arg] => lisp_to_val(arg).level_suc(1).unwrap(),
+        [Sym("lmax"), fst, snd] => lisp_to_val(fst).level_max(lisp_to_val(snd)).unwrap(),
+        [Sym("univ"), arg] => Val::univ(lisp_to_val(arg)).unwrap(),
         [Sym("app"), fst, snd] => lisp_to_val(fst).apply(lisp_to_val(snd)),
         [Sym("pair"), fst, snd] => Val::pair(lisp_to_val(fst), lisp_to_val(snd)),
         [Sym("lam"), snd] => Val::closure_lam(lisp_to_val(snd)),
@@ -98,3 +103,113 @@ fn test_lift() {
         "[1919810]"
     );
 }
+
+#[test]
+fn test_level_reduction() {
+    assert_eq!(
+        from_str("(lmax (lvl 1) (lsuc (lvl 1)))"),
+        from_str("(lvl 2)")
+    );
+    assert_eq!(from_str("(lmax 0 (lmax 0 1))"), from_str("(lmax 0 1)"));
+    assert_eq!(from_str("(lmax (lvl 1) (lsuc 0))"), from_str("(lsuc 0)"));
+    assert_eq!(
+        from_str("(app (lam (univ (lsuc 0))) (lvl 2))"),
+        from_str("(type 3)")
+    );
+    assert_eq!(
+        &format!("{}", from_str("(univ (lmax 0 (lsuc 1)))")),
+        "(set (lmax [0] [1]+1))"
+    );
+    assert_eq!(Val::univ(from_str("(type 0)")), None);
+    assert_eq!(from_str("(pair 0 1)").level_suc(1), None);
+}
+
+/// The motive of a dependent case split, instantiated with a constructor.
+#[test]
+fn test_case_motive_reduction() {
+    let mut split = CaseSplit::new();
+    split.insert("True".to_owned(), Closure::plain(from_str("(type 0)")));
+    split.insert("False".to_owned(), Closure::plain(from_str("0")));
+    let motive = Closure::plain(Val::case_tree(split).apply(from_str("0")));
+    let truth = Val::cons("True".to_owned(), vec![from_str("()")]);
+    assert_eq!(motive.instantiate_cloned(truth), from_str("(type 0)"));
+    let falsity = Val::cons("False".to_owned(), vec![from_str("(type 1)")]);
+    assert_eq!(motive.instantiate_cloned(falsity), from_str("(type 1)"));
+}
+
+/// The payload of a constructor, bound by a case branch, and its arguments.
+#[test]
+fn test_cons_payload() {
+    let args = vec![from_str("0"), from_str("1"), from_str("(type 0)")];
+    let payload = Val::cons_payload(args.clone());
+    assert_eq!(payload, from_str("(pair 0 (pair 1 (type 0)))"));
+    assert_eq!(payload.cons_args(3), args);
+    let single = Val::cons_payload(vec![from_str("0")]);
+    assert_eq!(single.cons_args(1), vec![from_str("0")]);
+}
+
+/// Field references, shadowed by the labels of a nested record type.
+#[test]
+fn test_field_instantiation() {
+    let field = |label: &str| Val::field(label.to_owned());
+    let mut inner = Variants::new();
+    inner.insert("A".to_owned(), from_str("(type 0)"));
+    inner.insert("a".to_owned(), field("A"));
+    let inner = Val::record_type(inner);
+    assert!(inner.field_refs().is_empty());
+    let ty = Val::pi(Plicit::Ex, field("A"), Closure::plain(inner.clone()));
+    assert_eq!(ty.field_refs(), vec!["A".to_owned()]);
+    let mut record = Fields::new();
+    record.insert("A".to_owned(), from_str("(type 1)"));
+    let expected = Val::pi(Plicit::Ex, from_str("(type 1)"), Closure::plain(inner));
+    assert_eq!(ty.instantiate_fields(&Val::Rec(record.clone())), expected);
+    // Substituted into a nested record type, the reference is not captured.
+    let wrap = |ty: Val| {
+        let mut wrap = Variants::new();
+        wrap.insert("A".to_owned(), from_str("(type 0)"));
+        wrap.insert("y".to_owned(), ty);
+        Val::record_type(wrap)
+    };
+    let ty = Val::closure_lam(wrap(Val::var(Default::default()))).apply(field("A"));
+    assert_eq!(ty.field_refs(), vec!["A".to_owned()]);
+    let expected = wrap(from_str("(type 1)"));
+    assert_eq!(ty.instantiate_fields(&Val::Rec(record)), expected);
+}
+
+#[test]
+fn test_generated_beta() {
+    use voile_util::axiom::Axiom;
+    use voile_util::uid::DBI;
+    let generated = |uid, dbi| Val::Neut(Neutral::Axi(Axiom::Generated(UID(uid), DBI(dbi))));
+    // `\b c. (\d. c) b`, the inner lambda is under `c` and `d`.
+    let inner = Val::closure_lam(generated(2, 1));
+    assert_eq!(inner.apply(generated(1, 1)), generated(2, 0));
+    // The argument is put under the binders in the body.
+    let inner = Val::closure_lam(Val::closure_lam(Val::var(DBI(1))));
+    let expected = Val::closure_lam(generated(1, 2));
+    assert_eq!(inner.apply(generated(1, 1)), expected);
+    // The lambdas produced by type-checking refer to their parameters by the axioms.
+    let checked = Val::closure_lam(generated(3, 0));
+    assert_eq!(checked.apply(generated(1, 1)), generated(1, 1));
+}
+
+/// Substituted into a case split on a neutral object, also into the branches.
+#[test]
+fn test_split_reduction() {
+    let split = |outer: Val| {
+        let mut split = CaseSplit::new();
+        split.insert("True".to_owned(), Closure::plain(outer));
+        split.insert("False".to_owned(), Closure::plain(from_str("0")));
+        split
+    };
+    // `\x y. case y of { True: \_. x; False: \z. z; }`, applied to `x`.
+    let body = Val::case_tree(split(from_str("2"))).apply(from_str("0"));
+    let lam = Val::closure_lam(Val::closure_lam(body));
+    let expected = Val::case_tree(split(from_str("114"))).apply(from_str("0"));
+    assert_eq!(lam.apply(from_str("114")), Val::closure_lam(expected));
+    // `\x y. case { True: \_. x; False: \z. z; } or y`, applied to `x`.
+    let body = Val::case_tree(split(from_str("2"))).split_extend(from_str("0"));
+    let lam = Val::closure_lam(Val::closure_lam(body));
+    let expected = Val::case_tree(split(from_str("114"))).split_extend(from_str("0"));
+    assert_eq!(lam.apply(from_str("114")), Val::closure_lam(expected));
+}
@@ -3,6 +3,8 @@ use voile_util::loc::{Ident, Labelled, Loc};
 use voile_util::tags::{Plicit, VarRec};
 use voile_util::vec1::Vec1;
 
+use crate::syntax::core::LevelPrim;
+
 pub type LabExpr = Labelled<Expr>;
 
 /// Surface syntax tree node: Parameter.
@@ -31,12 +33,22 @@ pub enum Expr {
     Lift(Loc, LevelType, Box<Self>),
     /// Record projections.
     Proj(Box<Self>, Vec1<Ident>),
+    /// Record (or row) restriction, `r \ x`.
+    Restrict(Box<Self>, Ident),
+    /// Label renaming, `r [x -> y]`, with the location of the brackets.
+    Rename(Box<Self>, Loc, Ident, Ident),
     /// `Type` literal, with levels.
-    Type(Loc, Level),
+    /// The level of `Type` without a number is inferred.
+    Type(Loc, Option<Level>),
+    /// Universe level primitives, like `Level` and `lsuc`.
+    LevelPrim(Loc, LevelPrim),
     /// Function application.<br/>
     /// Application operator, where `f a b c` is represented as `App(f, vec![a, b, c])`
     /// instead of `App(App(App(f, a), b), c)`.
     App(Box<Vec1<Self>>),
+    /// Named implicit argument, like `{A = a}` in `f {A = a}`.
+    /// Only appears as an argument of [App](Expr::App).
+    Named(Ident, Box<Self>),
     /// Function composition.<br/>
     /// Pipeline operator, where `a |> b |> f` is represented as `Pipe(vec![a, b, f])`
     /// instead of `Pipe(Pipe(Pipe(f, a), b), c)`.
@@ -59,13 +71,48 @@ pub enum Expr {
     /// instead of `Sig(a, Sig(b, c))`.
     /// `a` and `b` here can introduce telescopes.
     Sig(Vec<Param>, Box<Self>),
+    /// The arguments of an n-ary constructor, where `a, b, c` is represented
+    /// as `Tele(vec![a, b], c)`. `a` and `b` here can introduce telescopes.
+    Tele(Vec<Param>, Box<Self>),
     /// Case-chains.
-    /// Label, binding, body, rest of the clauses
-    Cases(Ident, Ident, Box<Self>, Box<Self>),
+    /// Label, bindings of the constructor arguments, body, rest of the clauses
+    Cases(Ident, Vec<Ident>, Box<Self>, Box<Self>),
     /// Termination of a case-chain.
     Whatever(Loc),
     /// Anonymous function, aka lambda expression.
     Lam(Loc, Vec<Ident>, Box<Self>),
+    /// Type ascription, `(e : T)`.
+    Ann(Loc, Box<Self>, Box<Self>),
+    /// Local definition, `let x : A = a in b`, with an optional type signature.
+    /// `b where { x = a; }` is also represented as this.
+    Let(Loc, Ident, Option<Box<Self>>, Box<Self>, Box<Self>),
+    /// Pattern-matching function, like `| @True _ = a | x = b`.
+    /// Only appears as the body of a definition.
+    Match(Loc, Vec<Clause>),
+}
+
+/// Surface syntax tree node: Pattern.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Pattern {
+    /// Matches anything, `_`.
+    Wildcard(Loc),
+    /// Matches anything, and binds it.
+    Var(Ident),
+    /// Constructor pattern, `@Cons p q`, the arguments are wildcards if absent.
+    Cons(Ident, Vec<Self>),
+    /// Record pattern, `{| a = p; |}`, the fields not mentioned are ignored.
+    Rec(Loc, Vec<Labelled<Self>>),
+    /// Pair pattern, `(p, q)`.
+    Pair(Loc, Box<Self>, Box<Self>),
+}
+
+/// Surface syntax tree node: Clause of a pattern-matching function,
+/// one pattern for each parameter.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Clause {
+    pub loc: Loc,
+    pub patterns: Vec<Pattern>,
+    pub body: Expr,
 }
 
 impl Expr {
@@ -118,12 +165,38 @@ impl Expr {
         Expr::Sig(params, Box::new(expr))
     }
 
+    pub fn tele(params: Vec<Param>, expr: Self) -> Self {
+        Expr::Tele(params, Box::new(expr))
+    }
+
     pub fn proj(expr: Self, projections: Vec1<Ident>) -> Self {
         Expr::Proj(Box::new(expr), projections)
     }
 
-    pub fn cases(label: Ident, binding: Ident, body: Self, or: Self) -> Self {
-        Expr::Cases(label, binding, Box::new(body), Box::new(or))
+    pub fn restrict(expr: Self, label: Ident) -> Self {
+        Expr::Restrict(Box::new(expr), label)
+    }
+
+    pub fn rename(expr: Self, info: Loc, from: Ident, to: Ident) -> Self {
+        Expr::Rename(Box::new(expr), info, from, to)
+    }
+
+    pub fn ann(info: Loc, expr: Self, ty: Self) -> Self {
+        Expr::Ann(info, Box::new(expr), Box::new(ty))
+    }
+
+    pub fn let_in(info: Loc, name: Ident, sig: Option<Self>, value: Self, body: Self) -> Self {
+        Expr::Let(
+            info,
+            name,
+            sig.map(Box::new),
+            Box::new(value),
+            Box::new(body),
+        )
+    }
+
+    pub fn cases(label: Ident, bindings: Vec<Ident>, body: Self, or: Self) -> Self {
+        Expr::Cases(label, bindings, Box::new(body), Box::new(or))
     }
 }
 
@@ -146,3 +219,23 @@ pub struct Decl {
     pub body: Expr,
     pub kind: DeclKind,
 }
+
+/// Surface syntax tree node: Import.
+///
+/// `import Data.Bool as B;` brings every definition of the module `Data.Bool`
+/// into scope, both unqualified and qualified (as `Data.Bool.x` and `B.x`).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Import {
+    /// Dot-separated module name, like `Data.Bool`.
+    pub name: Ident,
+    pub alias: Option<Ident>,
+}
+
+/// Surface syntax tree node: Module, the content of a file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Module {
+    /// Declared by `module Data.Bool;`, optional.
+    pub name: Option<Ident>,
+    pub imports: Vec<Import>,
+    pub decls: Vec<Decl>,
+}
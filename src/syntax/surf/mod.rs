@@ -1,5 +1,5 @@
 pub use self::ast::*;
-pub use self::parse::parse_str;
+pub use self::parse::{parse_module_in, parse_str, parse_str_module};
 use crate::syntax::surf::parse::parse_str_expr;
 
 /// Surface syntax tree.
@@ -26,5 +26,11 @@ pub fn parse_str_err_printed(code: &str) -> Result<Vec<Decl>, ()> {
     parse_str(code).map_err(|err| eprintln!("{}", err))
 }
 
+/// Parse a string into an optional module and print error to stderr.
+#[inline]
+pub fn parse_module_err_printed(code: &str) -> Result<Module, ()> {
+    parse_str_module(code).map_err(|err| eprintln!("{}", err))
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,15 +1,16 @@
 use pest_derive::Parser;
 
 use voile_util::level::Level;
-use voile_util::loc::{Ident, Loc};
+use voile_util::loc::{FileId, Ident, Labelled, Loc};
 use voile_util::pest_util::{end_of_rule, strict_parse};
 use voile_util::tags::{Plicit, VarRec};
 use voile_util::vec1::Vec1;
 
+use crate::syntax::core::LevelPrim;
 use crate::syntax::surf::LabExpr;
 
 use super::ast::Param;
-use super::{Decl, DeclKind, Expr};
+use super::{Clause, Decl, DeclKind, Expr, Import, Module, Pattern};
 
 #[derive(Parser)]
 #[grammar = "syntax/surf/grammar.pest"]
@@ -19,11 +20,21 @@ struct VoileParser;
 tik_tok!();
 
 pub fn parse_str(input: &str) -> Result<Vec<Decl>, String> {
-    strict_parse::<VoileParser, _, _, _>(Rule::file, input, declarations)
+    strict_parse::<VoileParser, _, _, _>(Rule::file, Default::default(), input, declarations)
+}
+
+pub fn parse_str_module(input: &str) -> Result<Module, String> {
+    parse_module_in(Default::default(), input)
+}
+
+/// Parse the content of `file` into a module,
+/// the locations are marked with `file`.
+pub fn parse_module_in(file: FileId, input: &str) -> Result<Module, String> {
+    strict_parse::<VoileParser, _, _, _>(Rule::module, file, input, module)
 }
 
 pub fn parse_str_expr(input: &str) -> Result<Expr, String> {
-    strict_parse::<VoileParser, _, _, _>(Rule::standalone_expr, input, expr)
+    strict_parse::<VoileParser, _, _, _>(Rule::standalone_expr, Default::default(), input, expr)
 }
 
 macro_rules! expr_parser {
@@ -49,7 +60,49 @@ fn next_ident(inner: &mut Tik) -> Ident {
 }
 
 fn declarations(the_rule: Tok) -> Vec<Decl> {
-    the_rule.into_inner().into_iter().map(declaration).collect()
+    the_rule
+        .into_inner()
+        .into_iter()
+        .flat_map(declaration)
+        .collect()
+}
+
+fn module(the_rule: Tok) -> Module {
+    let mut name = None;
+    let mut imports = Vec::new();
+    let mut decls = Vec::new();
+    for the_rule in the_rule.into_inner() {
+        match the_rule.as_rule() {
+            Rule::module_header => name = Some(module_header(the_rule)),
+            Rule::import => imports.push(import(the_rule)),
+            Rule::declaration => decls.extend(declaration(the_rule)),
+            e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
+        }
+    }
+    Module {
+        name,
+        imports,
+        decls,
+    }
+}
+
+fn module_header(rules: Tok) -> Ident {
+    let mut inner: Tik = rules.into_inner();
+    let name = next_rule!(inner, module_name);
+    end_of_rule(&mut inner);
+    name
+}
+
+fn import(rules: Tok) -> Import {
+    let mut inner: Tik = rules.into_inner();
+    let name = next_rule!(inner, module_name);
+    let alias = inner.next().map(ident);
+    end_of_rule(&mut inner);
+    Import { name, alias }
+}
+
+fn module_name(rule: Tok) -> Ident {
+    ident(rule)
 }
 
 fn rec_field(rules: Tok) -> LabExpr {
@@ -64,6 +117,19 @@ fn labelled(rules: Tok) -> LabExpr {
     LabExpr { expr, label }
 }
 
+fn constructor(rules: Tok) -> LabExpr {
+    let mut inner: Tik = rules.into_inner();
+    let label = next_ident(&mut inner);
+    let the_rule: Tok = inner.next().unwrap();
+    let expr = match the_rule.as_rule() {
+        Rule::cons_tele => cons_tele(the_rule),
+        Rule::expr => expr(the_rule),
+        e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
+    };
+    end_of_rule(&mut inner);
+    LabExpr { expr, label }
+}
+
 fn row_rest(rules: Tok) -> Expr {
     let mut inner: Tik = rules.into_inner();
     let expr = next_rule!(inner, expr);
@@ -72,51 +138,209 @@ fn row_rest(rules: Tok) -> Expr {
 }
 
 many_prefix_parser!(row_polymorphic, LabExpr, labelled, row_rest, Expr);
+many_prefix_parser!(variant_row, LabExpr, constructor, row_rest, Expr);
 many_prefix_parser!(record_literal, LabExpr, rec_field, row_rest, Expr);
 
 fn record(rules: Tok) -> Expr {
-    let info = Loc::from(rules.as_span());
+    let info = rules.loc();
     let (fields, rest) = record_literal(rules);
     Expr::record(info, fields, rest)
 }
 
+/// `{| r with x = a; |}` becomes `{| x = a; ... = r \ x |}`.
+fn record_update(rules: Tok) -> Expr {
+    let info = rules.loc();
+    let mut inner: Tik = rules.into_inner();
+    let record = next_rule!(inner, expr);
+    let fields: Vec<LabExpr> = inner.map(rec_field).collect();
+    let rest = (fields.iter()).fold(record, |rest, field| {
+        Expr::restrict(rest, field.label.clone())
+    });
+    Expr::record(info, fields, Some(rest))
+}
+
 fn variant_record(rules: Tok, kind: VarRec) -> Expr {
-    let info = Loc::from(rules.as_span());
+    let info = rules.loc();
     let mut inner: Tik = rules.into_inner();
-    let (labels, rest) = next_rule!(inner, row_polymorphic);
+    let (labels, rest) = match kind {
+        VarRec::Record => next_rule!(inner, row_polymorphic),
+        VarRec::Variant => next_rule!(inner, variant_row),
+    };
     Expr::row_polymorphic_type(info, labels, kind, rest)
 }
 
 fn variant_record_kind(rules: Tok, kind: VarRec) -> Expr {
-    let info = Loc::from(rules.as_span());
+    let info = rules.loc();
     let rules = rules.into_inner().next().unwrap();
     let labels = rules.into_inner().into_iter().map(ident).collect();
     Expr::RowKind(info, kind, labels)
 }
 
-fn declaration(rules: Tok) -> Decl {
+/// A `data` declaration is desugared into a signature and an implementation,
+/// so one declaration may produce two `Decl`s.
+fn declaration(rules: Tok) -> Vec<Decl> {
     let the_rule: Tok = rules.into_inner().next().unwrap();
     let kind = match the_rule.as_rule() {
         Rule::signature => DeclKind::Sign,
         Rule::implementation => DeclKind::Impl,
+        Rule::data_type => return data_type(the_rule),
         _ => unreachable!(),
     };
+    let loc = the_rule.loc();
     let mut inner: Tik = the_rule.into_inner();
     let name = next_ident(&mut inner);
-    let body = next_rule!(inner, expr);
+    let mut clauses = Vec::new();
+    let body = loop {
+        let the_rule: Tok = inner.next().unwrap();
+        match the_rule.as_rule() {
+            Rule::expr => break expr(the_rule),
+            Rule::clause => clauses.push(clause(the_rule)),
+            e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
+        }
+        if inner.peek().is_none() {
+            break Expr::Match(loc, clauses);
+        }
+    };
+    end_of_rule(&mut inner);
+    vec![Decl { kind, name, body }]
+}
+
+fn clause(rules: Tok) -> Clause {
+    let loc = rules.loc();
+    let mut patterns = Vec::new();
+    let mut inner: Tik = rules.into_inner();
+    let body = loop {
+        let the_rule: Tok = inner.next().unwrap();
+        match the_rule.as_rule() {
+            Rule::atom_pattern => patterns.push(atom_pattern(the_rule)),
+            _ => break expr(the_rule),
+        }
+    };
+    end_of_rule(&mut inner);
+    Clause {
+        loc,
+        patterns,
+        body,
+    }
+}
+
+fn pattern(rules: Tok) -> Pattern {
+    let mut inner: Tik = rules.into_inner();
+    let the_rule: Tok = inner.next().unwrap();
+    match the_rule.as_rule() {
+        Rule::cons => Pattern::Cons(ident(the_rule), inner.map(atom_pattern).collect()),
+        _ => {
+            let pattern = atom_pattern(the_rule);
+            end_of_rule(&mut inner);
+            pattern
+        }
+    }
+}
+
+/// `(p, q, r)` is parsed as `((p, q), r)`, like tuple expressions.
+fn atom_pattern(rules: Tok) -> Pattern {
+    let loc = rules.loc();
+    let mut inner: Tik = rules.into_inner();
+    let the_rule: Tok = inner.next().unwrap();
+    let pattern = match the_rule.as_rule() {
+        Rule::wildcard => Pattern::Wildcard(the_rule.loc()),
+        Rule::ident => Pattern::Var(ident(the_rule)),
+        Rule::cons => Pattern::Cons(ident(the_rule), Vec::new()),
+        Rule::rec_pattern => {
+            let fields = the_rule.into_inner().map(rec_pattern_field).collect();
+            Pattern::Rec(loc, fields)
+        }
+        Rule::pattern => (inner.by_ref().map(pattern)).fold(pattern(the_rule), |fst, snd| {
+            Pattern::Pair(loc, Box::new(fst), Box::new(snd))
+        }),
+        e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
+    };
+    end_of_rule(&mut inner);
+    pattern
+}
+
+fn rec_pattern_field(rules: Tok) -> Labelled<Pattern> {
+    let mut inner: Tik = rules.into_inner();
+    let label = next_ident(&mut inner);
+    let expr = next_rule!(inner, pattern);
     end_of_rule(&mut inner);
-    Decl { kind, name, body }
+    Labelled { expr, label }
+}
+
+/// `data List (A : Type) { Nil : Rec {}; Cons : A, List A; }` becomes
+/// `val List : (A : Type) -> Type;` and
+/// `let List = \A. Sum { Nil : Rec {}; Cons : A, List A; };`.
+fn data_type(rules: Tok) -> Vec<Decl> {
+    let info = rules.loc();
+    let mut params = Vec::new();
+    let mut universe = Expr::Type(info, None);
+    let mut variants = Vec::new();
+    let mut inner: Tik = rules.into_inner();
+    let name = next_ident(&mut inner);
+    for the_rule in inner {
+        match the_rule.as_rule() {
+            Rule::explicit => params.push(one_param(the_rule, Plicit::Ex)),
+            Rule::expr => universe = expr(the_rule),
+            Rule::constructor => variants.push(constructor(the_rule)),
+            e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
+        }
+    }
+    let names: Vec<_> = (params.iter())
+        .flat_map(|param| param.names.iter().cloned())
+        .collect();
+    let sum = Expr::sum(info, variants, None);
+    let (sign, body) = if params.is_empty() {
+        (universe, sum)
+    } else {
+        (Expr::pi(params, universe), Expr::lam(info, names, sum))
+    };
+    vec![
+        Decl {
+            kind: DeclKind::Sign,
+            name: name.clone(),
+            body: sign,
+        },
+        Decl {
+            kind: DeclKind::Impl,
+            name,
+            body,
+        },
+    ]
 }
 
 expr_parser!(dollar_expr, comma_expr, app);
 expr_parser!(comma_expr, pipe_expr, tup);
 expr_parser!(pipe_expr, lift_expr, pipe);
 // expr_parser!(lift_expr, app_expr, lift); customized
-expr_parser!(app_expr, primary_expr, app);
+// expr_parser!(app_expr, primary_expr, app); customized
+
+fn app_expr(rules: Tok) -> Expr {
+    let mut exprs: Vec<Expr> = Default::default();
+    for smaller in rules.into_inner() {
+        exprs.push(match smaller.as_rule() {
+            Rule::named_arg => named_arg(smaller),
+            _ => primary_expr(smaller),
+        });
+    }
+    let first = exprs.remove(0);
+    if exprs.is_empty() {
+        first
+    } else {
+        Expr::app(first, exprs)
+    }
+}
+
+fn named_arg(rules: Tok) -> Expr {
+    let mut inner: Tik = rules.into_inner();
+    let name = next_ident(&mut inner);
+    let arg = next_rule!(inner, expr);
+    end_of_rule(&mut inner);
+    Expr::Named(name, Box::new(arg))
+}
 
 fn lift_expr(rules: Tok) -> Expr {
     let mut lift_count = 0;
-    let loc = From::from(rules.as_span());
+    let loc = rules.loc();
     for smaller in rules.into_inner() {
         match smaller.as_rule() {
             Rule::lift_op => {
@@ -137,21 +361,32 @@ fn lift_expr(rules: Tok) -> Expr {
 }
 
 fn proj_expr(rules: Tok) -> Expr {
-    let mut projections = None;
+    let mut projections: Option<Vec1<Ident>> = None;
     let mut inner = rules.into_inner();
-    let projected = next_rule!(inner, app_expr);
-    for projection in inner {
-        assert_eq!(projection.as_rule(), Rule::proj_op);
-        let ident = Ident {
-            loc: Loc::from(projection.as_span()),
-            text: projection.as_str()[1..].to_owned(),
-        };
-        match projections {
-            None => projections = Some(Vec1::from(ident)),
-            Some(mut some_projections) => {
-                some_projections.push(ident);
-                projections = Some(some_projections);
-            }
+    let mut projected = next_rule!(inner, app_expr);
+    for op in inner {
+        let loc = op.loc();
+        if op.as_rule() == Rule::proj_op {
+            let ident = Ident {
+                loc,
+                text: op.as_str()[1..].to_owned(),
+            };
+            match &mut projections {
+                None => projections = Some(Vec1::from(ident)),
+                Some(some_projections) => some_projections.push(ident),
+            };
+            continue;
+        }
+        if let Some(projections) = projections.take() {
+            projected = Expr::proj(projected, projections);
+        }
+        let rule = op.as_rule();
+        let mut labels: Tik = op.into_inner();
+        let label = next_rule!(labels, ident);
+        projected = match rule {
+            Rule::restrict_op => Expr::restrict(projected, label),
+            Rule::rename_op => Expr::rename(projected, loc, label, next_rule!(labels, ident)),
+            _ => unreachable!(),
         };
     }
     match projections {
@@ -161,29 +396,71 @@ fn proj_expr(rules: Tok) -> Expr {
 }
 
 fn expr(rules: Tok) -> Expr {
+    let loc = rules.loc();
     let mut inner: Tik = rules.into_inner();
     let expr = next_rule!(inner, sig_expr);
+    let expr = match inner.next() {
+        // Later bindings can refer to the former ones.
+        Some(clause) => (where_clause(clause).into_iter().rev())
+            .fold(expr, |body, (name, sig, value)| {
+                Expr::let_in(loc, name, sig, value, body)
+            }),
+        None => expr,
+    };
     end_of_rule(&mut inner);
     expr
 }
 
+fn where_clause(rules: Tok) -> Vec<(Ident, Option<Expr>, Expr)> {
+    rules.into_inner().map(local_binding).collect()
+}
+
+fn local_binding(rules: Tok) -> (Ident, Option<Expr>, Expr) {
+    let mut inner: Tik = rules.into_inner();
+    let name = next_ident(&mut inner);
+    let the_rule: Tok = inner.next().unwrap();
+    let (sig, value) = match the_rule.as_rule() {
+        Rule::ascription => (Some(ascription(the_rule)), next_rule!(inner, expr)),
+        _ => (None, expr(the_rule)),
+    };
+    end_of_rule(&mut inner);
+    (name, sig, value)
+}
+
+fn let_expr(rules: Tok) -> Expr {
+    let loc = rules.loc();
+    let mut inner: Tik = rules.into_inner();
+    let (name, sig, value) = next_rule!(inner, local_binding);
+    let body = next_rule!(inner, expr);
+    end_of_rule(&mut inner);
+    Expr::let_in(loc, name, sig, value, body)
+}
+
 fn primary_expr(rules: Tok) -> Expr {
+    let loc = rules.loc();
     let mut inner: Tik = rules.into_inner();
     let the_rule: Tok = inner.next().unwrap();
     let expr = match the_rule.as_rule() {
         Rule::ident => Expr::Var(ident(the_rule)),
+        Rule::qualified => qualified(the_rule),
         Rule::cons => Expr::Cons(ident(the_rule)),
         Rule::meta => Expr::Meta(ident(the_rule)),
-        Rule::no_cases => Expr::Whatever(From::from(the_rule.as_span())),
+        Rule::no_cases => Expr::Whatever(the_rule.loc()),
         Rule::case_expr => case_expr(the_rule),
+        Rule::let_expr => let_expr(the_rule),
         Rule::lambda => lambda(the_rule),
         Rule::record => variant_record(the_rule, VarRec::Record),
         Rule::variant => variant_record(the_rule, VarRec::Variant),
         Rule::record_kind => variant_record_kind(the_rule, VarRec::Record),
         Rule::variant_kind => variant_record_kind(the_rule, VarRec::Variant),
         Rule::record_literal => record(the_rule),
+        Rule::record_update => record_update(the_rule),
         Rule::type_keyword => type_keyword(the_rule),
-        Rule::expr => expr(the_rule),
+        Rule::level_prim => level_prim(the_rule),
+        Rule::expr => match inner.next() {
+            Some(ty) => Expr::ann(loc, expr(the_rule), ascription(ty)),
+            None => expr(the_rule),
+        },
         e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
     };
     end_of_rule(&mut inner);
@@ -210,6 +487,7 @@ fn param(rules: Tok) -> Param {
             ty: match rule_type {
                 Rule::dollar_expr => dollar_expr(the_rule),
                 Rule::pi_expr => pi_expr(the_rule),
+                Rule::pipe_expr => pipe_expr(the_rule),
                 e => panic!("Unexpected rule: {:?} with token {}", e, the_rule.as_str()),
             },
         },
@@ -218,8 +496,13 @@ fn param(rules: Tok) -> Param {
     param
 }
 
+fn tele_param(rules: Tok) -> Param {
+    param(rules)
+}
+
 many_prefix_parser!(pi_expr_internal, Param, param, dollar_expr, Expr);
 many_prefix_parser!(sig_expr_internal, Param, param, pi_expr, Expr);
+many_prefix_parser!(cons_tele_internal, Param, tele_param, pipe_expr, Expr);
 many_prefix_parser!(multi_param, Ident, ident, expr, Expr);
 many_prefix_parser!(lambda_internal, Ident, ident, expr, Expr);
 
@@ -243,36 +526,83 @@ fn sig_expr(rules: Tok) -> Expr {
     }
 }
 
+fn cons_tele(rules: Tok) -> Expr {
+    let (params, ret) = cons_tele_internal(rules);
+    Expr::tele(params, ret.unwrap())
+}
+
 fn case_expr(rules: Tok) -> Expr {
     let mut inner: Tik = rules.into_inner();
     let label = next_ident(&mut inner);
-    let binding = next_ident(&mut inner);
+    let mut bindings = Vec::new();
+    while let Some(Rule::ident) = inner.peek().map(|rule| rule.as_rule()) {
+        bindings.push(next_ident(&mut inner));
+    }
     let body = next_rule!(inner, expr);
     let rest = next_rule!(inner, expr);
     end_of_rule(&mut inner);
-    Expr::cases(label, binding, body, rest)
+    Expr::cases(label, bindings, body, rest)
+}
+
+fn ascription(rules: Tok) -> Expr {
+    let mut inner: Tik = rules.into_inner();
+    let ty = next_rule!(inner, expr);
+    end_of_rule(&mut inner);
+    ty
 }
 
 fn lambda(rules: Tok) -> Expr {
-    let loc = Loc::from(rules.as_span());
+    let loc = rules.loc();
     let (params, ret) = lambda_internal(rules);
     let ret = ret.unwrap();
     Expr::lam(loc, params, ret)
 }
 
 fn type_keyword(rules: Tok) -> Expr {
-    let loc = Loc::from(rules.as_span());
+    let loc = rules.loc();
     let mut inner: Tik = rules.into_inner();
     let level_ast_node: Tok = inner.next().unwrap();
     debug_assert_eq!(level_ast_node.as_rule(), Rule::type_level);
-    let level = Level::Num(level_ast_node.as_str().parse().unwrap_or(0));
+    let level = level_ast_node.as_str().parse().ok().map(Level::Num);
     end_of_rule(&mut inner);
     Expr::Type(loc, level)
 }
 
+fn level_prim(rules: Tok) -> Expr {
+    let loc = rules.loc();
+    let prim = match rules.as_str() {
+        "Level" => LevelPrim::Level,
+        "lzero" => LevelPrim::Zero,
+        "lsuc" => LevelPrim::Suc,
+        "lmax" => LevelPrim::Max,
+        e => panic!("Unexpected level primitive: {}", e),
+    };
+    Expr::LevelPrim(loc, prim)
+}
+
+/// `a.b.c` is parsed as projections, where `a.b` may be resolved as a
+/// qualified name later.
+fn qualified(rule: Tok) -> Expr {
+    let whole = rule.loc();
+    let mut start = whole.start;
+    let mut names = rule.as_str().split('.').map(|text| {
+        let loc = Loc {
+            start,
+            col: whole.col + (start - whole.start),
+            end: start + text.len(),
+            ..whole
+        };
+        start += text.len() + 1;
+        Ident::new(loc, text.to_owned())
+    });
+    let head = names.next().unwrap();
+    let first = names.next().unwrap();
+    Expr::proj(Expr::Var(head), Vec1::new(first, names.collect()))
+}
+
 fn ident(rule: Tok) -> Ident {
     Ident {
         text: rule.as_str().to_owned(),
-        loc: From::from(rule.as_span()),
+        loc: rule.loc(),
     }
 }
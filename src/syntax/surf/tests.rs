@@ -1,4 +1,4 @@
-use super::{parse_expr_err_printed, parse_str_err_printed};
+use super::{parse_expr_err_printed, parse_module_err_printed, parse_str_err_printed};
 
 macro_rules! success {
     ($str:literal) => {
@@ -68,3 +68,222 @@ fn row_polymorphic_type_def_expr_parsing() {
     success!("val crimson : Sum {};");
     success!("val experience : Rec {};");
 }
+
+#[test]
+fn module_parsing() {
+    let module =
+        parse_module_err_printed("module A.B; import C; import D.E as F; let g = F.h i.j;")
+            .unwrap();
+    assert_eq!(module.name.unwrap().text, "A.B");
+    assert_eq!(module.imports.len(), 2);
+    assert_eq!(module.imports[1].alias.as_ref().unwrap().text, "F");
+    parse_module_err_printed("import C").unwrap_err();
+    parse_module_err_printed("let a = b; import C;").unwrap_err();
+}
+
+#[test]
+fn module_in_file_parsing() {
+    use super::{parse_module_in, Expr};
+    use voile_util::loc::FileId;
+    let module = parse_module_in(FileId(3), "import C; let a = b;").unwrap();
+    assert_eq!(module.imports[0].name.loc.file, FileId(3));
+    assert_eq!(module.decls[0].name.loc.file, FileId(3));
+    match &module.decls[0].body {
+        Expr::Var(ident) => assert_eq!((ident.loc.file, ident.loc.col), (FileId(3), 19)),
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    let module = parse_module_err_printed("let a = b;").unwrap();
+    assert_eq!(module.decls[0].name.loc.file, FileId::default());
+}
+
+#[test]
+fn data_type_parsing() {
+    use super::{DeclKind, Expr};
+    let decls =
+        parse_str_err_printed("data List (A : Type) { Nil : Rec {}; Cons : A, List A; }").unwrap();
+    assert_eq!(decls.len(), 2);
+    assert_eq!(decls[0].kind, DeclKind::Sign);
+    assert_eq!(decls[1].kind, DeclKind::Impl);
+    assert_eq!(decls[1].name.text, "List");
+    match &decls[1].body {
+        Expr::Lam(_, params, sum) => {
+            assert_eq!(params.len(), 1);
+            assert!(matches!(**sum, Expr::RowPoly(..)));
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    success!("data Nat : Type1 { Z : Rec {}; S : Nat; }");
+    success!("data Empty {}");
+    parse_str_err_printed("data Bad { A : B; ... = C }").unwrap_err();
+}
+
+#[test]
+fn constructor_parsing() {
+    use super::Expr;
+    let payload = |code: &str| match parse_str_err_printed(code).unwrap().remove(1).body {
+        Expr::RowPoly(_, _, mut variants, _) => variants.remove(0).expr,
+        body => panic!("Unexpected body: {:?}", body),
+    };
+    assert!(matches!(payload("data P { Both : A * B; }"), Expr::Sig(..)));
+    assert!(matches!(payload("data P { Both : A, B; }"), Expr::Tele(..)));
+    match payload("data P { Pack : {A : Type}, (a : A), B a; }") {
+        Expr::Tele(params, _) => assert_eq!(params.len(), 2),
+        payload => panic!("Unexpected payload: {:?}", payload),
+    }
+}
+
+#[test]
+fn level_parsing() {
+    use super::Expr;
+    use crate::syntax::core::LevelPrim;
+    success!("val id : {l : Level} -> {A : Type l} -> A -> A;");
+    success!("val lift : (a b : Level) -> Type (lmax a (lsuc b)) -> Type lzero;");
+    let decls = parse_str_err_printed("val levels : Levels;").unwrap();
+    assert!(matches!(decls[0].body, Expr::Var(..)));
+    let decls = parse_str_err_printed("val level : Level;").unwrap();
+    assert!(matches!(
+        decls[0].body,
+        Expr::LevelPrim(_, LevelPrim::Level)
+    ));
+}
+
+#[test]
+fn named_arg_parsing() {
+    use super::Expr;
+    success!("let idBool = id {A = Bool};");
+    success!("let r = f {| a = b; |} {A = Rec {}} x;");
+    let decls = parse_str_err_printed("let c = const {B = Bool} a;").unwrap();
+    match &decls[0].body {
+        Expr::App(app) => {
+            assert!(matches!(app.tail()[0], Expr::Named(..)));
+            assert!(matches!(app.tail()[1], Expr::Var(..)));
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    parse_str_err_printed("let bad = f {A : Type};").unwrap_err();
+}
+
+#[test]
+fn ascription_parsing() {
+    use super::Expr;
+    success!("let b = (@True (Rec {}) : Sum { True : Rec {}; });");
+    success_expr!("f (a : A) b");
+    let decls = parse_str_err_printed("let c = (x : A);").unwrap();
+    assert!(matches!(decls[0].body, Expr::Ann(..)));
+    // Still a pi-type when followed by an arrow.
+    let decls = parse_str_err_printed("val d : (x : A) -> B;").unwrap();
+    assert!(matches!(decls[0].body, Expr::Pi(..)));
+    parse_str_err_printed("let e = (x : A : B);").unwrap_err();
+}
+
+#[test]
+fn local_definition_parsing() {
+    use super::Expr;
+    success!("let a = let x = b in x;");
+    success!("let a = let x : B = b in \\y. x y;");
+    success!("let a = f x where { x = b; y : C = c; };");
+    success_expr!("let x = a in x where { a = b; }");
+    let decls = parse_str_err_printed("let a = b where { x = c; y = d; };").unwrap();
+    match &decls[0].body {
+        Expr::Let(_, x, None, _, body) => {
+            assert_eq!(x.text, "x");
+            assert!(matches!(**body, Expr::Let(..)));
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    // Keywords are not identifiers
+    parse_str_err_printed("let in = a;").unwrap_err();
+    parse_str_err_printed("let a = where;").unwrap_err();
+    success!("let index = wherever inner;");
+}
+
+#[test]
+fn clause_parsing() {
+    use super::{Expr, Pattern};
+    success!("let not | @True = f | @False = t;");
+    success!("let plus | @Z m = m | (@S n) m = @S (plus n m);");
+    success!("let f | (@S (@S n)) = n | _ = z;");
+    success!("let f | {| a = x; b = @C y; |} (p, q, r) = x;");
+    success!("let f | x = g x where { g = h; };");
+    let decls = parse_str_err_printed("let f | (a, b, c) @C = a;").unwrap();
+    match &decls[0].body {
+        Expr::Match(_, clauses) => {
+            assert_eq!(clauses.len(), 1);
+            let patterns = &clauses[0].patterns;
+            assert!(
+                matches!(&patterns[0], Pattern::Pair(_, fst, _) if matches!(**fst, Pattern::Pair(..)))
+            );
+            assert!(matches!(&patterns[1], Pattern::Cons(_, args) if args.is_empty()));
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    let decls = parse_str_err_printed("let f | (@Cons x (@Cons y _)) = x;").unwrap();
+    match &decls[0].body {
+        Expr::Match(_, clauses) => match &clauses[0].patterns[0] {
+            Pattern::Cons(_, args) => {
+                assert_eq!(args.len(), 2);
+                assert!(matches!(&args[1], Pattern::Cons(_, args) if args.len() == 2));
+            }
+            pattern => panic!("Unexpected pattern: {:?}", pattern),
+        },
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    // At least one pattern in a clause
+    parse_str_err_printed("let f | = a;").unwrap_err();
+    parse_str_err_printed("let f | _ = a = b;").unwrap_err();
+}
+
+#[test]
+fn case_parsing() {
+    use super::Expr;
+    success!("let f = case True u : u or whatever;");
+    let decls = parse_str_err_printed("let f = case Cons x xs : xs or whatever;").unwrap();
+    match &decls[0].body {
+        Expr::Cases(label, bindings, ..) => {
+            assert_eq!(label.text, "Cons");
+            assert_eq!(bindings.len(), 2);
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    // At least one binding in a case
+    parse_str_err_printed("let f = case True : u or whatever;").unwrap_err();
+}
+
+#[test]
+fn restriction_parsing() {
+    use super::Expr;
+    success!("let a = r \\ x;");
+    success!("let a = Rec { x: A; y: B; } \\ x [y -> z];");
+    success_expr!("f (r [x -> y]).y");
+    let decls = parse_str_err_printed("let a = r.x \\ y [z -> w];").unwrap();
+    match &decls[0].body {
+        Expr::Rename(restricted, _, from, to) => {
+            assert_eq!((from.text.as_str(), to.text.as_str()), ("z", "w"));
+            assert!(
+                matches!(&**restricted, Expr::Restrict(proj, _) if matches!(**proj, Expr::Proj(..)))
+            );
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    parse_str_err_printed("let a = r [x ->];").unwrap_err();
+}
+
+#[test]
+fn record_update_parsing() {
+    use super::Expr;
+    success!("let a = {| r with x = b; |};");
+    success!("let a = {| f r with x = b; y = c; |};");
+    let decls = parse_str_err_printed("let a = {| r with x = b; y = c; |};").unwrap();
+    match &decls[0].body {
+        Expr::Rec(_, fields, Some(rest)) => {
+            assert_eq!(fields.len(), 2);
+            assert!(
+                matches!(&**rest, Expr::Restrict(r, y) if y.text == "y" && matches!(**r, Expr::Restrict(..)))
+            );
+        }
+        body => panic!("Unexpected body: {:?}", body),
+    }
+    // Keywords are not identifiers
+    parse_str_err_printed("let with = a;").unwrap_err();
+    parse_str_err_printed("let a = {| r with |};").unwrap_err();
+}
@@ -48,7 +48,7 @@ impl<'a> std::fmt::Display for Lisp<'a> {
 tik_tok!();
 
 pub fn parse_str(input: &str) -> Result<Lisp, String> {
-    strict_parse::<CoreParser, _, _, _>(Rule::element, input, element)
+    strict_parse::<CoreParser, _, _, _>(Rule::element, Default::default(), input, element)
 }
 
 fn element(rules: Tok) -> Lisp {
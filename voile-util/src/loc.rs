@@ -1,13 +1,21 @@
 use std::fmt::{Display, Error, Formatter};
 use std::ops::Add;
 
+/// Index of a source file, `FileId(0)` stands for an unknown file.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Default)]
+pub struct FileId(pub usize);
+
 /// Trivial information about the surface syntax items,
 /// short for "Location".
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
 pub struct Loc {
     pub start: usize,
+    /// One-based line number of `start`.
     pub line: usize,
+    /// One-based column (in characters) of `start`.
+    pub col: usize,
     pub end: usize,
+    pub file: FileId,
     pub is_generated: bool,
 }
 
@@ -48,7 +56,9 @@ impl Add for Loc {
     fn add(self, rhs: Self) -> Self::Output {
         Self::Output {
             line: self.line,
+            col: self.col,
             start: self.start,
+            file: self.file,
             end: rhs.end,
             is_generated: self.is_generated || rhs.is_generated,
         }
@@ -1,14 +1,74 @@
 use pest::iterators::{Pair, Pairs};
-use pest::{Parser, RuleType, Span};
+use pest::{Parser, RuleType};
 
-use crate::loc::Loc;
+use crate::loc::{FileId, Loc};
+
+/// A token of the source file `file`, so its location is in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'a, R: RuleType> {
+    pair: Pair<'a, R>,
+    file: FileId,
+}
+
+impl<'a, R: RuleType> Token<'a, R> {
+    pub fn as_rule(&self) -> R {
+        self.pair.as_rule()
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.pair.as_str()
+    }
+
+    pub fn into_inner(self) -> Tokens<'a, R> {
+        Tokens {
+            pairs: self.pair.into_inner(),
+            file: self.file,
+        }
+    }
+
+    pub fn loc(&self) -> Loc {
+        let span = self.pair.as_span();
+        let (line, col) = span.start_pos().line_col();
+        Loc {
+            line,
+            col,
+            start: span.start(),
+            end: span.end(),
+            file: self.file,
+            is_generated: false,
+        }
+    }
+}
+
+/// The tokens of the source file `file`, like [`Token`](Token).
+#[derive(Debug, Clone)]
+pub struct Tokens<'a, R: RuleType> {
+    pairs: Pairs<'a, R>,
+    file: FileId,
+}
+
+impl<'a, R: RuleType> Tokens<'a, R> {
+    pub fn peek(&self) -> Option<Token<'a, R>> {
+        let file = self.file;
+        self.pairs.peek().map(|pair| Token { pair, file })
+    }
+}
+
+impl<'a, R: RuleType> Iterator for Tokens<'a, R> {
+    type Item = Token<'a, R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let file = self.file;
+        self.pairs.next().map(|pair| Token { pair, file })
+    }
+}
 
 // Tik♂Tok on the clock but the party don't stop!
 #[macro_export]
 macro_rules! tik_tok {
     () => {
-        type Tok<'a> = pest::iterators::Pair<'a, self::Rule>;
-        type Tik<'a> = pest::iterators::Pairs<'a, self::Rule>;
+        type Tok<'a> = $crate::pest_util::Token<'a, self::Rule>;
+        type Tik<'a> = $crate::pest_util::Tokens<'a, self::Rule>;
     };
 }
 
@@ -39,11 +99,17 @@ macro_rules! many_prefix_parser {
     };
 }
 
-pub fn strict_parse<'a, P, F, R, T>(rule: R, input: &'a str, f: F) -> Result<T, String>
+/// Parse `input`, the content of `file`, which should be consumed by `rule`.
+pub fn strict_parse<'a, P, F, R, T>(
+    rule: R,
+    file: FileId,
+    input: &'a str,
+    f: F,
+) -> Result<T, String>
 where
     P: Parser<R>,
     R: RuleType,
-    F: FnOnce(Pair<'a, R>) -> T,
+    F: FnOnce(Token<'a, R>) -> T,
 {
     let rule = P::parse(rule, input)
         .map_err(|err| format!("Parse failed at:{}", err))?
@@ -55,22 +121,11 @@ where
         let rest = &input[end_pos.pos()..];
         Err(format!("Does not consume the following code: '{}'", rest))
     } else {
-        Ok(f(rule))
+        Ok(f(Token { pair: rule, file }))
     }
 }
 
 #[inline]
-pub fn end_of_rule<Rule: RuleType>(inner: &mut Pairs<Rule>) {
+pub fn end_of_rule<Rule: RuleType>(inner: &mut Tokens<Rule>) {
     debug_assert_eq!(inner.next(), None);
 }
-
-impl<'a> From<Span<'a>> for Loc {
-    fn from(span: Span) -> Self {
-        Loc {
-            line: span.start_pos().line_col().0,
-            start: span.start(),
-            end: span.end(),
-            is_generated: false,
-        }
-    }
-}
@@ -21,6 +21,9 @@ impl Display for VarRec {
 pub enum PiSig {
     Pi,
     Sigma,
+    /// The arguments of an n-ary constructor, like a Sigma type
+    /// but each component is a separate argument.
+    Tele,
 }
 
 impl Display for PiSig {
@@ -28,6 +31,7 @@ impl Display for PiSig {
         f.write_str(match self {
             PiSig::Pi => "\u{03A0}",
             PiSig::Sigma => "\u{03A3}",
+            PiSig::Tele => "\u{0394}",
         })
     }
 }